@@ -1,18 +1,66 @@
 #import bevy_pbr::forward_io::VertexOutput
 
+struct TerrainParams {
+    size: vec2<u32>,
+    blend: f32,
+    _padding: u32,
+}
+
 @group(2) @binding(0) var textures: binding_array<texture_2d<f32>>;
 @group(2) @binding(1) var nearest_sampler: sampler;
 @group(2) @binding(2) var<storage, read> mapping: array<u32>;
-@group(2) @binding(3) var<uniform> size: vec2<u32>;
+@group(2) @binding(3) var<uniform> params: TerrainParams;
+@group(2) @binding(4) var<storage, read> visibility: array<u32>;
+
+// How bright the unexplored, explored and visible tiles are.
+const FOG_BRIGHTNESS: vec3<f32> = vec3<f32>(0.0, 0.45, 1.0);
+
+// The mapping has a one tile border with the tiles of the neighbouring chunks, so coords can go
+// from -1 to size (inclusive) and still match the tiles on the other side of the seam.
+fn tile_kind(coords: vec2<i32>) -> u32 {
+    let padded = params.size + 2u;
+    let clamped = vec2<u32>(clamp(coords + 1, vec2<i32>(0), vec2<i32>(padded) - 1));
+    return mapping[clamped.y * padded.x + clamped.x];
+}
+
+fn fog_brightness(coords: vec2<i32>) -> f32 {
+    let padded = params.size + 2u;
+    let clamped = vec2<u32>(clamp(coords + 1, vec2<i32>(0), vec2<i32>(padded) - 1));
+    return FOG_BRIGHTNESS[visibility[clamped.y * padded.x + clamped.x]];
+}
+
+fn sample_tile(coords: vec2<i32>, uv: vec2<f32>) -> vec4<f32> {
+    return textureSample(textures[tile_kind(coords)], nearest_sampler, uv);
+}
 
 @fragment
 fn fragment(
     mesh: VertexOutput,
 ) -> @location(0) vec4<f32> {
-    let coords = clamp(vec2<u32>(mesh.uv * vec2<f32>(size)), vec2<u32>(0u), size - 1);
-    let index = coords.y * size.x + coords.x;
-    let texture_index = mapping[index];
+    let position = mesh.uv * vec2<f32>(params.size);
+    let inner_uv = fract(position);
+
+    // Blend the four closest tiles, weighted so that the middle of each tile keeps its own
+    // texture and only the area within `blend` of the border is mixed with the neighbour.
+    let corner = floor(position - 0.5);
+    let offset = position - 0.5 - corner;
+    let blend = max(params.blend, 0.001);
+    let weight = smoothstep(vec2<f32>(0.5 - blend), vec2<f32>(0.5 + blend), offset);
+
+    let base = vec2<i32>(corner);
+    let c00 = sample_tile(base, inner_uv);
+    let c10 = sample_tile(base + vec2<i32>(1, 0), inner_uv);
+    let c01 = sample_tile(base + vec2<i32>(0, 1), inner_uv);
+    let c11 = sample_tile(base + vec2<i32>(1, 1), inner_uv);
+
+    let color = mix(mix(c00, c10, weight.x), mix(c01, c11, weight.x), weight.y);
+
+    // The fog is interpolated between the tile centers so its edges are soft.
+    let f00 = fog_brightness(base);
+    let f10 = fog_brightness(base + vec2<i32>(1, 0));
+    let f01 = fog_brightness(base + vec2<i32>(0, 1));
+    let f11 = fog_brightness(base + vec2<i32>(1, 1));
+    let brightness = mix(mix(f00, f10, offset.x), mix(f01, f11, offset.x), offset.y);
 
-    let inner_uv = fract(mesh.uv * vec2<f32>(size));
-    return textureSample(textures[texture_index], nearest_sampler, inner_uv);
+    return vec4<f32>(color.rgb * brightness, color.a);
 }
@@ -0,0 +1,129 @@
+#import bevy_pbr::{
+    forward_io::VertexOutput,
+    mesh_view_bindings::{globals, view},
+}
+
+@group(2) @binding(0) var<uniform> size: vec2<u32>;
+@group(2) @binding(1) var<storage, read> shore: array<f32>;
+@group(2) @binding(2) var<storage, read> visibility: array<u32>;
+
+const DEEP_COLOR: vec3<f32> = vec3<f32>(0.02, 0.10, 0.24);
+const SHALLOW_COLOR: vec3<f32> = vec3<f32>(0.10, 0.42, 0.55);
+const FOAM_COLOR: vec3<f32> = vec3<f32>(0.92, 0.95, 1.0);
+// Must match `SHORE_DISTANCE` in the terrain module.
+const SHORE_DISTANCE: f32 = 3.0;
+const FOAM_WIDTH: f32 = 0.6;
+// The direction towards the sun, matching the directional light spawned in `core::setup`.
+const LIGHT_DIRECTION: vec3<f32> = vec3<f32>(0.57735, 0.57735, 0.57735);
+const TILE_SIZE: f32 = 16.0;
+// How bright the unexplored, explored and visible tiles are, like in the terrain shader.
+const FOG_BRIGHTNESS: vec3<f32> = vec3<f32>(0.0, 0.45, 1.0);
+
+// The shore buffer has a one tile border with the neighbouring chunks, like the terrain mapping.
+fn shore_distance(coords: vec2<i32>) -> f32 {
+    let padded = size + 2u;
+    let clamped = vec2<u32>(clamp(coords + 1, vec2<i32>(0), vec2<i32>(padded) - 1));
+    return shore[clamped.y * padded.x + clamped.x];
+}
+
+// Bilinear interpolation of the distances between the tile centers.
+fn interpolated_shore_distance(position: vec2<f32>) -> f32 {
+    let corner = floor(position - 0.5);
+    let weight = position - 0.5 - corner;
+    let base = vec2<i32>(corner);
+
+    let d00 = shore_distance(base);
+    let d10 = shore_distance(base + vec2<i32>(1, 0));
+    let d01 = shore_distance(base + vec2<i32>(0, 1));
+    let d11 = shore_distance(base + vec2<i32>(1, 1));
+
+    return mix(mix(d00, d10, weight.x), mix(d01, d11, weight.x), weight.y);
+}
+
+fn fog_brightness(coords: vec2<i32>) -> f32 {
+    let padded = size + 2u;
+    let clamped = vec2<u32>(clamp(coords + 1, vec2<i32>(0), vec2<i32>(padded) - 1));
+    return FOG_BRIGHTNESS[visibility[clamped.y * padded.x + clamped.x]];
+}
+
+fn interpolated_fog_brightness(position: vec2<f32>) -> f32 {
+    let corner = floor(position - 0.5);
+    let weight = position - 0.5 - corner;
+    let base = vec2<i32>(corner);
+
+    let f00 = fog_brightness(base);
+    let f10 = fog_brightness(base + vec2<i32>(1, 0));
+    let f01 = fog_brightness(base + vec2<i32>(0, 1));
+    let f11 = fog_brightness(base + vec2<i32>(1, 1));
+
+    return mix(mix(f00, f10, weight.x), mix(f01, f11, weight.x), weight.y);
+}
+
+fn hash(p: vec2<f32>) -> f32 {
+    return fract(sin(dot(p, vec2<f32>(127.1, 311.7))) * 43758.5453);
+}
+
+fn value_noise(p: vec2<f32>) -> f32 {
+    let i = floor(p);
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+
+    let a = hash(i);
+    let b = hash(i + vec2<f32>(1.0, 0.0));
+    let c = hash(i + vec2<f32>(0.0, 1.0));
+    let d = hash(i + vec2<f32>(1.0, 1.0));
+
+    return mix(mix(a, b, u.x), mix(c, d, u.x), u.y);
+}
+
+// Two layers of noise scrolling in different directions.
+fn wave_height(p: vec2<f32>, time: f32) -> f32 {
+    let first = value_noise(p * 1.5 + vec2<f32>(0.35, 0.20) * time);
+    let second = value_noise(p * 3.1 - vec2<f32>(0.25, 0.40) * time);
+    return first * 0.65 + second * 0.35;
+}
+
+fn wave_normal(p: vec2<f32>, time: f32) -> vec3<f32> {
+    let e = 0.05;
+    let h = wave_height(p, time);
+    let dx = wave_height(p + vec2<f32>(e, 0.0), time) - h;
+    let dz = wave_height(p + vec2<f32>(0.0, e), time) - h;
+    return normalize(vec3<f32>(-dx, e * 2.0, -dz));
+}
+
+@fragment
+fn fragment(
+    mesh: VertexOutput,
+) -> @location(0) vec4<f32> {
+    let position = mesh.uv * vec2<f32>(size);
+    let distance = interpolated_shore_distance(position);
+
+    // Fade out over the land tiles so the water only covers the water tiles.
+    let coverage = smoothstep(0.0, 0.5, distance);
+    if coverage <= 0.0 {
+        discard;
+    }
+
+    let time = globals.time;
+    let world = mesh.world_position.xz / TILE_SIZE;
+    let normal = wave_normal(world, time);
+
+    let depth = clamp(distance / SHORE_DISTANCE, 0.0, 1.0);
+    var color = mix(SHALLOW_COLOR, DEEP_COLOR, depth);
+
+    let diffuse = max(dot(normal, LIGHT_DIRECTION), 0.0);
+    let view_direction = normalize(view.world_position - mesh.world_position.xyz);
+    let half_vector = normalize(LIGHT_DIRECTION + view_direction);
+    let specular = pow(max(dot(normal, half_vector), 0.0), 64.0);
+    color = color * (0.6 + 0.4 * diffuse) + vec3<f32>(specular * 0.6);
+
+    // Foam lines that move towards the shore and break up with the waves.
+    let wave = 0.5 + 0.5 * sin(distance * 9.0 - time * 2.5);
+    let foam_edge = 1.0 - smoothstep(0.0, FOAM_WIDTH, distance);
+    let breakup = step(0.35, wave_height(world * 2.0, time));
+    let foam = clamp(foam_edge * (0.6 + 0.4 * wave) * breakup, 0.0, 1.0);
+    color = mix(color, FOAM_COLOR, foam) * interpolated_fog_brightness(position);
+
+    let alpha = mix(0.6, 0.92, depth) * coverage;
+    return vec4<f32>(color, max(alpha, foam * coverage));
+}
@@ -0,0 +1,61 @@
+use std::{env, process};
+
+use bevy::{asset::ron, math::IVec2};
+use lethal_empire_bevy::terrain::{export_world_image, WorldGenSettings};
+
+// Render the generated world for a rectangle of chunks to a PNG, one pixel per tile.
+//
+// This does not create a window or touch the GPU, so it can be used in CI to check that a seed
+// still generates the same map.
+//
+// Usage: world_export <seed> <min_x> <min_y> <max_x> <max_y> <output.png> [settings.worldgen.ron]
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 7 && args.len() != 8 {
+        eprintln!(
+            "Usage: {} <seed> <min_x> <min_y> <max_x> <max_y> <output.png> [settings.worldgen.ron]",
+            args[0]
+        );
+        process::exit(1);
+    }
+
+    let seed = parse_arg::<u64>(&args[1], "seed");
+    let min = IVec2::new(parse_arg(&args[2], "min_x"), parse_arg(&args[3], "min_y"));
+    let max = IVec2::new(parse_arg(&args[4], "max_x"), parse_arg(&args[5], "max_y"));
+    let output = &args[6];
+    let settings = args
+        .get(7)
+        .map(|path| load_settings(path))
+        .unwrap_or_default();
+
+    if min.cmpgt(max).any() {
+        eprintln!("The min chunk coordinate must not be greater than the max chunk coordinate");
+        process::exit(1);
+    }
+
+    let image = export_world_image(seed, &settings, min, max);
+    if let Err(error) = image.save(output) {
+        eprintln!("Failed to write {}: {}", output, error);
+        process::exit(1);
+    }
+}
+
+fn parse_arg<T: std::str::FromStr>(value: &str, name: &str) -> T {
+    value.parse().unwrap_or_else(|_| {
+        eprintln!("Invalid value for {}: {}", name, value);
+        process::exit(1);
+    })
+}
+
+fn load_settings(path: &str) -> WorldGenSettings {
+    let contents = std::fs::read_to_string(path).unwrap_or_else(|error| {
+        eprintln!("Failed to read {}: {}", path, error);
+        process::exit(1);
+    });
+
+    ron::de::from_str(&contents).unwrap_or_else(|error| {
+        eprintln!("Failed to parse {}: {}", path, error);
+        process::exit(1);
+    })
+}
@@ -1,6 +1,5 @@
-use bevy::prelude::*;
-
-use crate::terrain::ResourceKind;
+use bevy::{prelude::*, utils::HashSet};
+use serde::{Deserialize, Serialize};
 
 #[derive(Component)]
 pub struct BuildingTool;
@@ -14,24 +13,151 @@ pub(super) struct GhostBuilding;
 #[derive(Component)]
 pub(super) struct BuildingValidGhost;
 
+/// The tiles the building tool is being dragged over, starting where the button was pressed.
+#[derive(Component, Default)]
+pub(super) struct BuildingToolDrag {
+    pub start: Option<IVec2>,
+    pub tiles: Vec<IVec2>,
+    /// The tiles that can be built on and paid for.
+    pub valid: HashSet<IVec2>,
+}
+
+/// The preview of one of the buildings placed by dragging the building tool.
+#[derive(Component, Deref)]
+pub(super) struct BuildingDragGhost(pub IVec2);
+
 #[derive(Component)]
 pub struct Building;
 
+/// How many gather jobs of the building are posted or being done.
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct BuildingWorkers(pub u32);
+
+/// How much workers prefer the jobs of the building over the jobs of other buildings.
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BuildingPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl BuildingPriority {
+    /// The priority the player switches to from this one, going around from high to low.
+    pub fn next(&self) -> Self {
+        match self {
+            BuildingPriority::Low => BuildingPriority::Normal,
+            BuildingPriority::Normal => BuildingPriority::High,
+            BuildingPriority::High => BuildingPriority::Low,
+        }
+    }
+
+    pub fn rank(&self) -> u32 {
+        *self as u32
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BuildingPriority::Low => "LOW",
+            BuildingPriority::Normal => "NORMAL",
+            BuildingPriority::High => "HIGH",
+        }
+    }
+}
+
+/// The level of the building, starting at 1.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Deref, DerefMut)]
+pub struct BuildingLevel(pub u32);
+
+impl Default for BuildingLevel {
+    fn default() -> Self {
+        BuildingLevel(1)
+    }
+}
+
+/// When the building was placed, counting up from the first one. Entities are recycled, so this
+/// is what tells which of two buildings is older.
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Deref)]
+pub struct BuildingOrder(pub u32);
+
+/// How many citizens work at the building. Workers are only sent out for this many.
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct BuildingStaff(pub u32);
+
+/// The building has fewer citizens working at it than its level asks for.
+#[derive(Component)]
+pub struct BuildingUnderstaffed;
+
+/// The building is being upgraded to the next level and does nothing until the timer finishes.
+#[derive(Component, Deref, DerefMut)]
+pub struct BuildingUpgrading(pub Timer);
+
+/// A worker is at the building, building its upgrade. The upgrade only progresses while there is
+/// one.
+#[derive(Component)]
+pub struct BuildingBuilder;
+
+/// A worker is on the way to gather the resource, so no other worker is sent to it.
+#[derive(Component)]
+pub struct ResourceClaimed;
+
+/// The building has sent out a worker or started a recipe, so placing it can't be undone.
 #[derive(Component)]
-pub struct BuildingHasWorker;
+pub struct BuildingProducing;
+
+/// The building has no resources of its kind in range, so its worker has nothing to do.
+#[derive(Component)]
+pub struct BuildingIdle;
+
+/// The building is waiting `Timer` seconds for its recipe to finish.
+#[derive(Component, Deref, DerefMut)]
+pub struct BuildingProcessing(pub Timer);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CropStage {
+    Sowing,
+    Growing,
+    Harvesting,
+}
+
+/// The crop on the fields of a farm, in a stage that ends when the timer finishes. Sowing and
+/// harvesting need the staff of the farm, growing doesn't.
+#[derive(Component)]
+pub struct FarmCrop {
+    pub stage: CropStage,
+    pub timer: Timer,
+}
+
+/// The expected resources per minute of a building, from the distance of its last trip.
+#[derive(Component, Default, Deref, DerefMut)]
+pub struct BuildingThroughput(pub f32);
 
-#[derive(Component, Default, PartialEq, Eq, Clone, Hash, Debug)]
+#[derive(Component, Default, PartialEq, Eq, Clone, Hash, Debug, Serialize, Deserialize)]
 pub enum BuildingKind {
     #[default]
     LumberMill,
     StoneQuarry,
+    Sawmill,
+    Mason,
+    Warehouse,
+    House,
+    Farm,
+    FishingHut,
+    Barracks,
 }
 
-impl Into<ResourceKind> for BuildingKind {
-    fn into(self) -> ResourceKind {
+impl BuildingKind {
+    pub fn name(&self) -> &'static str {
         match self {
-            BuildingKind::LumberMill => ResourceKind::Tree,
-            BuildingKind::StoneQuarry => ResourceKind::Rock,
+            BuildingKind::LumberMill => "Lumber mill",
+            BuildingKind::StoneQuarry => "Stone quarry",
+            BuildingKind::Sawmill => "Sawmill",
+            BuildingKind::Mason => "Mason",
+            BuildingKind::Warehouse => "Warehouse",
+            BuildingKind::House => "House",
+            BuildingKind::Farm => "Farm",
+            BuildingKind::FishingHut => "Fishing hut",
+            BuildingKind::Barracks => "Barracks",
         }
     }
 }
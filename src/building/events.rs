@@ -0,0 +1,36 @@
+use bevy::prelude::*;
+
+use super::BuildingKind;
+
+/// Buildings of one kind on several tiles, placed and paid for together. A single click places
+/// one building, dragging places a line or an area of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlaceBuildings {
+    pub kind: BuildingKind,
+    pub rotation: Quat,
+    /// The global tile coordinates of the buildings.
+    pub tiles: Vec<IVec2>,
+}
+
+/// Start upgrading a building to its next level, paying for it from the storages in reach.
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UpgradeBuilding(pub Entity);
+
+/// Switch a building to its next priority.
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChangeBuildingPriority(pub Entity);
+
+/// An action of the player on buildings. Every action that succeeds is recorded in the
+/// `BuildHistory`, so it can be undone and redone.
+#[derive(Event, Debug, Clone, PartialEq)]
+pub enum BuildCommand {
+    Place(PlaceBuildings),
+    /// Remove a building for good. Nothing is refunded, but undoing it brings the building back
+    /// with the goods it stored.
+    Demolish(Entity),
+    /// Remove a building that has not started producing yet and refund what was paid for it and
+    /// its upgrades.
+    Cancel(Entity),
+    Undo,
+    Redo,
+}
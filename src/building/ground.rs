@@ -0,0 +1,54 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use crate::{
+    fog::FogOfWar,
+    roads::Roads,
+    spatial::SpatialIndex,
+    terrain::{ChunkManager, TileKind, TileMapping},
+};
+
+use super::Placement;
+
+/// What is on the tiles of the map, as far as building roads and buildings on them goes. The
+/// roads themselves aren't part of it, as placing roads changes them.
+#[derive(SystemParam)]
+pub struct BuildGround<'w, 's> {
+    pub chunk_manager: Res<'w, ChunkManager>,
+    q_mappings: Query<'w, 's, &'static TileMapping>,
+    pub index: Res<'w, SpatialIndex>,
+    fog: Res<'w, FogOfWar>,
+}
+
+impl BuildGround<'_, '_> {
+    /// The kind of a tile, if its chunk is loaded.
+    pub fn tile_kind(&self, global_coord: &IVec2) -> Option<TileKind> {
+        self.chunk_manager.tile_kind(global_coord, &self.q_mappings)
+    }
+
+    /// Whether a road can be put on the tile: explored land without an obstacle.
+    pub fn is_free(&self, global_coord: &IVec2) -> bool {
+        let Some(tile_kind) = self.tile_kind(global_coord) else {
+            return false;
+        };
+
+        !matches!(tile_kind, TileKind::Water)
+            && !self.index.is_blocked(global_coord)
+            && self.fog.is_explored(global_coord)
+    }
+
+    /// Whether a building can be put on the tile: explored land without an obstacle or a road,
+    /// that meets the placement of the building kind if it has one.
+    pub fn is_buildable(
+        &self,
+        global_coord: &IVec2,
+        placement: Option<Placement>,
+        roads: &Roads,
+    ) -> bool {
+        let is_placeable = match placement {
+            Some(placement) => placement.allows(global_coord, |coord| self.tile_kind(coord)),
+            None => true,
+        };
+
+        self.is_free(global_coord) && is_placeable && !roads.contains(global_coord)
+    }
+}
@@ -1,29 +1,60 @@
 use bevy::prelude::*;
 pub use components::*;
+pub use events::*;
+pub use ground::*;
 use materials::*;
+pub use production::*;
+pub use resources::*;
 use systems::*;
 
-use crate::core::{CursorActive, GameStates, ToolMode};
+use crate::core::{setup_settings, update_settings, GameStates, RonAssetLoader, ToolMode};
 
 mod components;
+mod events;
+mod ground;
 mod materials;
+mod production;
+mod resources;
 mod systems;
 
-pub const BUILDING_COST: u32 = 5;
 pub const BUILDING_RADIUS: u32 = 16;
+pub const BUILDING_VISION: u32 = 12;
+pub const BUILDING_SELECT_RADIUS: f32 = 10.0;
+/// The most buildings a single drag of the building tool places.
+pub const BUILDING_DRAG_LIMIT: usize = 256;
+/// How many build actions can be undone.
+pub const BUILD_HISTORY_LIMIT: usize = 32;
+/// How far, in tiles, building costs and recipe inputs can be taken from a storage.
+pub const STORAGE_REACH: u32 = 32;
 
 pub struct BuildingPlugin;
 
 impl Plugin for BuildingPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins(MaterialPlugin::<ValidBuildingToolMaterial>::default())
-            .add_systems(OnEnter(GameStates::Playing), setup_building_tool)
+            .init_asset::<ProductionSettings>()
+            .init_asset_loader::<RonAssetLoader<ProductionSettings>>()
+            .init_resource::<ProductionSettings>()
+            .add_event::<BuildCommand>()
+            .add_event::<UpgradeBuilding>()
+            .add_event::<ChangeBuildingPriority>()
+            .init_resource::<BuildHistory>()
+            .init_resource::<BuildingsPlaced>()
+            .add_systems(
+                OnEnter(GameStates::Playing),
+                (setup_building_tool, setup_settings::<ProductionSettings>),
+            )
             .add_systems(
                 Update,
                 (
                     follow_building_tool,
                     rotate_building_tool,
-                    handle_building_tool.run_if(|cursor_active: Res<CursorActive>| **cursor_active),
+                    (
+                        drag_building_tool,
+                        check_building_drag_valid,
+                        release_building_tool,
+                    )
+                        .chain(),
                     check_building_tool_valid,
                     update_tool_ghost_material,
                 )
@@ -35,7 +66,24 @@ impl Plugin for BuildingPlugin {
             )
             .add_systems(
                 Update,
-                (select_building_kind, update_ghost_building, building_increase_resource_count).run_if(in_state(GameStates::Playing)),
+                (
+                    select_building_kind,
+                    update_ghost_building,
+                    clear_building_drag,
+                    update_building_drag_ghosts,
+                    send_build_commands,
+                    handle_build_commands,
+                    start_building_upgrades,
+                    update_building_upgrades,
+                    change_building_priorities,
+                    update_settings::<ProductionSettings>,
+                    spawn_starting_warehouse,
+                    building_increase_resource_count,
+                    update_processing_buildings,
+                    update_farms,
+                    update_stockpile,
+                )
+                    .run_if(in_state(GameStates::Playing)),
             );
     }
 }
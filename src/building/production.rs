@@ -0,0 +1,714 @@
+use std::fmt::Debug;
+
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    combat::{ArmorKind, AttackKind},
+    core::{GameAssets, SettingsAsset},
+    goods::{Good, Goods},
+    military::{SoldierKind, SoldierRules, SoldierStats},
+    terrain::{ResourceKind, TileKind},
+};
+
+use super::{BuildingKind, BUILDING_RADIUS};
+
+/// How every kind of building produces and what it costs, loaded from a `.production.ron` asset.
+#[derive(Asset, Resource, TypePath, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProductionSettings {
+    /// The buildings that harvest resources from the map.
+    pub buildings: HashMap<BuildingKind, ProductionRules>,
+    /// The buildings that turn goods from the stockpile into other goods. Recipes without
+    /// inputs, like fishing, make their outputs from nothing.
+    pub recipes: HashMap<BuildingKind, Recipe>,
+    /// The buildings that grow a crop on the fertile tiles around them.
+    pub farms: HashMap<BuildingKind, FarmRules>,
+    /// Where the buildings can be placed. Missing buildings can be placed on any land.
+    pub placement: HashMap<BuildingKind, Placement>,
+    /// The goods the citizens eat, in the order they are eaten.
+    pub food: Vec<Good>,
+    /// How many goods the storage buildings hold.
+    pub storage: HashMap<BuildingKind, u32>,
+    /// How many citizens the houses shelter.
+    pub housing: HashMap<BuildingKind, u32>,
+    /// The kinds of soldiers the buildings train.
+    pub training: HashMap<BuildingKind, Vec<SoldierKind>>,
+    pub soldiers: HashMap<SoldierKind, SoldierRules>,
+    pub costs: HashMap<BuildingKind, Goods>,
+    /// What each new road tile costs.
+    pub road_cost: Goods,
+    /// The levels a building can be upgraded to, starting from level 2. Buildings start at
+    /// level 1, which is `BuildingLevelRules::default()`.
+    pub upgrades: HashMap<BuildingKind, Vec<BuildingLevelRules>>,
+}
+
+/// What a level of a building is like, and what upgrading to it takes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BuildingLevelRules {
+    /// What upgrading to this level costs.
+    pub cost: Goods,
+    /// How many seconds the upgrade to this level takes, during which the building stops.
+    pub time: f32,
+    /// How many workers the building has at the same time: the gather jobs it posts, or the
+    /// citizens it needs as staff.
+    pub workers: u32,
+    /// How far, in tiles, the workers look for resources.
+    pub radius: u32,
+    /// Multiplier of how fast workers gather and recipes finish. It has to be above 0.
+    pub speed: f32,
+    /// Multiplier of the size of the model.
+    pub scale: f32,
+}
+
+impl Default for BuildingLevelRules {
+    fn default() -> Self {
+        Self {
+            cost: Goods::default(),
+            time: 0.0,
+            workers: 1,
+            radius: BUILDING_RADIUS,
+            speed: 1.0,
+            scale: 1.0,
+        }
+    }
+}
+
+/// How the worker of a building harvests its resource.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProductionRules {
+    pub resource: ResourceKind,
+    /// The good the harvested resource becomes.
+    pub output: Good,
+    /// How many seconds the worker spends at the resource before carrying it back.
+    pub gather_time: f32,
+    /// How many resources the worker carries back per trip, before the tile multiplier.
+    pub carry_capacity: u32,
+    /// Multiplier of the carried amount by the tile the resource is on. Missing tiles count as 1.
+    pub tile_multipliers: HashMap<TileKind, f32>,
+}
+
+/// A processing step: the inputs are taken from the storages in reach and the outputs are stored
+/// `time` seconds later.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Recipe {
+    pub inputs: Goods,
+    pub outputs: Goods,
+    pub time: f32,
+}
+
+/// How a farm grows its crop: the staff sows the fields, the crop grows on its own and then the
+/// staff harvests it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FarmRules {
+    pub output: Good,
+    /// The kind of tile the crop grows on.
+    pub fertile: TileKind,
+    /// How far, in tiles, the fields reach around the farm.
+    pub radius: u32,
+    /// How many seconds sowing and harvesting each take.
+    pub work_time: f32,
+    /// How many seconds the crop grows between sowing and harvesting.
+    pub growth_time: f32,
+    /// How many goods each fertile tile yields per harvest.
+    pub field_yield: f32,
+}
+
+impl FarmRules {
+    /// How many fertile tiles there are within the radius of a farm, looking up the kinds of the
+    /// tiles with `tile_kind`.
+    pub fn fields(
+        &self,
+        global_coord: &IVec2,
+        tile_kind: impl Fn(&IVec2) -> Option<TileKind>,
+    ) -> u32 {
+        let radius = self.radius as i32;
+
+        (-radius..=radius)
+            .flat_map(|y| (-radius..=radius).map(move |x| IVec2::new(x, y)))
+            .filter(|offset| tile_kind(&(*global_coord + *offset)) == Some(self.fertile))
+            .count() as u32
+    }
+
+    /// How many goods a harvest of the given number of fertile tiles yields.
+    pub fn harvest(&self, fields: u32) -> u32 {
+        (fields as f32 * self.field_yield).round() as u32
+    }
+
+    /// The goods harvested per minute from the given number of fertile tiles.
+    pub fn throughput(&self, fields: u32) -> f32 {
+        let cycle_time = self.work_time * 2.0 + self.growth_time;
+        if cycle_time <= 0.0 {
+            return 0.0;
+        }
+
+        self.harvest(fields) as f32 * 60.0 / cycle_time
+    }
+}
+
+/// What the tiles under and around a building have to be for it to be placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Placement {
+    /// The building has to stand on this kind of tile.
+    On(TileKind),
+    /// One of the 4 tiles next to the building has to be of this kind.
+    NextTo(TileKind),
+}
+
+impl Placement {
+    /// Whether the building can be placed on the tile, looking up the kinds of the tiles with
+    /// `tile_kind`. Tiles that are not generated yet don't count.
+    pub fn allows(
+        &self,
+        global_coord: &IVec2,
+        tile_kind: impl Fn(&IVec2) -> Option<TileKind>,
+    ) -> bool {
+        match self {
+            Placement::On(kind) => tile_kind(global_coord).as_ref() == Some(kind),
+            Placement::NextTo(kind) => [IVec2::X, IVec2::NEG_X, IVec2::Y, IVec2::NEG_Y]
+                .iter()
+                .any(|offset| tile_kind(&(*global_coord + *offset)).as_ref() == Some(kind)),
+        }
+    }
+}
+
+impl Recipe {
+    /// The outputs produced per minute when the inputs never run out.
+    pub fn throughput(&self) -> f32 {
+        if self.time <= 0.0 {
+            return 0.0;
+        }
+
+        self.outputs.values().sum::<u32>() as f32 * 60.0 / self.time
+    }
+}
+
+impl ProductionRules {
+    /// How many resources a trip to a resource on the given tile brings back.
+    pub fn trip_yield(&self, tile: &TileKind) -> u32 {
+        let multiplier = self.tile_multipliers.get(tile).copied().unwrap_or(1.0);
+
+        (self.carry_capacity as f32 * multiplier).round() as u32
+    }
+
+    /// The expected resources per minute when harvesting a resource on the given tile, for a
+    /// worker walking `distance` world units per trip at `speed` world units per second.
+    pub fn throughput(&self, tile: &TileKind, distance: f32, speed: f32) -> f32 {
+        let trip_time = self.gather_time + distance / speed;
+        if trip_time <= 0.0 {
+            return 0.0;
+        }
+
+        self.trip_yield(tile) as f32 * 60.0 / trip_time
+    }
+}
+
+impl ProductionSettings {
+    pub fn get(&self, kind: &BuildingKind) -> Option<&ProductionRules> {
+        self.buildings.get(kind)
+    }
+
+    pub fn recipe(&self, kind: &BuildingKind) -> Option<&Recipe> {
+        self.recipes.get(kind)
+    }
+
+    pub fn farm(&self, kind: &BuildingKind) -> Option<&FarmRules> {
+        self.farms.get(kind)
+    }
+
+    pub fn placement(&self, kind: &BuildingKind) -> Option<Placement> {
+        self.placement.get(kind).copied()
+    }
+
+    pub fn storage(&self, kind: &BuildingKind) -> Option<u32> {
+        self.storage.get(kind).copied()
+    }
+
+    pub fn housing(&self, kind: &BuildingKind) -> u32 {
+        self.housing.get(kind).copied().unwrap_or_default()
+    }
+
+    /// The kinds of soldiers a building of this kind trains.
+    pub fn trains(&self, kind: &BuildingKind) -> &[SoldierKind] {
+        self.training
+            .get(kind)
+            .map_or(&[], |soldiers| soldiers.as_slice())
+    }
+
+    pub fn soldier(&self, kind: &SoldierKind) -> Option<&SoldierRules> {
+        self.soldiers.get(kind)
+    }
+
+    /// How many citizens a building of this kind needs at the given level. Only the buildings
+    /// that process or farm goods need any; gathering is done by the free workers.
+    pub fn staff(&self, kind: &BuildingKind, level: u32) -> u32 {
+        if self.recipe(kind).is_none() && self.farm(kind).is_none() {
+            return 0;
+        }
+
+        self.level(kind, level).map_or(0, |level| level.workers)
+    }
+
+    /// What placing a building of this kind costs. Buildings without a cost are free.
+    pub fn cost(&self, kind: &BuildingKind) -> Goods {
+        self.costs.get(kind).cloned().unwrap_or_default()
+    }
+
+    /// What placing a building of this kind and upgrading it to the level cost altogether.
+    pub fn paid(&self, kind: &BuildingKind, level: u32) -> Goods {
+        let mut goods = self.cost(kind);
+        for level in 2..=level {
+            if let Some(rules) = self.level(kind, level) {
+                goods.add(&rules.cost);
+            }
+        }
+
+        goods
+    }
+
+    /// The rules of a level of a building kind, if it has that level.
+    pub fn level(&self, kind: &BuildingKind, level: u32) -> Option<BuildingLevelRules> {
+        match level {
+            0 => None,
+            1 => Some(BuildingLevelRules::default()),
+            _ => self.upgrades.get(kind)?.get(level as usize - 2).cloned(),
+        }
+    }
+
+    /// The highest level a building of this kind can be upgraded to.
+    pub fn max_level(&self, kind: &BuildingKind) -> u32 {
+        self.upgrades.get(kind).map_or(0, |upgrades| upgrades.len()) as u32 + 1
+    }
+}
+
+impl Default for ProductionSettings {
+    fn default() -> Self {
+        Self {
+            buildings: HashMap::from_iter([
+                (
+                    BuildingKind::LumberMill,
+                    ProductionRules {
+                        resource: ResourceKind::Tree,
+                        output: Good::new("logs"),
+                        gather_time: 2.0,
+                        carry_capacity: 2,
+                        // Dead trees on barren land give less wood.
+                        tile_multipliers: HashMap::from_iter([(TileKind::Barren, 0.5)]),
+                    },
+                ),
+                (
+                    BuildingKind::StoneQuarry,
+                    ProductionRules {
+                        resource: ResourceKind::Rock,
+                        output: Good::new("stone"),
+                        gather_time: 4.0,
+                        carry_capacity: 2,
+                        // Rocks on barren land are denser and give more stone.
+                        tile_multipliers: HashMap::from_iter([(TileKind::Barren, 1.5)]),
+                    },
+                ),
+            ]),
+            recipes: HashMap::from_iter([
+                (
+                    BuildingKind::Sawmill,
+                    Recipe {
+                        inputs: Goods::from_iter([("logs", 2)]),
+                        outputs: Goods::from_iter([("planks", 1)]),
+                        time: 6.0,
+                    },
+                ),
+                (
+                    BuildingKind::Mason,
+                    Recipe {
+                        inputs: Goods::from_iter([("stone", 2)]),
+                        outputs: Goods::from_iter([("blocks", 1)]),
+                        time: 8.0,
+                    },
+                ),
+                (
+                    BuildingKind::FishingHut,
+                    Recipe {
+                        inputs: Goods::default(),
+                        outputs: Goods::from_iter([("fish", 2)]),
+                        time: 12.0,
+                    },
+                ),
+            ]),
+            farms: HashMap::from_iter([(
+                BuildingKind::Farm,
+                FarmRules {
+                    output: Good::new("wheat"),
+                    fertile: TileKind::Grass,
+                    radius: 2,
+                    work_time: 5.0,
+                    growth_time: 40.0,
+                    field_yield: 0.5,
+                },
+            )]),
+            placement: HashMap::from_iter([
+                (BuildingKind::Farm, Placement::On(TileKind::Grass)),
+                (BuildingKind::FishingHut, Placement::NextTo(TileKind::Water)),
+            ]),
+            food: vec![Good::new("fish"), Good::new("wheat")],
+            storage: HashMap::from_iter([(BuildingKind::Warehouse, 100)]),
+            housing: HashMap::from_iter([(BuildingKind::House, 4)]),
+            training: HashMap::from_iter([(
+                BuildingKind::Barracks,
+                vec![SoldierKind::Swordsman, SoldierKind::Archer],
+            )]),
+            soldiers: HashMap::from_iter([
+                (
+                    SoldierKind::Swordsman,
+                    SoldierRules {
+                        cost: Goods::from_iter([("planks", 4), ("blocks", 2)]),
+                        time: 15.0,
+                        stats: SoldierStats {
+                            health: 100,
+                            damage: 12,
+                            attack: AttackKind::Melee,
+                            range: 1.0,
+                            attack_speed: 1.0,
+                            armor: 3,
+                            armor_kind: ArmorKind::Heavy,
+                            speed: 14.0,
+                        },
+                    },
+                ),
+                (
+                    SoldierKind::Archer,
+                    SoldierRules {
+                        cost: Goods::from_iter([("logs", 6), ("planks", 4)]),
+                        time: 12.0,
+                        stats: SoldierStats {
+                            health: 60,
+                            damage: 8,
+                            attack: AttackKind::Ranged,
+                            range: 6.0,
+                            attack_speed: 0.8,
+                            armor: 1,
+                            armor_kind: ArmorKind::Light,
+                            speed: 16.0,
+                        },
+                    },
+                ),
+            ]),
+            costs: HashMap::from_iter([
+                (BuildingKind::LumberMill, Goods::from_iter([("logs", 5)])),
+                (BuildingKind::StoneQuarry, Goods::from_iter([("logs", 5)])),
+                (
+                    BuildingKind::Sawmill,
+                    Goods::from_iter([("logs", 10), ("stone", 5)]),
+                ),
+                (
+                    BuildingKind::Mason,
+                    Goods::from_iter([("planks", 5), ("stone", 10)]),
+                ),
+                (BuildingKind::Warehouse, Goods::from_iter([("logs", 10)])),
+                (BuildingKind::House, Goods::from_iter([("logs", 8)])),
+                (BuildingKind::Farm, Goods::from_iter([("logs", 10)])),
+                (BuildingKind::FishingHut, Goods::from_iter([("logs", 6)])),
+                (
+                    BuildingKind::Barracks,
+                    Goods::from_iter([("logs", 15), ("planks", 5), ("stone", 10)]),
+                ),
+            ]),
+            road_cost: Goods::from_iter([("logs", 1)]),
+            upgrades: HashMap::from_iter([
+                (
+                    BuildingKind::LumberMill,
+                    vec![
+                        BuildingLevelRules {
+                            cost: Goods::from_iter([("logs", 10), ("planks", 5)]),
+                            time: 10.0,
+                            workers: 2,
+                            radius: 20,
+                            speed: 1.25,
+                            scale: 1.15,
+                        },
+                        BuildingLevelRules {
+                            cost: Goods::from_iter([("blocks", 5), ("planks", 10)]),
+                            time: 20.0,
+                            workers: 3,
+                            radius: 24,
+                            speed: 1.5,
+                            scale: 1.3,
+                        },
+                    ],
+                ),
+                (
+                    BuildingKind::StoneQuarry,
+                    vec![
+                        BuildingLevelRules {
+                            cost: Goods::from_iter([("logs", 5), ("planks", 5)]),
+                            time: 10.0,
+                            workers: 2,
+                            radius: 20,
+                            speed: 1.25,
+                            scale: 1.15,
+                        },
+                        BuildingLevelRules {
+                            cost: Goods::from_iter([("blocks", 5), ("planks", 10)]),
+                            time: 20.0,
+                            workers: 3,
+                            radius: 24,
+                            speed: 1.5,
+                            scale: 1.3,
+                        },
+                    ],
+                ),
+                (
+                    BuildingKind::Sawmill,
+                    vec![BuildingLevelRules {
+                        cost: Goods::from_iter([("planks", 10), ("stone", 10)]),
+                        time: 15.0,
+                        workers: 2,
+                        radius: BUILDING_RADIUS,
+                        speed: 1.5,
+                        scale: 1.2,
+                    }],
+                ),
+                (
+                    BuildingKind::Mason,
+                    vec![BuildingLevelRules {
+                        cost: Goods::from_iter([("blocks", 5), ("planks", 10)]),
+                        time: 15.0,
+                        workers: 2,
+                        radius: BUILDING_RADIUS,
+                        speed: 1.5,
+                        scale: 1.2,
+                    }],
+                ),
+            ]),
+        }
+    }
+}
+
+impl SettingsAsset for ProductionSettings {
+    const EXTENSIONS: &'static [&'static str] = &["production.ron"];
+    const NAME: &'static str = "Production";
+
+    fn handle(game_assets: &GameAssets) -> &Handle<Self> {
+        &game_assets.production
+    }
+
+    /// A level or soldier with a speed of 0 or less would never finish its work or get anywhere,
+    /// and a gather, recipe, farming, upgrade or training time of 0 or less would be done in no
+    /// time.
+    fn validate(&self) -> Result<(), String> {
+        for (kind, rules) in self.buildings.iter() {
+            check_positive(rules.gather_time, "gather time", kind)?;
+        }
+        for (kind, recipe) in self.recipes.iter() {
+            check_positive(recipe.time, "recipe time", kind)?;
+        }
+        for (kind, farm) in self.farms.iter() {
+            check_positive(farm.work_time, "work time", kind)?;
+            check_positive(farm.growth_time, "growth time", kind)?;
+        }
+        for (kind, soldier) in self.soldiers.iter() {
+            check_positive(soldier.time, "training time", kind)?;
+            check_positive(soldier.stats.speed, "speed", kind)?;
+            check_positive(soldier.stats.attack_speed, "attack speed", kind)?;
+        }
+        for (kind, levels) in self.upgrades.iter() {
+            if levels.iter().any(|level| level.speed <= 0.0) {
+                return Err(format!("The upgrades of {:?} need a speed above 0", kind));
+            }
+            if levels.iter().any(|level| level.time <= 0.0) {
+                return Err(format!("The upgrades of {:?} need a time above 0", kind));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Fail with what the value is and whose it is unless it is above 0.
+fn check_positive(value: f32, name: &str, kind: &impl Debug) -> Result<(), String> {
+    if value > 0.0 {
+        Ok(())
+    } else {
+        Err(format!("The {} of {:?} needs to be above 0", name, kind))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::testing;
+
+    #[test]
+    fn test_production_asset_matches_default() {
+        testing::assert_asset_matches_default::<ProductionSettings>(
+            "settings/buildings.production.ron",
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        let mut settings = ProductionSettings::default();
+        assert!(settings.validate().is_ok());
+
+        settings.upgrades.get_mut(&BuildingKind::Sawmill).unwrap()[0].speed = 0.0;
+        assert!(settings.validate().is_err());
+
+        let mut settings = ProductionSettings::default();
+        settings.upgrades.get_mut(&BuildingKind::Sawmill).unwrap()[0].time = 0.0;
+        assert!(settings.validate().is_err());
+
+        let mut settings = ProductionSettings::default();
+        settings
+            .buildings
+            .get_mut(&BuildingKind::LumberMill)
+            .unwrap()
+            .gather_time = 0.0;
+        assert!(settings.validate().is_err());
+
+        let mut settings = ProductionSettings::default();
+        settings
+            .recipes
+            .get_mut(&BuildingKind::Sawmill)
+            .unwrap()
+            .time = -1.0;
+        assert!(settings.validate().is_err());
+
+        let mut settings = ProductionSettings::default();
+        settings
+            .farms
+            .get_mut(&BuildingKind::Farm)
+            .unwrap()
+            .growth_time = 0.0;
+        assert!(settings.validate().is_err());
+
+        let mut settings = ProductionSettings::default();
+        settings
+            .soldiers
+            .get_mut(&SoldierKind::Archer)
+            .unwrap()
+            .time = 0.0;
+        assert!(settings.validate().is_err());
+
+        let mut settings = ProductionSettings::default();
+        settings
+            .soldiers
+            .get_mut(&SoldierKind::Swordsman)
+            .unwrap()
+            .stats
+            .attack_speed = 0.0;
+        assert!(settings.validate().is_err());
+    }
+
+    #[test]
+    fn test_production_rules_throughput() {
+        let settings = ProductionSettings::default();
+        let rules = settings.get(&BuildingKind::StoneQuarry).unwrap();
+
+        assert_eq!(rules.trip_yield(&TileKind::Grass), 2);
+        assert_eq!(rules.trip_yield(&TileKind::Barren), 3);
+
+        // 2 stones every 4 seconds of gathering plus 64 / 16 seconds of walking.
+        assert_eq!(rules.throughput(&TileKind::Grass, 64.0, 16.0), 15.0);
+        assert!(
+            rules.throughput(&TileKind::Grass, 128.0, 16.0)
+                < rules.throughput(&TileKind::Grass, 64.0, 16.0)
+        );
+    }
+
+    #[test]
+    fn test_training() {
+        let settings = ProductionSettings::default();
+
+        assert_eq!(
+            settings.trains(&BuildingKind::Barracks),
+            &[SoldierKind::Swordsman, SoldierKind::Archer]
+        );
+        assert!(settings.trains(&BuildingKind::House).is_empty());
+        for kind in SoldierKind::ALL {
+            assert!(settings.soldier(&kind).is_some());
+        }
+        assert_eq!(settings.staff(&BuildingKind::Barracks, 1), 0);
+    }
+
+    #[test]
+    fn test_recipe_throughput() {
+        let settings = ProductionSettings::default();
+        let recipe = settings.recipe(&BuildingKind::Sawmill).unwrap();
+
+        assert_eq!(recipe.throughput(), 10.0);
+        assert!(settings.recipe(&BuildingKind::LumberMill).is_none());
+    }
+
+    #[test]
+    fn test_building_levels() {
+        let settings = ProductionSettings::default();
+
+        assert_eq!(settings.max_level(&BuildingKind::LumberMill), 3);
+        assert_eq!(settings.max_level(&BuildingKind::Warehouse), 1);
+        assert_eq!(
+            settings.level(&BuildingKind::LumberMill, 1),
+            Some(BuildingLevelRules::default())
+        );
+        assert_eq!(
+            settings
+                .level(&BuildingKind::LumberMill, 3)
+                .map(|level| level.workers),
+            Some(3)
+        );
+        assert_eq!(settings.level(&BuildingKind::LumberMill, 4), None);
+        assert_eq!(settings.level(&BuildingKind::Warehouse, 0), None);
+
+        // A building at a level paid for itself and every upgrade to it.
+        assert_eq!(
+            settings.paid(&BuildingKind::LumberMill, 1),
+            settings.cost(&BuildingKind::LumberMill)
+        );
+        assert_eq!(
+            settings.paid(&BuildingKind::LumberMill, 3),
+            Goods::from_iter([("logs", 15), ("planks", 15), ("blocks", 5)])
+        );
+    }
+
+    #[test]
+    fn test_building_staff() {
+        let settings = ProductionSettings::default();
+
+        assert_eq!(settings.staff(&BuildingKind::LumberMill, 2), 0);
+        assert_eq!(settings.staff(&BuildingKind::Sawmill, 1), 1);
+        assert_eq!(settings.staff(&BuildingKind::House, 1), 0);
+        assert_eq!(settings.housing(&BuildingKind::House), 4);
+        assert_eq!(settings.housing(&BuildingKind::Warehouse), 0);
+        assert_eq!(settings.staff(&BuildingKind::Farm, 1), 1);
+    }
+
+    #[test]
+    fn test_farm_harvest() {
+        let settings = ProductionSettings::default();
+        let rules = settings.farm(&BuildingKind::Farm).unwrap();
+
+        assert_eq!(rules.harvest(25), 13);
+        assert_eq!(rules.harvest(0), 0);
+        // 10 wheat every 5 seconds of sowing, 40 of growing and 5 of harvesting.
+        assert_eq!(rules.throughput(20), 12.0);
+
+        // The column left of the farm is water, the rest is grass.
+        let tile_kind = |coord: &IVec2| match coord.x {
+            -1 => Some(TileKind::Water),
+            _ => Some(TileKind::Grass),
+        };
+        assert_eq!(rules.fields(&IVec2::ZERO, tile_kind), 20);
+    }
+
+    #[test]
+    fn test_placement_allows() {
+        let tile_kind = |coord: &IVec2| match coord.x {
+            x if x < 0 => Some(TileKind::Water),
+            0 => Some(TileKind::Grass),
+            _ => Some(TileKind::Barren),
+        };
+
+        assert!(Placement::On(TileKind::Grass).allows(&IVec2::ZERO, tile_kind));
+        assert!(!Placement::On(TileKind::Grass).allows(&IVec2::X, tile_kind));
+        assert!(Placement::NextTo(TileKind::Water).allows(&IVec2::ZERO, tile_kind));
+        assert!(!Placement::NextTo(TileKind::Water).allows(&IVec2::X, tile_kind));
+        assert!(!Placement::NextTo(TileKind::Water).allows(&IVec2::ZERO, |_| None));
+    }
+}
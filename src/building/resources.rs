@@ -0,0 +1,165 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::goods::Goods;
+
+use super::{BuildingKind, BuildingOrder, BUILD_HISTORY_LIMIT};
+
+/// How many buildings were placed so far. The next one is numbered with it.
+#[derive(Resource, Debug, Default, Deref, DerefMut)]
+pub struct BuildingsPlaced(pub u32);
+
+impl BuildingsPlaced {
+    /// The order of the next building placed.
+    pub fn next(&mut self) -> BuildingOrder {
+        self.0 += 1;
+        BuildingOrder(self.0)
+    }
+}
+
+/// Everything needed to spawn a building again after it was removed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildingSpec {
+    pub kind: BuildingKind,
+    pub tile: IVec2,
+    pub rotation: Quat,
+    pub level: u32,
+    /// What was in its storage, if it had one.
+    pub goods: Goods,
+    /// It had started producing, so it can't be cancelled for a refund once it is back.
+    pub producing: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildActionKind {
+    /// The buildings were spawned and `goods` were paid for them.
+    Added,
+    /// The buildings were despawned and `goods` were refunded for them.
+    Removed,
+}
+
+/// An action that was done to some buildings, with what is needed to take it back or do it again.
+/// The buildings are kept by tile rather than by entity, since they can be despawned and spawned
+/// again in the meantime, and are looked up on their tiles when the action is taken back.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuildAction {
+    pub kind: BuildActionKind,
+    pub buildings: Vec<BuildingSpec>,
+    pub goods: Goods,
+}
+
+/// The actions that can be undone, oldest first, and the undone actions that can be redone.
+#[derive(Resource, Debug, Default)]
+pub struct BuildHistory {
+    undo: VecDeque<BuildAction>,
+    redo: Vec<BuildAction>,
+}
+
+impl BuildHistory {
+    /// Record a new action. It can't be redone after anything else, so the undone actions are
+    /// forgotten, and so is the oldest action once the history is full, which is returned.
+    pub fn push(&mut self, action: BuildAction) -> Option<BuildAction> {
+        self.redo.clear();
+        self.undo.push_back(action);
+        self.forget_oldest()
+    }
+
+    pub fn pop_undo(&mut self) -> Option<BuildAction> {
+        self.undo.pop_back()
+    }
+
+    pub fn pop_redo(&mut self) -> Option<BuildAction> {
+        self.redo.pop()
+    }
+
+    /// Record an action that was undone, so it can be redone.
+    pub fn undone(&mut self, action: BuildAction) {
+        self.redo.push(action);
+    }
+
+    /// Record an action that was redone, without forgetting the other undone actions. The oldest
+    /// action is forgotten and returned once the history is full.
+    pub fn redone(&mut self, action: BuildAction) -> Option<BuildAction> {
+        self.undo.push_back(action);
+        self.forget_oldest()
+    }
+
+    /// Put back an action that couldn't be undone right now, so it can be tried again.
+    pub fn retry_undo(&mut self, action: BuildAction) {
+        self.undo.push_back(action);
+    }
+
+    /// Put back an action that couldn't be redone right now, so it can be tried again.
+    pub fn retry_redo(&mut self, action: BuildAction) {
+        self.redo.push(action);
+    }
+
+    fn forget_oldest(&mut self) -> Option<BuildAction> {
+        match self.undo.len() > BUILD_HISTORY_LIMIT {
+            true => self.undo.pop_front(),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn action(tile: i32) -> BuildAction {
+        BuildAction {
+            kind: BuildActionKind::Added,
+            buildings: vec![BuildingSpec {
+                kind: BuildingKind::LumberMill,
+                tile: IVec2::new(tile, 0),
+                rotation: Quat::IDENTITY,
+                level: 1,
+                goods: Goods::default(),
+                producing: false,
+            }],
+            goods: Goods::from_iter([("logs", 5)]),
+        }
+    }
+
+    #[test]
+    fn test_build_history_bounded() {
+        let mut history = BuildHistory::default();
+        for tile in 0..BUILD_HISTORY_LIMIT as i32 {
+            assert_eq!(history.push(action(tile)), None);
+        }
+        for tile in BUILD_HISTORY_LIMIT as i32..BUILD_HISTORY_LIMIT as i32 + 5 {
+            // The oldest action is handed back as it is forgotten.
+            assert_eq!(
+                history.push(action(tile)),
+                Some(action(tile - BUILD_HISTORY_LIMIT as i32))
+            );
+        }
+
+        let mut undone = 0;
+        while let Some(action) = history.pop_undo() {
+            history.undone(action);
+            undone += 1;
+        }
+
+        assert_eq!(undone, BUILD_HISTORY_LIMIT);
+        assert_eq!(history.pop_redo(), Some(action(5)));
+    }
+
+    #[test]
+    fn test_build_history_push_clears_redo() {
+        let mut history = BuildHistory::default();
+        history.push(action(0));
+        history.push(action(1));
+
+        let undone = history.pop_undo().unwrap();
+        history.undone(undone);
+
+        history.push(action(2));
+
+        assert_eq!(history.pop_redo(), None);
+        assert_eq!(history.pop_undo(), Some(action(2)));
+        assert_eq!(history.pop_undo(), Some(action(0)));
+        assert_eq!(history.pop_undo(), None);
+    }
+}
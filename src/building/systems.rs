@@ -1,17 +1,39 @@
-use std::{collections::VecDeque, f32::consts::FRAC_PI_2};
+use std::f32::consts::FRAC_PI_2;
 
-use bevy::{prelude::*, utils::HashSet};
+use bevy::{
+    ecs::system::{EntityCommands, SystemParam},
+    prelude::*,
+    utils::HashSet,
+};
 
 use crate::{
-    core::{GameAssets, Obstacle, ToolMode},
+    core::{Alert, AlertKind, CursorActive, GameAssets, Obstacle, ToolMode},
+    fog::Vision,
+    goods::{
+        storages_in_reach, take_from_storages, Goods, Stockpile, Storage, StoragesInReach,
+        STARTING_GOODS,
+    },
     helpers,
-    quota::ResourceCount,
-    terrain::{ChunkCoord, ChunkManager, ResourceKind, TileCoord, TileKind, TileMapping},
-    units::{Unit, UnitVelocity, UnitWaypointAction, UnitWaypoints},
+    jobs::{Job, JobKind, JobState},
+    military::TrainingQueue,
+    population::Population,
+    roads::Roads,
+    selection::{Selectable, Selected},
+    terrain::{
+        ChunkCoord, ChunkManager, ResourceKind, ResourceMapping, TileCoord, TileKind, TileMapping,
+    },
+    units::UNIT_SPEED,
 };
 
 use super::{
-    Building, BuildingHasWorker, BuildingKind, BuildingTool, BuildingToolValid, BuildingValidGhost, GhostBuilding, ValidBuildingToolMaterial, BUILDING_COST, BUILDING_RADIUS
+    BuildAction, BuildActionKind, BuildCommand, BuildGround, BuildHistory, Building,
+    BuildingBuilder, BuildingDragGhost, BuildingIdle, BuildingKind, BuildingLevel, BuildingOrder,
+    BuildingPriority, BuildingProcessing, BuildingProducing, BuildingSpec, BuildingStaff,
+    BuildingThroughput, BuildingTool, BuildingToolDrag, BuildingToolValid, BuildingUpgrading,
+    BuildingValidGhost, BuildingWorkers, BuildingsPlaced, ChangeBuildingPriority, CropStage,
+    FarmCrop, GhostBuilding, PlaceBuildings, ProductionRules, ProductionSettings, ResourceClaimed,
+    UpgradeBuilding, ValidBuildingToolMaterial, BUILDING_DRAG_LIMIT, BUILDING_SELECT_RADIUS,
+    BUILDING_VISION, STORAGE_REACH,
 };
 
 pub fn setup_building_tool(
@@ -24,6 +46,7 @@ pub fn setup_building_tool(
         .spawn((
             BuildingTool,
             BuildingToolValid(false),
+            BuildingToolDrag::default(),
             BuildingKind::default(),
             SpatialBundle {
                 transform: Transform::from_xyz(0.0, 0.0, 0.0).with_scale(Vec3::splat(16.0)),
@@ -57,11 +80,12 @@ pub fn setup_building_tool(
 }
 
 pub fn update_tool_ghost_material(
-    q_tool: Query<&BuildingToolValid, With<BuildingTool>>,
+    q_tool: Query<(&BuildingToolValid, &BuildingToolDrag), With<BuildingTool>>,
     q_tool_ghost: Query<&Handle<ValidBuildingToolMaterial>, With<BuildingValidGhost>>,
+    q_drag_ghosts: Query<(&BuildingDragGhost, &Handle<ValidBuildingToolMaterial>)>,
     mut materials: ResMut<Assets<ValidBuildingToolMaterial>>,
 ) {
-    let Ok(building_valid) = q_tool.get_single() else {
+    let Ok((building_valid, drag)) = q_tool.get_single() else {
         return;
     };
 
@@ -70,6 +94,12 @@ pub fn update_tool_ghost_material(
             material.valid = **building_valid as u32;
         }
     }
+
+    for (ghost, handle) in q_drag_ghosts.iter() {
+        if let Some(material) = materials.get_mut(handle) {
+            material.valid = drag.valid.contains(&**ghost) as u32;
+        }
+    }
 }
 
 pub fn update_ghost_building(
@@ -117,6 +147,27 @@ pub fn select_building_kind(
     } else if input.just_pressed(KeyCode::Digit2) {
         *tool_mode = ToolMode::Build;
         *building_kind = BuildingKind::StoneQuarry;
+    } else if input.just_pressed(KeyCode::Digit3) {
+        *tool_mode = ToolMode::Build;
+        *building_kind = BuildingKind::Sawmill;
+    } else if input.just_pressed(KeyCode::Digit4) {
+        *tool_mode = ToolMode::Build;
+        *building_kind = BuildingKind::Mason;
+    } else if input.just_pressed(KeyCode::Digit5) {
+        *tool_mode = ToolMode::Build;
+        *building_kind = BuildingKind::Warehouse;
+    } else if input.just_pressed(KeyCode::Digit6) {
+        *tool_mode = ToolMode::Build;
+        *building_kind = BuildingKind::House;
+    } else if input.just_pressed(KeyCode::Digit7) {
+        *tool_mode = ToolMode::Build;
+        *building_kind = BuildingKind::Farm;
+    } else if input.just_pressed(KeyCode::Digit8) {
+        *tool_mode = ToolMode::Build;
+        *building_kind = BuildingKind::FishingHut;
+    } else if input.just_pressed(KeyCode::Digit9) {
+        *tool_mode = ToolMode::Build;
+        *building_kind = BuildingKind::Barracks;
     }
 }
 
@@ -157,149 +208,1462 @@ pub fn follow_building_tool(
     building_tool_transform.translation = tile_pos.extend(0.0).xzy();
 }
 
-pub fn handle_building_tool(
-    mut commands: Commands,
+/// Start dragging the building tool where the button is pressed and follow the cursor while it
+/// is held, over a line of tiles or, with Shift, over an area.
+pub fn drag_building_tool(
     mouse_button_input: Res<ButtonInput<MouseButton>>,
-    q_tool: Query<(&BuildingKind, &BuildingToolValid, &Transform), With<BuildingTool>>,
+    input: Res<ButtonInput<KeyCode>>,
+    cursor_active: Res<CursorActive>,
+    mut q_tool: Query<(&Transform, &mut BuildingToolDrag), With<BuildingTool>>,
     chunk_manager: Res<ChunkManager>,
-    game_assets: Res<GameAssets>,
-    mut resources: ResMut<ResourceCount>,
 ) {
-    let Ok((building_kind, building_valid, tool_transform)) = q_tool.get_single() else {
+    let Ok((tool_transform, mut drag)) = q_tool.get_single_mut() else {
+        return;
+    };
+
+    let size = chunk_manager.size();
+    let tile_size = chunk_manager.tile_size();
+    let coord = helpers::geometry::world_pos_to_global_coord(
+        &tool_transform.translation.xz(),
+        &size,
+        &tile_size,
+    );
+
+    if mouse_button_input.just_pressed(MouseButton::Left) && **cursor_active {
+        drag.start = Some(coord);
+    }
+    let Some(start) = drag.start else {
+        return;
+    };
+    if !mouse_button_input.pressed(MouseButton::Left) {
         return;
+    }
+
+    let tiles = match input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        true => helpers::geometry::area_coords(&start, &coord)
+            .take(BUILDING_DRAG_LIMIT)
+            .collect(),
+        false => helpers::geometry::line_coords(&start, &coord)
+            .into_iter()
+            .take(BUILDING_DRAG_LIMIT)
+            .collect::<Vec<_>>(),
     };
 
-    if !**building_valid {
+    // Only touch the drag when it changes, so the ghosts are respawned only then.
+    if drag.tiles != tiles {
+        drag.tiles = tiles;
+    }
+}
+
+/// Place the buildings on the valid tiles of the drag when the button is released.
+pub fn release_building_tool(
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    mut q_tool: Query<(&BuildingKind, &Transform, &mut BuildingToolDrag), With<BuildingTool>>,
+    mut build_commands: EventWriter<BuildCommand>,
+) {
+    if !mouse_button_input.just_released(MouseButton::Left) {
+        return;
+    }
+    let Ok((building_kind, tool_transform, mut drag)) = q_tool.get_single_mut() else {
+        return;
+    };
+    if drag.start.is_none() {
         return;
     }
 
-    let point = tool_transform.translation;
+    let tiles = drag
+        .tiles
+        .iter()
+        .filter(|tile| drag.valid.contains(*tile))
+        .copied()
+        .collect::<Vec<_>>();
+    *drag = BuildingToolDrag::default();
+
+    if !tiles.is_empty() {
+        build_commands.send(BuildCommand::Place(PlaceBuildings {
+            kind: building_kind.clone(),
+            rotation: tool_transform.rotation,
+            tiles,
+        }));
+    }
+}
+
+/// Forget the drag when the building tool is put away in the middle of it.
+pub fn clear_building_drag(
+    tool_mode: Res<ToolMode>,
+    mut q_tool: Query<&mut BuildingToolDrag, With<BuildingTool>>,
+) {
+    if matches!(*tool_mode, ToolMode::Build) {
+        return;
+    }
+
+    for mut drag in q_tool.iter_mut() {
+        if drag.start.is_some() {
+            *drag = BuildingToolDrag::default();
+        }
+    }
+}
+
+/// The materials of the drag ghosts, which the ghosts of every drag take again. New ones are only
+/// made for drags longer than any so far.
+#[derive(SystemParam)]
+pub struct DragGhostMaterials<'w, 's> {
+    materials: ResMut<'w, Assets<ValidBuildingToolMaterial>>,
+    handles: Local<'s, Vec<Handle<ValidBuildingToolMaterial>>>,
+}
+
+impl DragGhostMaterials<'_, '_> {
+    /// The material of the ghost on the tile with this index in the drag.
+    fn get(&mut self, index: usize) -> Handle<ValidBuildingToolMaterial> {
+        while self.handles.len() <= index {
+            let handle = self.materials.add(ValidBuildingToolMaterial::default());
+            self.handles.push(handle);
+        }
+
+        self.handles[index].clone()
+    }
+}
+
+/// Respawn a ghost on every tile of the drag when it changes. The ghost of the tool itself is
+/// hidden while dragging, since the drag covers its tile too.
+pub fn update_building_drag_ghosts(
+    mut commands: Commands,
+    tool_mode: Res<ToolMode>,
+    q_tool: Query<(&Transform, Ref<BuildingToolDrag>), With<BuildingTool>>,
+    q_ghosts: Query<Entity, With<BuildingDragGhost>>,
+    mut q_tool_ghost: Query<(&Handle<Mesh>, &mut Visibility), With<BuildingValidGhost>>,
+    mut materials: DragGhostMaterials,
+    chunk_manager: Res<ChunkManager>,
+) {
+    let Ok((tool_transform, drag)) = q_tool.get_single() else {
+        return;
+    };
+    if !drag.is_changed() {
+        return;
+    }
+    let Ok((mesh, mut visibility)) = q_tool_ghost.get_single_mut() else {
+        return;
+    };
+
+    *visibility = match (&*tool_mode, drag.start) {
+        (ToolMode::Build, None) => Visibility::Visible,
+        _ => Visibility::Hidden,
+    };
+
+    for ghost in q_ghosts.iter() {
+        commands.entity(ghost).despawn_recursive();
+    }
 
     let size = chunk_manager.size();
     let tile_size = chunk_manager.tile_size();
-    let chunk_coord = helpers::geometry::world_pos_to_chunk_coord(&point.xz(), &size, &tile_size);
-    let Some(chunk) = chunk_manager.get(&chunk_coord) else {
+    for (index, tile) in drag.tiles.iter().enumerate() {
+        let position = helpers::geometry::global_coord_to_world_pos(tile, &size, &tile_size);
+
+        commands.spawn((
+            BuildingDragGhost(*tile),
+            MaterialMeshBundle {
+                mesh: mesh.clone(),
+                material: materials.get(index),
+                transform: Transform::from_translation(
+                    position.extend(tool_transform.scale.y * 0.5).xzy(),
+                )
+                .with_scale(tool_transform.scale),
+                ..default()
+            },
+        ));
+    }
+}
+
+/// The selected buildings, with whether they can be cancelled for a refund.
+type SelectedBuildings<'w, 's> = Query<
+    'w,
+    's,
+    (Entity, Has<BuildingProducing>, Option<&'static Storage>),
+    (With<Building>, With<Selected>),
+>;
+
+/// Undo with Ctrl+Z, redo with Ctrl+Y or Ctrl+Shift+Z, upgrade the selected buildings with U and
+/// remove them with Delete: their construction is cancelled if they have not started producing,
+/// otherwise they are demolished.
+pub fn send_build_commands(
+    input: Res<ButtonInput<KeyCode>>,
+    q_selected: SelectedBuildings,
+    mut build_commands: EventWriter<BuildCommand>,
+    mut upgrades: EventWriter<UpgradeBuilding>,
+    mut priorities: EventWriter<ChangeBuildingPriority>,
+) {
+    let control = input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]);
+    let shift = input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+
+    if control && input.just_pressed(KeyCode::KeyZ) {
+        build_commands.send(match shift {
+            true => BuildCommand::Redo,
+            false => BuildCommand::Undo,
+        });
+    } else if control && input.just_pressed(KeyCode::KeyY) {
+        build_commands.send(BuildCommand::Redo);
+    }
+
+    if input.just_pressed(KeyCode::KeyU) {
+        for (building, ..) in q_selected.iter() {
+            upgrades.send(UpgradeBuilding(building));
+        }
+    }
+
+    if input.just_pressed(KeyCode::KeyP) {
+        for (building, ..) in q_selected.iter() {
+            priorities.send(ChangeBuildingPriority(building));
+        }
+    }
+
+    if input.just_pressed(KeyCode::Delete) {
+        for (building, is_producing, storage) in q_selected.iter() {
+            let has_goods = storage.is_some_and(|storage| !storage.goods.is_empty());
+            build_commands.send(match is_producing || has_goods {
+                true => BuildCommand::Demolish(building),
+                false => BuildCommand::Cancel(building),
+            });
+        }
+    }
+}
+
+/// What is needed to put buildings on tiles: the tiles themselves, and the models and rules of the
+/// buildings.
+#[derive(SystemParam)]
+pub struct BuildingSites<'w, 's> {
+    ground: BuildGround<'w, 's>,
+    roads: Res<'w, Roads>,
+    game_assets: Res<'w, GameAssets>,
+    production: Res<'w, ProductionSettings>,
+    placed: ResMut<'w, BuildingsPlaced>,
+}
+
+/// The rules of the buildings and the population, to tell how fast the buildings work.
+#[derive(SystemParam)]
+pub struct BuildingWork<'w> {
+    production: Res<'w, ProductionSettings>,
+    population: Res<'w, Population>,
+}
+
+impl BuildingWork<'_> {
+    /// How fast a building works with its staff. Every citizen of the staff does the work of the
+    /// staff of the first level, so the staff an upgrade adds speeds the building up. A hungry
+    /// building works slower, and not at all without any staff.
+    fn speed(&self, kind: &BuildingKind, level: u32, staff: u32) -> f32 {
+        let staffing = staff as f32 / self.production.staff(kind, 1).max(1) as f32;
+
+        self.production.level(kind, level).unwrap_or_default().speed
+            * staffing
+            * self.population.work_speed()
+    }
+}
+
+/// The buildings, with what it takes to place them again.
+type PlacedBuildings<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static BuildingKind,
+        &'static BuildingLevel,
+        &'static GlobalTransform,
+        &'static Transform,
+        Has<BuildingProducing>,
+    ),
+    With<Building>,
+>;
+
+/// Carry out the build commands of the player and keep the history of what they did. An undo or
+/// a redo that can't be carried out anymore, because its buildings are gone or its tiles are
+/// taken, is dropped from the history instead of blocking the actions behind it. One that can't
+/// be carried out right now, e.g. because the storages are full, stays to be tried again.
+pub fn handle_build_commands(
+    mut commands: Commands,
+    mut build_commands: EventReader<BuildCommand>,
+    mut history: ResMut<BuildHistory>,
+    q_buildings: PlacedBuildings,
+    mut q_storages: Query<(Entity, &GlobalTransform, &mut Storage)>,
+    mut sites: BuildingSites,
+) {
+    let size = sites.ground.chunk_manager.size();
+    let tile_size = sites.ground.chunk_manager.tile_size();
+
+    for command in build_commands.read() {
+        match command {
+            BuildCommand::Place(placement) => {
+                let buildings = placement
+                    .tiles
+                    .iter()
+                    .map(|tile| BuildingSpec {
+                        kind: placement.kind.clone(),
+                        tile: *tile,
+                        rotation: placement.rotation,
+                        level: 1,
+                        goods: Goods::default(),
+                        producing: false,
+                    })
+                    .collect::<Vec<_>>();
+                let mut action = BuildAction {
+                    kind: BuildActionKind::Added,
+                    goods: sites
+                        .production
+                        .cost(&placement.kind)
+                        .times(buildings.len() as u32),
+                    buildings,
+                };
+
+                if add_buildings(&mut action, &mut commands, &mut q_storages, &mut sites).is_ok() {
+                    if let Some(forgotten) = history.push(action) {
+                        refund_forgotten_goods(
+                            &forgotten,
+                            &mut q_storages,
+                            &sites.ground.chunk_manager,
+                        );
+                    }
+                } else {
+                    warn!(
+                        "Not enough goods to place {} buildings",
+                        placement.tiles.len()
+                    );
+                }
+            }
+            BuildCommand::Demolish(building) | BuildCommand::Cancel(building) => {
+                let Ok((_, kind, level, global_transform, transform, is_producing)) =
+                    q_buildings.get(*building)
+                else {
+                    continue;
+                };
+                let is_cancel = matches!(command, BuildCommand::Cancel(_));
+                let mut action = BuildAction {
+                    kind: BuildActionKind::Removed,
+                    buildings: vec![BuildingSpec {
+                        kind: kind.clone(),
+                        tile: helpers::geometry::world_pos_to_global_coord(
+                            &global_transform.translation().xz(),
+                            &size,
+                            &tile_size,
+                        ),
+                        rotation: transform.rotation,
+                        level: **level,
+                        goods: Goods::default(),
+                        producing: is_producing,
+                    }],
+                    goods: match is_cancel {
+                        true => sites.production.paid(kind, **level),
+                        false => Goods::default(),
+                    },
+                };
+
+                if remove_buildings(
+                    &mut action,
+                    is_cancel,
+                    &mut commands,
+                    &q_buildings,
+                    &mut q_storages,
+                    &sites.ground.chunk_manager,
+                )
+                .is_ok()
+                {
+                    if let Some(forgotten) = history.push(action) {
+                        refund_forgotten_goods(
+                            &forgotten,
+                            &mut q_storages,
+                            &sites.ground.chunk_manager,
+                        );
+                    }
+                } else {
+                    warn!("Could not remove building {:?}", building);
+                }
+            }
+            BuildCommand::Undo => {
+                let Some(mut action) = history.pop_undo() else {
+                    continue;
+                };
+
+                // Placing can only be undone while none of the buildings has started producing.
+                let undone = match action.kind {
+                    BuildActionKind::Added => remove_buildings(
+                        &mut action,
+                        true,
+                        &mut commands,
+                        &q_buildings,
+                        &mut q_storages,
+                        &sites.ground.chunk_manager,
+                    ),
+                    BuildActionKind::Removed => {
+                        add_buildings(&mut action, &mut commands, &mut q_storages, &mut sites)
+                    }
+                };
+
+                match undone {
+                    Ok(()) => history.undone(action),
+                    Err(BuildFailure::Gone) => {
+                        warn!("Could not undo the last build action, so it was dropped")
+                    }
+                    Err(BuildFailure::Blocked) => {
+                        warn!("Could not undo the last build action yet");
+                        history.retry_undo(action);
+                    }
+                }
+            }
+            BuildCommand::Redo => {
+                let Some(mut action) = history.pop_redo() else {
+                    continue;
+                };
+
+                // Removing buildings with a refund was a cancel, which needs them to be idle.
+                let redone = match action.kind {
+                    BuildActionKind::Added => {
+                        add_buildings(&mut action, &mut commands, &mut q_storages, &mut sites)
+                    }
+                    BuildActionKind::Removed => {
+                        let only_idle = !action.goods.is_empty();
+                        remove_buildings(
+                            &mut action,
+                            only_idle,
+                            &mut commands,
+                            &q_buildings,
+                            &mut q_storages,
+                            &sites.ground.chunk_manager,
+                        )
+                    }
+                };
+
+                match redone {
+                    Ok(()) => {
+                        if let Some(forgotten) = history.redone(action) {
+                            refund_forgotten_goods(
+                                &forgotten,
+                                &mut q_storages,
+                                &sites.ground.chunk_manager,
+                            );
+                        }
+                    }
+                    Err(BuildFailure::Gone) => {
+                        warn!("Could not redo the last undone build action, so it was dropped")
+                    }
+                    Err(BuildFailure::Blocked) => {
+                        warn!("Could not redo the last undone build action yet");
+                        history.retry_redo(action);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Put the goods of the demolished storages of an action that was forgotten by the history back
+/// into the storages closest to where they stood, however far away, since undoing can't bring
+/// them back anymore. What doesn't fit anywhere is lost.
+fn refund_forgotten_goods(
+    action: &BuildAction,
+    q_storages: &mut Query<(Entity, &GlobalTransform, &mut Storage)>,
+    chunk_manager: &ChunkManager,
+) {
+    // Only removed buildings are gone with their goods; added ones still hold them.
+    if action.kind != BuildActionKind::Removed {
         return;
+    }
+
+    let size = chunk_manager.size();
+    let tile_size = chunk_manager.tile_size();
+    for building in action
+        .buildings
+        .iter()
+        .filter(|building| !building.goods.is_empty())
+    {
+        let point = helpers::geometry::global_coord_to_world_pos(&building.tile, &size, &tile_size);
+        let mut storages = storages_in_reach(
+            q_storages
+                .iter_mut()
+                .map(|(_, transform, storage)| (transform, storage)),
+            &point,
+            f32::INFINITY,
+        );
+
+        let mut rest = building.goods.clone();
+        for storage in storages.iter_mut() {
+            rest = storage.store(&rest);
+        }
+        if !rest.is_empty() {
+            warn!(
+                "No room for the goods of a demolished {}, lost {}",
+                building.kind.name(),
+                rest
+            );
+        }
+    }
+}
+
+/// Why the buildings of an action couldn't be added or removed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BuildFailure {
+    /// The buildings are gone or their tiles are taken, so the action can't ever be carried out.
+    Gone,
+    /// The goods are missing or don't fit, or a building is busy, which can change later.
+    Blocked,
+}
+
+/// Spawn the buildings of the action on the tiles that can still be built on, with their level,
+/// the goods they stored and whether they had started producing, and take the goods for them from
+/// the storages in reach of the first one. When the action has a cost, only the spawned buildings
+/// are paid for, at their level, and the action keeps only them. Nothing happens unless a building
+/// can be spawned and all of its goods are there.
+fn add_buildings(
+    action: &mut BuildAction,
+    commands: &mut Commands,
+    q_storages: &mut Query<(Entity, &GlobalTransform, &mut Storage)>,
+    sites: &mut BuildingSites,
+) -> Result<(), BuildFailure> {
+    let BuildingSites {
+        ground,
+        roads,
+        game_assets,
+        production,
+        placed,
+    } = sites;
+
+    let buildings = action
+        .buildings
+        .iter()
+        .filter(|building| {
+            ground.is_buildable(&building.tile, production.placement(&building.kind), roads)
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    let Some(first) = buildings.first() else {
+        return Err(BuildFailure::Gone);
     };
 
-    let tile_coord = helpers::geometry::world_pos_to_tile_coord(&point.xz(), &size, &tile_size);
-    let tile_pos = helpers::geometry::tile_coord_to_world_off(&tile_coord, &size, &tile_size);
-    if mouse_button_input.just_pressed(MouseButton::Left) {
-        **resources -= BUILDING_COST;
+    let goods = match action.goods.is_empty() {
+        true => Goods::default(),
+        false => {
+            let mut goods = Goods::default();
+            for building in buildings.iter() {
+                goods.add(&production.paid(&building.kind, building.level));
+            }
+            goods
+        }
+    };
 
-        let scene = game_assets.buildings[&*building_kind].clone();
+    let size = ground.chunk_manager.size();
+    let tile_size = ground.chunk_manager.tile_size();
+    let reach = STORAGE_REACH as f32 * tile_size.x.max(tile_size.y);
+    let start = helpers::geometry::global_coord_to_world_pos(&first.tile, &size, &tile_size);
+    let mut storages = storages_in_reach(
+        q_storages
+            .iter_mut()
+            .map(|(_, transform, storage)| (transform, storage)),
+        &start,
+        reach,
+    );
+    if !take_from_storages(&mut storages, &goods) {
+        return Err(BuildFailure::Blocked);
+    }
+
+    for building in buildings.iter() {
+        // The tile is buildable, so its chunk is there.
+        let chunk_coord = helpers::geometry::global_coord_to_chunk_coord(&building.tile, &size);
+        let Some(chunk) = ground.chunk_manager.get(&chunk_coord) else {
+            continue;
+        };
+        let tile_coord = helpers::geometry::global_coord_to_tile_coord(&building.tile, &size);
+        let tile_pos = helpers::geometry::tile_coord_to_world_off(&tile_coord, &size, &tile_size);
+        let scale = production
+            .level(&building.kind, building.level)
+            .unwrap_or_default()
+            .scale;
+        let transform = Transform::from_translation(tile_pos.extend(0.0).xzy())
+            .with_rotation(building.rotation)
+            .with_scale(Vec3::splat(16.0 * scale));
 
+        let order = placed.next();
         commands.entity(*chunk).with_children(|parent| {
-            parent.spawn((
-                Building,
-                building_kind.clone(),
-                TileCoord(tile_coord),
-                Obstacle,
-                SceneBundle {
-                    scene,
-                    transform: tool_transform.with_translation(tile_pos.extend(0.0).xzy()),
-                    ..default()
-                },
-            ));
+            let mut spawned = spawn_building(
+                parent,
+                &building.kind,
+                order,
+                tile_coord,
+                transform,
+                game_assets,
+                production,
+            );
+            spawned.insert(BuildingLevel(building.level));
+            if building.producing {
+                spawned.insert(BuildingProducing);
+            }
+            if let (Some(capacity), false) = (
+                production.storage(&building.kind),
+                building.goods.is_empty(),
+            ) {
+                spawned.insert(Storage {
+                    capacity,
+                    goods: building.goods.clone(),
+                });
+            }
         });
     }
+
+    action.buildings = buildings;
+    action.goods = goods;
+
+    Ok(())
 }
 
-pub fn check_building_tool_valid(
+/// Despawn the buildings of the action, found on their tiles, and refund its goods to the storages
+/// in reach of its first building. Nothing happens unless all of the buildings are still there,
+/// the refund fits in the storages and, when `only_idle` is set, none of the buildings has started
+/// producing or holds any goods.
+fn remove_buildings(
+    action: &mut BuildAction,
+    only_idle: bool,
+    commands: &mut Commands,
+    q_buildings: &PlacedBuildings,
+    q_storages: &mut Query<(Entity, &GlobalTransform, &mut Storage)>,
+    chunk_manager: &ChunkManager,
+) -> Result<(), BuildFailure> {
+    let size = chunk_manager.size();
+    let tile_size = chunk_manager.tile_size();
+    let tile_of = |transform: &GlobalTransform| {
+        helpers::geometry::world_pos_to_global_coord(
+            &transform.translation().xz(),
+            &size,
+            &tile_size,
+        )
+    };
+
+    let mut entities = Vec::new();
+    let mut buildings = Vec::new();
+    for spec in action.buildings.iter() {
+        let found = q_buildings
+            .iter()
+            .find(|(_, kind, _, global_transform, ..)| {
+                **kind == spec.kind && tile_of(global_transform) == spec.tile
+            });
+        let Some((entity, kind, level, _, transform, is_producing)) = found else {
+            return Err(BuildFailure::Gone);
+        };
+        let goods = q_storages
+            .get(entity)
+            .map(|(_, _, storage)| storage.goods.clone())
+            .unwrap_or_default();
+        if only_idle && (is_producing || !goods.is_empty()) {
+            return Err(BuildFailure::Blocked);
+        }
+
+        entities.push(entity);
+        buildings.push(BuildingSpec {
+            kind: kind.clone(),
+            tile: spec.tile,
+            rotation: transform.rotation,
+            level: **level,
+            goods,
+            producing: is_producing,
+        });
+    }
+    let Some(first) = buildings.first() else {
+        return Err(BuildFailure::Gone);
+    };
+
+    if !action.goods.is_empty() {
+        let reach = STORAGE_REACH as f32 * tile_size.x.max(tile_size.y);
+        let start = helpers::geometry::global_coord_to_world_pos(&first.tile, &size, &tile_size);
+        let mut storages = storages_in_reach(
+            q_storages
+                .iter_mut()
+                .filter(|(entity, ..)| !entities.contains(entity))
+                .map(|(_, transform, storage)| (transform, storage)),
+            &start,
+            reach,
+        );
+
+        let space = storages.iter().map(|storage| storage.space()).sum::<u32>();
+        if space < action.goods.total() {
+            return Err(BuildFailure::Blocked);
+        }
+        let mut rest = action.goods.clone();
+        for storage in storages.iter_mut() {
+            rest = storage.store(&rest);
+        }
+    }
+
+    for entity in entities {
+        commands.entity(entity).despawn_recursive();
+    }
+    action.buildings = buildings;
+
+    Ok(())
+}
+
+/// Spawn a building as a child of its chunk, with a storage if its kind has one.
+fn spawn_building<'a>(
+    parent: &'a mut ChildBuilder,
+    building_kind: &BuildingKind,
+    order: BuildingOrder,
+    tile_coord: UVec2,
+    transform: Transform,
+    game_assets: &GameAssets,
+    production: &ProductionSettings,
+) -> EntityCommands<'a> {
+    let scene = game_assets.buildings[building_kind].clone();
+
+    let mut building = parent.spawn((
+        Building,
+        building_kind.clone(),
+        order,
+        BuildingLevel::default(),
+        BuildingWorkers::default(),
+        BuildingStaff::default(),
+        BuildingPriority::default(),
+        TileCoord(tile_coord),
+        Obstacle,
+        Vision(BUILDING_VISION),
+        Selectable(BUILDING_SELECT_RADIUS),
+        SceneBundle {
+            scene,
+            transform,
+            ..default()
+        },
+    ));
+    if let Some(capacity) = production.storage(building_kind) {
+        building.insert(Storage::new(capacity));
+    }
+    if !production.trains(building_kind).is_empty() {
+        building.insert(TrainingQueue::default());
+    }
+
+    building
+}
+
+/// Spawn the warehouse the player starts with, on the free land tile closest to the center of
+/// the map, once the chunk there is generated.
+pub fn spawn_starting_warehouse(
+    mut commands: Commands,
     chunk_manager: Res<ChunkManager>,
-    q_chunks: Query<(&Children, &TileMapping), With<ChunkCoord>>,
-    q_tiles: Query<&TileCoord, With<Obstacle>>,
-    mut q_tool: Query<(&mut BuildingToolValid, &Transform), With<BuildingTool>>,
-    resources: Res<ResourceCount>,
+    q_chunks: Query<(&TileMapping, &ResourceMapping), With<ChunkCoord>>,
+    game_assets: Res<GameAssets>,
+    production: Res<ProductionSettings>,
+    mut placed: ResMut<BuildingsPlaced>,
+    mut spawned: Local<bool>,
 ) {
-    let Ok((mut building_valid, tool_transform)) = q_tool.get_single_mut() else {
+    if *spawned {
+        return;
+    }
+    let Some(chunk) = chunk_manager.get(&IVec2::ZERO) else {
+        return;
+    };
+    let Ok((tiles, resources)) = q_chunks.get(*chunk) else {
         return;
     };
-    let point = tool_transform.translation;
 
     let size = chunk_manager.size();
     let tile_size = chunk_manager.tile_size();
-    let chunk_coord = helpers::geometry::world_pos_to_chunk_coord(&point.xz(), &size, &tile_size);
-    let tile_coord = helpers::geometry::world_pos_to_tile_coord(&point.xz(), &size, &tile_size);
+    let center = (size / 2).as_vec2();
+    let tile_coord = tiles
+        .iter()
+        .zip(resources.iter())
+        .enumerate()
+        .filter(|(_, (tile, resource))| {
+            !matches!(tile, TileKind::Water) && matches!(resource, ResourceKind::None)
+        })
+        .map(|(index, _)| helpers::geometry::index_to_tile_coord(index, &size))
+        .min_by(|a, b| {
+            a.as_vec2()
+                .distance(center)
+                .total_cmp(&b.as_vec2().distance(center))
+        })
+        .unwrap_or_else(|| {
+            warn!("No free land in the center chunk for the starting warehouse");
+            size / 2
+        });
+
+    let tile_pos = helpers::geometry::tile_coord_to_world_off(&tile_coord, &size, &tile_size);
+    let transform =
+        Transform::from_translation(tile_pos.extend(0.0).xzy()).with_scale(Vec3::splat(16.0));
+    let mut storage = Storage::new(
+        production
+            .storage(&BuildingKind::Warehouse)
+            .unwrap_or_default(),
+    );
+    storage.store(&STARTING_GOODS.iter().copied().collect());
+
+    let order = placed.next();
+    commands.entity(*chunk).with_children(|parent| {
+        // The starting warehouse was never paid for, so it can't be cancelled for a refund.
+        spawn_building(
+            parent,
+            &BuildingKind::Warehouse,
+            order,
+            tile_coord,
+            transform,
+            &game_assets,
+            &production,
+        )
+        .insert((storage, BuildingProducing));
+    });
+    *spawned = true;
+}
+
+pub fn update_stockpile(q_storages: Query<&Storage>, mut stockpile: ResMut<Stockpile>) {
+    let mut goods = Goods::default();
+    for storage in q_storages.iter() {
+        goods.add(&storage.goods);
+    }
+
+    **stockpile = goods;
+}
 
-    let Some(chunk) = chunk_manager.get(&chunk_coord) else {
+pub fn check_building_tool_valid(
+    ground: BuildGround,
+    mut q_tool: Query<(&mut BuildingToolValid, &Transform, &BuildingKind), With<BuildingTool>>,
+    q_storages: Query<(&GlobalTransform, &Storage)>,
+    production: Res<ProductionSettings>,
+    roads: Res<Roads>,
+) {
+    let Ok((mut building_valid, tool_transform, building_kind)) = q_tool.get_single_mut() else {
         return;
     };
-    let Ok((children, mapping)) = q_chunks.get(*chunk) else {
+    let point = tool_transform.translation;
+
+    let size = ground.chunk_manager.size();
+    let tile_size = ground.chunk_manager.tile_size();
+    let global_coord = helpers::geometry::world_pos_to_global_coord(&point.xz(), &size, &tile_size);
+    let reachable = reachable_goods(&q_storages, &point.xz(), &tile_size);
+
+    let placement = production.placement(building_kind);
+    let is_buildable = ground.is_buildable(&global_coord, placement, &roads);
+    let has_resources = reachable.contains(&production.cost(building_kind));
+
+    **building_valid = is_buildable && has_resources;
+}
+
+/// Check every ghost of the drag on its own, paying for them in order: a ghost is only valid if
+/// the storages that pay for the drag, the ones in reach of its first buildable tile, can pay for
+/// it and the valid ones before it.
+pub fn check_building_drag_valid(
+    ground: BuildGround,
+    mut q_tool: Query<(&BuildingKind, &mut BuildingToolDrag), With<BuildingTool>>,
+    q_storages: Query<(&GlobalTransform, &Storage)>,
+    production: Res<ProductionSettings>,
+    roads: Res<Roads>,
+) {
+    let Ok((building_kind, mut drag)) = q_tool.get_single_mut() else {
         return;
     };
+    if drag.start.is_none() {
+        return;
+    }
+
+    let size = ground.chunk_manager.size();
+    let tile_size = ground.chunk_manager.tile_size();
+    let cost = production.cost(building_kind);
 
-    let obstacles = children
-        .into_iter()
-        .filter_map(|child| q_tiles.get(*child).ok().map(|x| **x))
-        .collect::<HashSet<UVec2>>();
+    let placement = production.placement(building_kind);
 
-    let index = helpers::geometry::tile_coord_to_index(&tile_coord, &size);
-    let tile_kind = mapping[index];
+    let mut reachable = None;
+    let mut total = Goods::default();
+    let mut valid = HashSet::new();
+    for tile in drag.tiles.iter() {
+        if !ground.is_buildable(tile, placement, &roads) {
+            continue;
+        }
+        let reachable = reachable.get_or_insert_with(|| {
+            let start = helpers::geometry::global_coord_to_world_pos(tile, &size, &tile_size);
+            reachable_goods(&q_storages, &start, &tile_size)
+        });
 
-    let is_water = matches!(tile_kind, TileKind::Water);
-    let is_blocked = obstacles.contains(&tile_coord);
-    let has_resources = **resources >= BUILDING_COST;
+        total.add(&cost);
+        if !reachable.contains(&total) {
+            break;
+        }
+        valid.insert(*tile);
+    }
+
+    // The ghosts are respawned when the tiles change, not when their validity does.
+    drag.bypass_change_detection().valid = valid;
+}
+
+/// The goods in all the storages close enough to a point to pay for a building there.
+fn reachable_goods(
+    q_storages: &Query<(&GlobalTransform, &Storage)>,
+    point: &Vec2,
+    tile_size: &Vec2,
+) -> Goods {
+    let reach = STORAGE_REACH as f32 * tile_size.x.max(tile_size.y);
+    let mut reachable = Goods::default();
+    for (transform, storage) in q_storages.iter() {
+        if transform.translation().xz().distance(*point) <= reach {
+            reachable.add(&storage.goods);
+        }
+    }
 
-    **building_valid = !is_blocked && !is_water && has_resources;
+    reachable
 }
 
+/// The buildings working, with the workers they sent out.
+type GatheringBuildings<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static GlobalTransform,
+        &'static BuildingKind,
+        &'static BuildingLevel,
+        &'static mut BuildingWorkers,
+        Has<BuildingIdle>,
+    ),
+    (With<Building>, Without<BuildingUpgrading>),
+>;
+
+/// Gathering buildings post a job for every resource in range their level has workers for. The
+/// workers of the jobs bring the goods to the storage with space closest to the resource.
 pub fn building_increase_resource_count(
     mut commands: Commands,
-    chunk_manager: Res<ChunkManager>,
-    q_buildings: Query<(Entity, &GlobalTransform, &BuildingKind), (With<Building>, Without<BuildingHasWorker>)>,
-    q_chunks: Query<&Children, With<ChunkCoord>>,
-    q_resources: Query<(Entity, &GlobalTransform, &ResourceKind)>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    work: BuildingWork,
+    ground: BuildGround,
+    mut q_buildings: GatheringBuildings,
+    q_resources: Query<&GlobalTransform, (With<ResourceKind>, Without<ResourceClaimed>)>,
+    q_storages: Query<(&GlobalTransform, &Storage)>,
+    mut alerts: EventWriter<Alert>,
 ) {
-    for (building, building_transform, building_kind) in q_buildings.iter() {
+    let production = &work.production;
+    // The resources claimed this frame, before the commands claiming them are applied.
+    let mut claimed = HashSet::new();
+
+    for (building, building_transform, building_kind, level, mut workers, is_idle) in
+        q_buildings.iter_mut()
+    {
+        let Some(rules) = production.get(building_kind) else {
+            continue;
+        };
+        let Some(level) = production.level(building_kind, **level) else {
+            continue;
+        };
+        if **workers >= level.workers {
+            continue;
+        }
+        let rules = ProductionRules {
+            gather_time: rules.gather_time / (level.speed * work.population.work_speed()),
+            ..rules.clone()
+        };
         let point = building_transform.translation().xz();
 
-        let size = chunk_manager.size();
-        let tile_size = chunk_manager.tile_size();
-        let chunk_coords = helpers::geometry::world_area_to_chunk_coords(
-            &point,
-            BUILDING_RADIUS,
-            &size,
-            &tile_size,
-        );
+        let size = ground.chunk_manager.size();
+        let tile_size = ground.chunk_manager.tile_size();
+        let global_coord = helpers::geometry::world_pos_to_global_coord(&point, &size, &tile_size);
+
+        let closest = ground
+            .index
+            .nearest_resource(&rules.resource, &global_coord, level.radius, |entity| {
+                q_resources.contains(entity) && !claimed.contains(&entity)
+            })
+            .and_then(|(entity, coord)| {
+                let position = q_resources.get(entity).ok()?.translation().xz();
+                let tile = ground.tile_kind(&coord)?;
+                Some((entity, position, tile))
+            });
 
-        let chunks = chunk_coords
+        // A building with jobs left is not idle until they are all done.
+        let Some((closest, position, tile)) = closest else {
+            if !is_idle && **workers == 0 {
+                commands
+                    .entity(building)
+                    .insert((BuildingIdle, BuildingThroughput(0.0)));
+                alerts.send(Alert {
+                    kind: AlertKind::BuildingIdle,
+                    position: Some(point),
+                });
+            }
+            continue;
+        };
+
+        let storage = q_storages
             .iter()
-            .filter_map(|coord| chunk_manager.get(coord));
-
-        if let Some((closest, position, distance)) = chunks
-            .filter_map(|chunk| q_chunks.get(*chunk).ok())
-            .flatten()
-            .filter_map(|child| q_resources.get(*child).ok())
-            .filter(|(_, _, kind)| **kind == building_kind.clone().into())
-            .map(|(entity, transform, _)| (entity, transform.translation().xz()))
-            .map(|(entity, pos)| (entity, pos, pos.distance(point)))
-            .min_by_key(|(_, _, dist)| *dist as i32)
-        {
-            if distance < BUILDING_RADIUS as f32 * tile_size.x.max(tile_size.y) {
-                commands.entity(building).insert(BuildingHasWorker);
-
-                commands.spawn((
-                    Unit,
-                    UnitVelocity(16.0),
-                    UnitWaypoints(VecDeque::from(vec![
-                        (position, vec![UnitWaypointAction::Gather(closest)]),
-                        (point, vec![UnitWaypointAction::Deposit, UnitWaypointAction::Release(building)]),
-                    ])),
-                    MaterialMeshBundle {
-                        mesh: meshes.add(Capsule3d::new(0.5, 1.0)),
-                        material: materials.add(StandardMaterial {
-                            base_color: Color::WHITE,
-                            unlit: true,
-                            ..default()
-                        }),
-                        transform: Transform::from_translation(point.extend(2.0).xzy()).with_scale(Vec3::splat(2.0)),
-                        ..default()
-                    },
-                ));
+            .filter(|(_, storage)| storage.space() > 0)
+            .map(|(transform, _)| transform.translation().xz())
+            .min_by(|a, b| a.distance(position).total_cmp(&b.distance(position)));
+        let Some(storage_position) = storage else {
+            if !is_idle && **workers == 0 {
+                commands
+                    .entity(building)
+                    .insert((BuildingIdle, BuildingThroughput(0.0)));
+                alerts.send(Alert {
+                    kind: AlertKind::StorageFull,
+                    position: Some(point),
+                });
+            }
+            continue;
+        };
+
+        // The trip is estimated as if a worker of the building started from it.
+        let trip = point.distance(position)
+            + position.distance(storage_position)
+            + storage_position.distance(point);
+        **workers += 1;
+        claimed.insert(closest);
+        commands.entity(closest).insert(ResourceClaimed);
+        commands.entity(building).remove::<BuildingIdle>().insert((
+            BuildingProducing,
+            BuildingThroughput(rules.throughput(&tile, trip, UNIT_SPEED) * level.workers as f32),
+        ));
+
+        commands.spawn((
+            Job {
+                kind: JobKind::Gather {
+                    resource: closest,
+                    goods: Goods::from_iter([(rules.output.clone(), rules.trip_yield(&tile))]),
+                    work_time: rules.gather_time,
+                },
+                building,
+                position,
+            },
+            JobState::Open,
+        ));
+    }
+}
+
+/// The buildings working, with their staff and the batch they are processing.
+type ProcessingBuildings<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static GlobalTransform,
+        &'static BuildingKind,
+        &'static BuildingLevel,
+        &'static BuildingStaff,
+        Option<&'static mut BuildingProcessing>,
+        Has<BuildingIdle>,
+    ),
+    (With<Building>, Without<BuildingUpgrading>),
+>;
+
+pub fn update_processing_buildings(
+    mut commands: Commands,
+    work: BuildingWork,
+    mut q_buildings: ProcessingBuildings,
+    mut storages: StoragesInReach,
+    mut alerts: EventWriter<Alert>,
+    time: Res<Time>,
+) {
+    for (building, transform, building_kind, level, staff, processing, is_idle) in
+        q_buildings.iter_mut()
+    {
+        let Some(recipe) = work.production.recipe(building_kind) else {
+            continue;
+        };
+        let speed = work.speed(building_kind, **level, **staff);
+        let point = transform.translation().xz();
+        let mut storages = storages.near(&point);
+
+        if let Some(mut processing) = processing {
+            // The outputs wait in the building until there is room for all of them in reach.
+            let space = storages.iter().map(|storage| storage.space()).sum::<u32>();
+            if processing.tick(time.delta().mul_f32(speed)).finished()
+                && space >= recipe.outputs.total()
+            {
+                let mut rest = recipe.outputs.clone();
+                for storage in storages.iter_mut() {
+                    rest = storage.store(&rest);
+                }
+                commands.entity(building).remove::<BuildingProcessing>();
+            }
+        } else if **staff == 0 {
+            continue;
+        } else if take_from_storages(&mut storages, &recipe.inputs) {
+            commands.entity(building).remove::<BuildingIdle>().insert((
+                BuildingProducing,
+                BuildingProcessing(Timer::from_seconds(recipe.time, TimerMode::Once)),
+                BuildingThroughput(recipe.throughput() * speed),
+            ));
+        } else if !is_idle {
+            commands
+                .entity(building)
+                .insert((BuildingIdle, BuildingThroughput(0.0)));
+            alerts.send(Alert {
+                kind: AlertKind::BuildingIdle,
+                position: Some(point),
+            });
+        }
+    }
+}
+
+/// Start upgrading buildings to their next level, if they have one and the storages in reach can
+/// pay for it.
+pub fn start_building_upgrades(
+    mut commands: Commands,
+    mut upgrades: EventReader<UpgradeBuilding>,
+    q_buildings: Query<
+        (
+            &GlobalTransform,
+            &BuildingKind,
+            &BuildingLevel,
+            Has<BuildingUpgrading>,
+        ),
+        With<Building>,
+    >,
+    mut q_storages: Query<(&GlobalTransform, &mut Storage)>,
+    chunk_manager: Res<ChunkManager>,
+    production: Res<ProductionSettings>,
+) {
+    let tile_size = chunk_manager.tile_size();
+    let reach = STORAGE_REACH as f32 * tile_size.x.max(tile_size.y);
+
+    for UpgradeBuilding(building) in upgrades.read() {
+        let Ok((transform, kind, level, is_upgrading)) = q_buildings.get(*building) else {
+            continue;
+        };
+        if is_upgrading {
+            continue;
+        }
+        let Some(next) = production.level(kind, **level + 1) else {
+            continue;
+        };
+
+        let point = transform.translation().xz();
+        let mut storages = storages_in_reach(q_storages.iter_mut(), &point, reach);
+        if !take_from_storages(&mut storages, &next.cost) {
+            warn!(
+                "Not enough goods to upgrade the {}",
+                kind.name().to_lowercase()
+            );
+            continue;
+        }
+
+        // The upgrade was paid for, so undoing the placement would no longer refund everything.
+        commands.entity(*building).insert((
+            BuildingProducing,
+            BuildingUpgrading(Timer::from_seconds(next.time, TimerMode::Once)),
+        ));
+    }
+}
+
+/// Build the upgrades that have a worker on site, and finish the ones whose time is up, which
+/// raises the level and grows the model.
+pub fn update_building_upgrades(
+    mut commands: Commands,
+    mut q_buildings: Query<
+        (
+            Entity,
+            &BuildingKind,
+            &mut BuildingLevel,
+            &mut BuildingUpgrading,
+            &mut Transform,
+        ),
+        With<BuildingBuilder>,
+    >,
+    production: Res<ProductionSettings>,
+    time: Res<Time>,
+) {
+    for (building, kind, mut level, mut upgrading, mut transform) in q_buildings.iter_mut() {
+        if !upgrading.tick(time.delta()).finished() {
+            continue;
+        }
+
+        **level += 1;
+        let scale = production.level(kind, **level).unwrap_or_default().scale;
+        transform.scale = Vec3::splat(16.0 * scale);
+        commands
+            .entity(building)
+            .remove::<(BuildingUpgrading, BuildingBuilder)>();
+    }
+}
+
+pub fn change_building_priorities(
+    mut changes: EventReader<ChangeBuildingPriority>,
+    mut q_buildings: Query<&mut BuildingPriority, With<Building>>,
+) {
+    for ChangeBuildingPriority(building) in changes.read() {
+        if let Ok(mut priority) = q_buildings.get_mut(*building) {
+            *priority = priority.next();
+        }
+    }
+}
+
+/// The buildings working, with their staff and the crop they grow.
+type Farms<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static GlobalTransform,
+        &'static BuildingKind,
+        &'static BuildingLevel,
+        &'static BuildingStaff,
+        Option<&'static mut FarmCrop>,
+        Has<BuildingIdle>,
+    ),
+    (With<Building>, Without<BuildingUpgrading>),
+>;
+
+/// Farms sow the fertile tiles around them, wait for the crop to grow and harvest it into the
+/// storages in reach. The more fertile tiles, the bigger the harvest.
+pub fn update_farms(
+    mut commands: Commands,
+    work: BuildingWork,
+    ground: BuildGround,
+    mut q_buildings: Farms,
+    mut storages: StoragesInReach,
+    mut alerts: EventWriter<Alert>,
+    time: Res<Time>,
+) {
+    let size = ground.chunk_manager.size();
+    let tile_size = ground.chunk_manager.tile_size();
+
+    for (building, transform, building_kind, level, staff, crop, is_idle) in q_buildings.iter_mut()
+    {
+        let Some(rules) = work.production.farm(building_kind) else {
+            continue;
+        };
+        let point = transform.translation().xz();
+        let global_coord = helpers::geometry::world_pos_to_global_coord(&point, &size, &tile_size);
+        let fields = || rules.fields(&global_coord, |coord| ground.tile_kind(coord));
+        let speed = work.speed(building_kind, **level, **staff);
+
+        let Some(mut crop) = crop else {
+            if **staff == 0 {
+                continue;
+            }
+
+            let fields = fields();
+            if fields == 0 {
+                if !is_idle {
+                    commands
+                        .entity(building)
+                        .insert((BuildingIdle, BuildingThroughput(0.0)));
+                    alerts.send(Alert {
+                        kind: AlertKind::BuildingIdle,
+                        position: Some(point),
+                    });
+                }
+                continue;
+            }
+
+            commands.entity(building).remove::<BuildingIdle>().insert((
+                BuildingProducing,
+                FarmCrop {
+                    stage: CropStage::Sowing,
+                    timer: Timer::from_seconds(rules.work_time, TimerMode::Once),
+                },
+                BuildingThroughput(rules.throughput(fields) * speed),
+            ));
+            continue;
+        };
+
+        // The crop grows on its own, but sowing and harvesting go at the pace of the staff.
+        let delta = match crop.stage {
+            CropStage::Growing => time.delta(),
+            CropStage::Sowing | CropStage::Harvesting => time.delta().mul_f32(speed),
+        };
+        if !crop.timer.tick(delta).finished() {
+            continue;
+        }
+
+        match crop.stage {
+            CropStage::Sowing => {
+                *crop = FarmCrop {
+                    stage: CropStage::Growing,
+                    timer: Timer::from_seconds(rules.growth_time, TimerMode::Once),
+                };
+            }
+            CropStage::Growing => {
+                *crop = FarmCrop {
+                    stage: CropStage::Harvesting,
+                    timer: Timer::from_seconds(rules.work_time, TimerMode::Once),
+                };
+            }
+            CropStage::Harvesting => {
+                // The harvest waits on the fields until there is room for all of it in reach.
+                let harvest = Goods::from_iter([(rules.output.clone(), rules.harvest(fields()))]);
+                let mut storages = storages.near(&point);
+                let space = storages.iter().map(|storage| storage.space()).sum::<u32>();
+                if space >= harvest.total() {
+                    let mut rest = harvest;
+                    for storage in storages.iter_mut() {
+                        rest = storage.store(&rest);
+                    }
+                    commands.entity(building).remove::<FarmCrop>();
+                }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        goods::Good,
+        helpers::testing::{advance, spawn_storage, stored, TestApp},
+        spatial::SpatialIndex,
+    };
+
+    /// A headless app carrying out the build commands of the player on a chunk of explored grass,
+    /// next to a warehouse with the logs to pay for a lumber mill.
+    fn build_app() -> (App, Entity) {
+        let mut app = TestApp::default()
+            .grass()
+            .explored(IVec2::ZERO, 8)
+            .game_assets(&[BuildingKind::LumberMill])
+            .plugins(TransformPlugin)
+            .event::<BuildCommand>()
+            .event::<UpgradeBuilding>()
+            .event::<ChangeBuildingPriority>()
+            .init_resource::<ButtonInput<KeyCode>>()
+            .init_resource::<BuildHistory>()
+            .init_resource::<BuildingsPlaced>()
+            .init_resource::<SpatialIndex>()
+            .init_resource::<Roads>()
+            .systems((send_build_commands, handle_build_commands).chain())
+            .build();
+        let storage = spawn_storage(&mut app, Vec2::ZERO, 100, &Goods::from_iter([("logs", 20)]));
+
+        (app, storage)
+    }
+
+    fn place(app: &mut App, tile: IVec2) -> Entity {
+        app.world.send_event(BuildCommand::Place(PlaceBuildings {
+            kind: BuildingKind::LumberMill,
+            rotation: Quat::IDENTITY,
+            tiles: vec![tile],
+        }));
+        app.update();
+
+        the_building(app)
+    }
+
+    fn the_building(app: &mut App) -> Entity {
+        app.world
+            .query_filtered::<Entity, With<Building>>()
+            .single(&app.world)
+    }
+
+    /// Select the building and press Delete.
+    fn delete(app: &mut App, building: Entity) {
+        app.world.entity_mut(building).insert(Selected);
+        app.world
+            .resource_mut::<ButtonInput<KeyCode>>()
+            .press(KeyCode::Delete);
+        app.update();
+        let mut input = app.world.resource_mut::<ButtonInput<KeyCode>>();
+        input.release(KeyCode::Delete);
+        input.clear();
+    }
+
+    #[test]
+    fn test_undo_demolish_keeps_producing() {
+        let (mut app, storage) = build_app();
+        let building = place(&mut app, IVec2::new(2, 0));
+        assert_eq!(stored(&app, storage), Goods::from_iter([("logs", 15)]));
+
+        app.world.entity_mut(building).insert(BuildingProducing);
+        delete(&mut app, building);
+        assert_eq!(
+            app.world
+                .query_filtered::<(), With<Building>>()
+                .iter(&app.world)
+                .count(),
+            0
+        );
+
+        // The building comes back for free, but it already produced, so deleting it again
+        // demolishes it instead of refunding its cost.
+        app.world.send_event(BuildCommand::Undo);
+        app.update();
+        let building = the_building(&mut app);
+        assert!(app.world.get::<BuildingProducing>(building).is_some());
+
+        delete(&mut app, building);
+        assert_eq!(
+            app.world
+                .query_filtered::<(), With<Building>>()
+                .iter(&app.world)
+                .count(),
+            0
+        );
+        assert_eq!(stored(&app, storage), Goods::from_iter([("logs", 15)]));
+    }
+
+    #[test]
+    fn test_undo_blocked_is_kept() {
+        let (mut app, storage) = build_app();
+        let building = place(&mut app, IVec2::new(2, 0));
+
+        // A producing building can't be taken back, but the undo stays for when it can.
+        app.world.entity_mut(building).insert(BuildingProducing);
+        app.world.send_event(BuildCommand::Undo);
+        app.update();
+        assert_eq!(the_building(&mut app), building);
+
+        app.world.entity_mut(building).remove::<BuildingProducing>();
+        app.world.send_event(BuildCommand::Undo);
+        app.update();
+        assert_eq!(
+            app.world
+                .query_filtered::<(), With<Building>>()
+                .iter(&app.world)
+                .count(),
+            0
+        );
+        assert_eq!(stored(&app, storage), Goods::from_iter([("logs", 20)]));
+    }
+
+    #[test]
+    fn test_cancel_refunds_level() {
+        let (mut app, storage) = build_app();
+        let building = place(&mut app, IVec2::new(2, 0));
+        app.world.entity_mut(building).insert(BuildingLevel(2));
+
+        delete(&mut app, building);
+
+        let paid = ProductionSettings::default().paid(&BuildingKind::LumberMill, 2);
+        let mut refunded = Goods::from_iter([("logs", 15)]);
+        refunded.add(&paid);
+        assert_eq!(stored(&app, storage), refunded);
+    }
+
+    /// A headless app processing the goods of a sawmill next to a warehouse with the logs for one
+    /// batch.
+    fn processing_app(level: u32, staff: u32) -> (App, Entity) {
+        let mut app = TestApp::default()
+            .systems(update_processing_buildings)
+            .build();
+        app.world.spawn((
+            Building,
+            BuildingKind::Sawmill,
+            BuildingLevel(level),
+            BuildingStaff(staff),
+            GlobalTransform::default(),
+        ));
+        let storage = spawn_storage(&mut app, Vec2::ZERO, 100, &Goods::from_iter([("logs", 2)]));
+        app.update();
+
+        (app, storage)
+    }
+
+    fn planks(app: &App, storage: Entity) -> u32 {
+        stored(app, storage).get(&Good::new("planks"))
+    }
+
+    #[test]
+    fn test_processing_speed() {
+        // A batch of planks takes 6 seconds, and the level 2 staff of two works three times as
+        // fast as the single citizen of the first level.
+        let (mut app, storage) = processing_app(2, 2);
+        advance(&mut app, 1.9);
+        assert_eq!(planks(&app, storage), 0);
+        advance(&mut app, 0.2);
+        assert_eq!(planks(&app, storage), 1);
+
+        // Work stops while the staff is away, but keeps what it did.
+        let (mut app, storage) = processing_app(1, 1);
+        advance(&mut app, 3.0);
+        let mut q_staff = app.world.query::<&mut BuildingStaff>();
+        **q_staff.single_mut(&mut app.world) = 0;
+        advance(&mut app, 10.0);
+        assert_eq!(planks(&app, storage), 0);
+        **q_staff.single_mut(&mut app.world) = 1;
+        advance(&mut app, 3.1);
+        assert_eq!(planks(&app, storage), 1);
+    }
+}
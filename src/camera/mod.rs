@@ -1,17 +1,380 @@
-use bevy::prelude::*;
-use bevy_panorbit_camera::PanOrbitCamera;
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+};
+
+use crate::{
+    core::{Alert, CursorActive},
+    fog::FogOfWar,
+    helpers,
+    terrain::ChunkManager,
+};
+
+/// A top down camera that looks at a point on the ground.
+///
+/// The `target_*` fields are what the input changes; the camera moves towards them smoothly.
+#[derive(Component, Debug, Clone)]
+pub struct RtsCamera {
+    pub focus: Vec3,
+    pub yaw: f32,
+    pub zoom: f32,
+    pub target_focus: Vec3,
+    pub target_yaw: f32,
+    pub target_zoom: f32,
+}
+
+impl Default for RtsCamera {
+    fn default() -> Self {
+        Self {
+            focus: Vec3::ZERO,
+            yaw: 0.0,
+            zoom: 0.5,
+            target_focus: Vec3::ZERO,
+            target_yaw: 0.0,
+            target_zoom: 0.5,
+        }
+    }
+}
+
+/// Move the camera so it looks at a point on the ground.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct FocusCamera(pub Vec2);
+
+/// A saved camera position that can be jumped back to.
+#[derive(Debug, Clone, Copy)]
+pub struct CameraBookmark {
+    pub focus: Vec3,
+    pub yaw: f32,
+    pub zoom: f32,
+}
+
+/// The saved camera positions, one slot per key in `CameraBindings::bookmarks`.
+#[derive(Resource, Debug, Default, Deref, DerefMut)]
+pub struct CameraBookmarks(pub Vec<Option<CameraBookmark>>);
+
+/// The position of the last alert that happened somewhere on the map.
+#[derive(Resource, Debug, Default, Deref, DerefMut)]
+pub struct LastAlertPosition(pub Option<Vec2>);
+
+#[derive(Resource, Debug, Clone)]
+pub struct RtsCameraSettings {
+    /// Panning speed in world units per second, at the lowest zoom level.
+    pub pan_speed: f32,
+    /// How close to the edge of the window, in pixels, the cursor has to be to pan.
+    pub edge_margin: f32,
+    pub min_height: f32,
+    pub max_height: f32,
+    /// The pitch, in radians below the horizon, when zoomed in all the way.
+    pub min_pitch: f32,
+    /// The pitch, in radians below the horizon, when zoomed out all the way.
+    pub max_pitch: f32,
+    /// How much one scroll line changes the zoom, in the `0..1` range.
+    pub zoom_speed: f32,
+    /// Rotation speed in radians per second.
+    pub rotate_speed: f32,
+    /// How fast the camera catches up with its target; higher is snappier.
+    pub smoothing: f32,
+    /// How far, in world units, the camera can look past the explored area.
+    pub bounds_margin: f32,
+}
+
+impl Default for RtsCameraSettings {
+    fn default() -> Self {
+        Self {
+            pan_speed: 200.0,
+            edge_margin: 8.0,
+            min_height: 40.0,
+            max_height: 600.0,
+            min_pitch: 0.6,
+            max_pitch: 1.2,
+            zoom_speed: 0.08,
+            rotate_speed: 1.5,
+            smoothing: 12.0,
+            bounds_margin: 64.0,
+        }
+    }
+}
+
+/// The keys used to control the camera. Every action can be bound to more than one key.
+#[derive(Resource, Debug, Clone)]
+pub struct CameraBindings {
+    pub pan_forward: Vec<KeyCode>,
+    pub pan_back: Vec<KeyCode>,
+    pub pan_left: Vec<KeyCode>,
+    pub pan_right: Vec<KeyCode>,
+    pub rotate_left: Vec<KeyCode>,
+    pub rotate_right: Vec<KeyCode>,
+    /// Jump to a bookmark, or save it while a modifier is held.
+    pub bookmarks: Vec<KeyCode>,
+    pub bookmark_save_modifier: Vec<KeyCode>,
+    pub jump_to_alert: Vec<KeyCode>,
+}
+
+impl Default for CameraBindings {
+    fn default() -> Self {
+        Self {
+            pan_forward: vec![KeyCode::KeyW, KeyCode::ArrowUp],
+            pan_back: vec![KeyCode::KeyS, KeyCode::ArrowDown],
+            pan_left: vec![KeyCode::KeyA, KeyCode::ArrowLeft],
+            pan_right: vec![KeyCode::KeyD, KeyCode::ArrowRight],
+            rotate_left: vec![KeyCode::KeyQ],
+            rotate_right: vec![KeyCode::KeyE],
+            bookmarks: vec![KeyCode::F1, KeyCode::F2, KeyCode::F3, KeyCode::F4],
+            bookmark_save_modifier: vec![KeyCode::ControlLeft, KeyCode::ControlRight],
+            jump_to_alert: vec![KeyCode::Space],
+        }
+    }
+}
 
 pub struct CameraPlugin;
 
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, hack_panorbit_camera);
+        app.init_resource::<RtsCameraSettings>()
+            .init_resource::<CameraBindings>()
+            .init_resource::<CameraBookmarks>()
+            .init_resource::<LastAlertPosition>()
+            .add_event::<FocusCamera>()
+            .add_systems(
+                Update,
+                (
+                    pan_camera,
+                    zoom_camera,
+                    rotate_camera,
+                    handle_camera_bookmarks,
+                    track_last_alert,
+                    jump_to_last_alert,
+                    focus_camera,
+                    clamp_camera_to_explored,
+                    update_camera_transform,
+                )
+                    .chain(),
+            );
     }
 }
 
-// TODO: this is a hack. stop being a pussy and actually do a proper camera setup
-pub fn hack_panorbit_camera(mut q_camera: Query<&mut PanOrbitCamera>) {
+fn pan_camera(
+    mut q_camera: Query<&mut RtsCamera>,
+    windows: Query<&Window>,
+    input: Res<ButtonInput<KeyCode>>,
+    bindings: Res<CameraBindings>,
+    cursor_active: Res<CursorActive>,
+    settings: Res<RtsCameraSettings>,
+    time: Res<Time>,
+) {
+    let pressed = |keys: &Vec<KeyCode>| input.any_pressed(keys.iter().copied());
+
+    let mut direction = Vec2::ZERO;
+    if pressed(&bindings.pan_forward) {
+        direction.y -= 1.0;
+    }
+    if pressed(&bindings.pan_back) {
+        direction.y += 1.0;
+    }
+    if pressed(&bindings.pan_left) {
+        direction.x -= 1.0;
+    }
+    if pressed(&bindings.pan_right) {
+        direction.x += 1.0;
+    }
+
+    // The edges only pan while the cursor is over the map, not over the panels along them.
+    let window = windows.get_single().ok().filter(|_| **cursor_active);
+    if let Some(window) = window {
+        if let Some(cursor) = window.cursor_position() {
+            if cursor.x < settings.edge_margin {
+                direction.x -= 1.0;
+            } else if cursor.x > window.width() - settings.edge_margin {
+                direction.x += 1.0;
+            }
+            if cursor.y < settings.edge_margin {
+                direction.y -= 1.0;
+            } else if cursor.y > window.height() - settings.edge_margin {
+                direction.y += 1.0;
+            }
+        }
+    }
+
+    if direction == Vec2::ZERO {
+        return;
+    }
+
     for mut camera in q_camera.iter_mut() {
-        camera.target_focus.y = 0.0;
+        // Move faster when zoomed out, so the speed on screen stays about the same.
+        let speed = settings.pan_speed * (1.0 + camera.target_zoom * 4.0);
+        let rotation = Quat::from_rotation_y(camera.target_yaw);
+        let delta = rotation * direction.normalize().extend(0.0).xzy();
+
+        camera.target_focus += delta * speed * time.delta_seconds();
+    }
+}
+
+fn zoom_camera(
+    mut q_camera: Query<&mut RtsCamera>,
+    mut scroll_events: EventReader<MouseWheel>,
+    cursor_active: Res<CursorActive>,
+    settings: Res<RtsCameraSettings>,
+) {
+    let scroll = scroll_events
+        .read()
+        .map(|event| match event.unit {
+            MouseScrollUnit::Line => event.y,
+            MouseScrollUnit::Pixel => event.y / 100.0,
+        })
+        .sum::<f32>();
+
+    if scroll == 0.0 || !**cursor_active {
+        return;
+    }
+
+    for mut camera in q_camera.iter_mut() {
+        camera.target_zoom = (camera.target_zoom - scroll * settings.zoom_speed).clamp(0.0, 1.0);
+    }
+}
+
+fn rotate_camera(
+    mut q_camera: Query<&mut RtsCamera>,
+    input: Res<ButtonInput<KeyCode>>,
+    bindings: Res<CameraBindings>,
+    settings: Res<RtsCameraSettings>,
+    time: Res<Time>,
+) {
+    let pressed = |keys: &Vec<KeyCode>| input.any_pressed(keys.iter().copied());
+
+    let mut direction = 0.0;
+    if pressed(&bindings.rotate_left) {
+        direction += 1.0;
+    }
+    if pressed(&bindings.rotate_right) {
+        direction -= 1.0;
+    }
+
+    if direction == 0.0 {
+        return;
+    }
+
+    for mut camera in q_camera.iter_mut() {
+        camera.target_yaw += direction * settings.rotate_speed * time.delta_seconds();
+    }
+}
+
+fn handle_camera_bookmarks(
+    mut q_camera: Query<&mut RtsCamera>,
+    input: Res<ButtonInput<KeyCode>>,
+    bindings: Res<CameraBindings>,
+    mut bookmarks: ResMut<CameraBookmarks>,
+) {
+    let Ok(mut camera) = q_camera.get_single_mut() else {
+        return;
+    };
+
+    let Some(slot) = bindings
+        .bookmarks
+        .iter()
+        .position(|key| input.just_pressed(*key))
+    else {
+        return;
+    };
+
+    if bookmarks.len() < bindings.bookmarks.len() {
+        bookmarks.resize(bindings.bookmarks.len(), None);
+    }
+
+    if input.any_pressed(bindings.bookmark_save_modifier.iter().copied()) {
+        bookmarks[slot] = Some(CameraBookmark {
+            focus: camera.target_focus,
+            yaw: camera.target_yaw,
+            zoom: camera.target_zoom,
+        });
+    } else if let Some(bookmark) = bookmarks[slot] {
+        camera.target_focus = bookmark.focus;
+        camera.target_yaw = bookmark.yaw;
+        camera.target_zoom = bookmark.zoom;
+    }
+}
+
+fn track_last_alert(mut alerts: EventReader<Alert>, mut last_alert: ResMut<LastAlertPosition>) {
+    if let Some(position) = alerts.read().filter_map(|alert| alert.position).last() {
+        **last_alert = Some(position);
+    }
+}
+
+fn jump_to_last_alert(
+    input: Res<ButtonInput<KeyCode>>,
+    bindings: Res<CameraBindings>,
+    last_alert: Res<LastAlertPosition>,
+    mut focus_events: EventWriter<FocusCamera>,
+) {
+    if !input.any_just_pressed(bindings.jump_to_alert.iter().copied()) {
+        return;
+    }
+
+    if let Some(position) = **last_alert {
+        focus_events.send(FocusCamera(position));
+    }
+}
+
+fn focus_camera(mut q_camera: Query<&mut RtsCamera>, mut focus_events: EventReader<FocusCamera>) {
+    let Some(FocusCamera(position)) = focus_events.read().last() else {
+        return;
+    };
+
+    for mut camera in q_camera.iter_mut() {
+        camera.target_focus = position.extend(0.0).xzy();
+    }
+}
+
+fn clamp_camera_to_explored(
+    mut q_camera: Query<&mut RtsCamera>,
+    fog: Res<FogOfWar>,
+    chunk_manager: Res<ChunkManager>,
+    settings: Res<RtsCameraSettings>,
+) {
+    let size = chunk_manager.size();
+    let tile_size = chunk_manager.tile_size();
+
+    let (min, max) = match fog.explored_bounds() {
+        Some(bounds) => (
+            helpers::geometry::global_coord_to_world_pos(&bounds.min, &size, &tile_size),
+            helpers::geometry::global_coord_to_world_pos(&bounds.max, &size, &tile_size),
+        ),
+        None => (Vec2::ZERO, Vec2::ZERO),
+    };
+    let min = min - settings.bounds_margin;
+    let max = max + settings.bounds_margin;
+
+    for mut camera in q_camera.iter_mut() {
+        let focus = camera.target_focus.xz().clamp(min, max);
+        if focus != camera.target_focus.xz() {
+            camera.target_focus = focus.extend(0.0).xzy();
+        }
+    }
+}
+
+fn update_camera_transform(
+    mut q_camera: Query<(&mut RtsCamera, &mut Transform)>,
+    settings: Res<RtsCameraSettings>,
+    time: Res<Time>,
+) {
+    let t = 1.0 - (-settings.smoothing * time.delta_seconds()).exp();
+
+    for (mut camera, mut transform) in q_camera.iter_mut() {
+        camera.focus = camera.focus.lerp(camera.target_focus, t);
+        camera.yaw += (camera.target_yaw - camera.yaw) * t;
+        camera.zoom += (camera.target_zoom - camera.zoom) * t;
+
+        let height =
+            settings.min_height + (settings.max_height - settings.min_height) * camera.zoom;
+        let pitch = settings.min_pitch + (settings.max_pitch - settings.min_pitch) * camera.zoom;
+        let pitch = pitch.clamp(0.1, FRAC_PI_2 - 0.01);
+
+        // The camera sits behind the focus point (towards +z before the yaw is applied).
+        let back = height / pitch.tan();
+        let offset = Quat::from_rotation_y(camera.yaw) * Vec3::new(0.0, height, back);
+
+        *transform =
+            Transform::from_translation(camera.focus + offset).looking_at(camera.focus, Vec3::Y);
     }
 }
@@ -0,0 +1,933 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    building::Building,
+    core::{Alert, AlertKind, GameStates},
+    goods::{Goods, Storage},
+    jobs::Worker,
+    military::{AttackMove, Soldier},
+    population::Population,
+    terrain::ChunkManager,
+    units::{Unit, UnitCargo, UnitEngagement, UnitWaypoints},
+};
+
+/// How much health buildings have.
+pub const BUILDING_HEALTH: u32 = 400;
+/// How much is taken off the damage of every attack on a building.
+pub const BUILDING_ARMOR: u32 = 4;
+/// How much health workers have.
+pub const WORKER_HEALTH: u32 = 40;
+/// How close, in tiles, an enemy has to be for a fighter to go after it.
+pub const AGGRO_RANGE: f32 = 8.0;
+/// How far, in tiles, a fighter follows its target before giving up on it.
+const LEASH_RANGE: f32 = 12.0;
+/// How far, in tiles, the edge of a target is from its center. Attacks reach that much further.
+const TARGET_RADIUS: f32 = 0.5;
+/// How fast projectiles fly, in world units per second.
+const PROJECTILE_SPEED: f32 = 96.0;
+
+/// Who an entity fights for. Entities of different factions are enemies.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Faction {
+    Player,
+    Barbarians,
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Health {
+    pub current: u32,
+    pub max: u32,
+}
+
+impl Health {
+    pub fn new(max: u32) -> Self {
+        Health { current: max, max }
+    }
+
+    pub fn is_dead(&self) -> bool {
+        self.current == 0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AttackKind {
+    /// A blow at an enemy next to the attacker, which lands at once.
+    Melee,
+    /// A projectile shot at an enemy from afar, which lands when it gets there.
+    Ranged,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ArmorKind {
+    Unarmored,
+    Light,
+    Heavy,
+    /// Walls, which arrows barely scratch.
+    Fortified,
+}
+
+impl ArmorKind {
+    /// How much of the damage of an attack of a kind goes through this armor, before its value is
+    /// taken off.
+    pub fn multiplier(&self, attack: AttackKind) -> f32 {
+        match (self, attack) {
+            (ArmorKind::Unarmored, _) => 1.0,
+            (ArmorKind::Light, AttackKind::Melee) => 1.0,
+            (ArmorKind::Light, AttackKind::Ranged) => 1.25,
+            (ArmorKind::Heavy, AttackKind::Melee) => 0.75,
+            (ArmorKind::Heavy, AttackKind::Ranged) => 0.5,
+            (ArmorKind::Fortified, AttackKind::Melee) => 0.5,
+            (ArmorKind::Fortified, AttackKind::Ranged) => 0.25,
+        }
+    }
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Armor {
+    pub kind: ArmorKind,
+    /// How much is taken off the damage of every attack.
+    pub value: u32,
+}
+
+/// How an entity fights.
+#[derive(Component, Debug, Clone, PartialEq)]
+pub struct Attack {
+    pub kind: AttackKind,
+    pub damage: u32,
+    /// How far, in tiles, the attacks reach.
+    pub range: f32,
+    /// How many seconds there are between two attacks. It has to be above 0.
+    pub cooldown: f32,
+    /// How many seconds are left before the next attack.
+    pub ready_in: f32,
+}
+
+impl Attack {
+    pub fn new(kind: AttackKind, damage: u32, range: f32, cooldown: f32) -> Self {
+        Attack {
+            kind,
+            damage,
+            range,
+            cooldown,
+            ready_in: 0.0,
+        }
+    }
+}
+
+/// The enemy the entity is fighting, and where the entity was when it went after it. It gives up
+/// on the enemy once the enemy is further than its leash from there.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Deref)]
+pub struct AttackTarget {
+    #[deref]
+    pub enemy: Entity,
+    pub origin: Vec2,
+}
+
+/// An arrow flying to its target, which takes the damage once it gets there. It is lost if the
+/// target dies on the way.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct Projectile {
+    pub target: Entity,
+    pub damage: u32,
+}
+
+/// An attack landed on an entity.
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Damage {
+    pub target: Entity,
+    /// The damage of the attack, before the armor of the target.
+    pub amount: u32,
+    pub kind: AttackKind,
+}
+
+/// An entity ran out of health and was despawned, dropping the goods it carried or stored.
+#[derive(Event, Debug, Clone, PartialEq)]
+pub struct Died {
+    pub entity: Entity,
+    pub faction: Faction,
+    pub position: Vec2,
+    pub goods: Goods,
+}
+
+pub struct CombatPlugin;
+
+impl Plugin for CombatPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Damage>().add_event::<Died>().add_systems(
+            Update,
+            (
+                add_health,
+                acquire_targets,
+                engage_targets,
+                attack_targets,
+                move_projectiles,
+                apply_damage,
+                handle_deaths,
+                store_dropped_goods,
+                add_projectile_meshes,
+            )
+                .chain()
+                .run_if(in_state(GameStates::Playing)),
+        );
+    }
+}
+
+/// The damage an attack does to an entity with the armor, at least 1.
+pub fn damage_dealt(damage: u32, attack: AttackKind, armor: Option<&Armor>) -> u32 {
+    let Some(armor) = armor else {
+        return damage.max(1);
+    };
+
+    let damage = (damage as f32 * armor.kind.multiplier(attack)).round() as u32;
+    damage.saturating_sub(armor.value).max(1)
+}
+
+/// Whether an attack with the range, in tiles, reaches from one point to a target at the other.
+fn in_reach(from: &Vec2, to: &Vec2, range: f32, tile_size: f32) -> bool {
+    from.distance(*to) <= (range + TARGET_RADIUS) * tile_size
+}
+
+/// Buildings and workers can be attacked as soon as they are spawned. Soldiers get their health
+/// from their stats when they are trained.
+fn add_health(
+    mut commands: Commands,
+    q_buildings: Query<Entity, (Added<Building>, Without<Health>)>,
+    q_workers: Query<Entity, (Added<Worker>, Without<Health>)>,
+) {
+    for building in q_buildings.iter() {
+        commands.entity(building).insert((
+            Faction::Player,
+            Health::new(BUILDING_HEALTH),
+            Armor {
+                kind: ArmorKind::Fortified,
+                value: BUILDING_ARMOR,
+            },
+        ));
+    }
+
+    for worker in q_workers.iter() {
+        commands.entity(worker).insert((
+            Faction::Player,
+            Health::new(WORKER_HEALTH),
+            Armor {
+                kind: ArmorKind::Unarmored,
+                value: 0,
+            },
+        ));
+    }
+}
+
+/// The fighters, with their target and their orders.
+type Fighters<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static GlobalTransform,
+        &'static Faction,
+        Option<&'static AttackTarget>,
+        Option<&'static UnitWaypoints>,
+        Has<AttackMove>,
+    ),
+    With<Attack>,
+>;
+
+/// Fighters keep their target while it lives and is within their leash of where they went after
+/// it. Idle ones, and the ones on an attack-move, go after the closest enemy within their aggro
+/// range; the ones only told to move walk past enemies, and out of the fight they were in.
+fn acquire_targets(
+    mut commands: Commands,
+    q_fighters: Fighters,
+    q_targets: Query<(Entity, &GlobalTransform, &Faction, &Health)>,
+    chunk_manager: Res<ChunkManager>,
+) {
+    let tile_size = chunk_manager.tile_size().x;
+    let targets = q_targets
+        .iter()
+        .filter(|(_, _, _, health)| !health.is_dead())
+        .map(|(entity, transform, faction, _)| (entity, transform.translation().xz(), *faction))
+        .collect::<Vec<_>>();
+
+    for (fighter, transform, faction, target, waypoints, is_attack_moving) in q_fighters.iter() {
+        let can_acquire =
+            is_attack_moving || waypoints.is_none_or(|waypoints| waypoints.is_empty());
+        if !can_acquire {
+            if target.is_some() {
+                commands.entity(fighter).remove::<AttackTarget>();
+            }
+            continue;
+        }
+
+        let position = transform.translation().xz();
+        let is_enemy = |(_, _, other): &&(Entity, Vec2, Faction)| other != faction;
+
+        let current = target.and_then(|target| {
+            targets
+                .iter()
+                .filter(is_enemy)
+                .find(|(entity, ..)| *entity == target.enemy)
+                .map(|(_, point, _)| point.distance(target.origin))
+        });
+        if current.is_some_and(|distance| distance <= LEASH_RANGE * tile_size) {
+            continue;
+        }
+
+        // A target that got away is not picked again right away.
+        let closest = targets
+            .iter()
+            .filter(is_enemy)
+            .filter(|(entity, ..)| target.is_none_or(|target| target.enemy != *entity))
+            .map(|(entity, point, _)| (*entity, point.distance(position)))
+            .filter(|(_, distance)| *distance <= AGGRO_RANGE * tile_size)
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        match closest {
+            Some((enemy, _)) => {
+                commands.entity(fighter).insert(AttackTarget {
+                    enemy,
+                    origin: position,
+                });
+            }
+            None if target.is_some() => {
+                commands.entity(fighter).remove::<AttackTarget>();
+            }
+            None => {}
+        }
+    }
+}
+
+/// The fighting units, with what they are going after.
+type EngagingUnits<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static GlobalTransform,
+        &'static Attack,
+        Option<&'static AttackTarget>,
+        Option<&'static mut UnitEngagement>,
+    ),
+    With<Unit>,
+>;
+
+/// Units with a target go after it until it is in reach, and stand their ground while they
+/// attack it. The ones without one go back to their waypoints.
+fn engage_targets(
+    mut commands: Commands,
+    mut q_fighters: EngagingUnits,
+    q_targets: Query<&GlobalTransform, With<Health>>,
+    chunk_manager: Res<ChunkManager>,
+) {
+    let tile_size = chunk_manager.tile_size().x;
+
+    for (fighter, transform, attack, target, engagement) in q_fighters.iter_mut() {
+        let Some(point) = target
+            .and_then(|target| q_targets.get(**target).ok())
+            .map(|target| target.translation().xz())
+        else {
+            if engagement.is_some() {
+                commands.entity(fighter).remove::<UnitEngagement>();
+            }
+            continue;
+        };
+
+        let next = match in_reach(
+            &transform.translation().xz(),
+            &point,
+            attack.range,
+            tile_size,
+        ) {
+            true => UnitEngagement(None),
+            false => UnitEngagement(Some(point)),
+        };
+        match engagement {
+            Some(mut engagement) if *engagement != next => *engagement = next,
+            Some(_) => {}
+            None => {
+                commands.entity(fighter).insert(next);
+            }
+        }
+    }
+}
+
+/// Fighters attack their target whenever it is in reach and they are ready. Melee attacks land at
+/// once, ranged ones shoot a projectile. The time a fighter was ready for before the frame counts
+/// towards its next attack, so it attacks as often whatever the length of the frames, as long as
+/// they are shorter than its cooldown.
+fn attack_targets(
+    mut commands: Commands,
+    mut q_fighters: Query<(&GlobalTransform, &mut Attack, Option<&AttackTarget>)>,
+    q_targets: Query<&GlobalTransform, With<Health>>,
+    mut damages: EventWriter<Damage>,
+    chunk_manager: Res<ChunkManager>,
+    time: Res<Time>,
+) {
+    let tile_size = chunk_manager.tile_size().x;
+
+    for (transform, mut attack, target) in q_fighters.iter_mut() {
+        attack.ready_in -= time.delta_seconds();
+
+        let position = transform.translation();
+        let target = target
+            .and_then(|target| q_targets.get(**target).ok().map(|point| (**target, point)))
+            .filter(|(_, point)| {
+                in_reach(
+                    &position.xz(),
+                    &point.translation().xz(),
+                    attack.range,
+                    tile_size,
+                )
+            });
+        let Some((target, _)) = target else {
+            // Time spent without anything to attack doesn't add up.
+            attack.ready_in = attack.ready_in.max(0.0);
+            continue;
+        };
+
+        if attack.ready_in > 0.0 {
+            continue;
+        }
+        attack.ready_in = attack.cooldown + attack.ready_in % attack.cooldown;
+
+        match attack.kind {
+            AttackKind::Melee => {
+                damages.send(Damage {
+                    target,
+                    amount: attack.damage,
+                    kind: attack.kind,
+                });
+            }
+            AttackKind::Ranged => {
+                commands.spawn((
+                    Projectile {
+                        target,
+                        damage: attack.damage,
+                    },
+                    TransformBundle::from_transform(Transform::from_translation(position)),
+                ));
+            }
+        }
+    }
+}
+
+/// Fly the projectiles to their target, and land the ones that got there.
+fn move_projectiles(
+    mut commands: Commands,
+    mut q_projectiles: Query<(Entity, &Projectile, &mut Transform)>,
+    q_targets: Query<&GlobalTransform, With<Health>>,
+    mut damages: EventWriter<Damage>,
+    time: Res<Time>,
+) {
+    let delta = PROJECTILE_SPEED * time.delta_seconds();
+
+    for (entity, projectile, mut transform) in q_projectiles.iter_mut() {
+        let Ok(target) = q_targets.get(projectile.target) else {
+            commands.entity(entity).despawn_recursive();
+            continue;
+        };
+
+        let direction = target.translation() - transform.translation;
+        if direction.length() > delta {
+            transform.translation += direction.normalize() * delta;
+            continue;
+        }
+
+        damages.send(Damage {
+            target: projectile.target,
+            amount: projectile.damage,
+            kind: AttackKind::Ranged,
+        });
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Take the damage of the attacks that landed off the health of their target, after its armor.
+fn apply_damage(
+    mut damages: EventReader<Damage>,
+    mut q_targets: Query<(
+        &GlobalTransform,
+        &mut Health,
+        Option<&Armor>,
+        Option<&Faction>,
+    )>,
+    mut alerts: EventWriter<Alert>,
+) {
+    let mut attacked = None;
+
+    for Damage {
+        target,
+        amount,
+        kind,
+    } in damages.read()
+    {
+        let Ok((transform, mut health, armor, faction)) = q_targets.get_mut(*target) else {
+            continue;
+        };
+
+        health.current = health
+            .current
+            .saturating_sub(damage_dealt(*amount, *kind, armor));
+        if faction == Some(&Faction::Player) {
+            attacked.get_or_insert(transform.translation().xz());
+        }
+    }
+
+    if let Some(position) = attacked {
+        alerts.send(Alert {
+            kind: AlertKind::UnderAttack,
+            position: Some(position),
+        });
+    }
+}
+
+/// What was hurt, with what it leaves behind if it died.
+type Hurt<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Health,
+        &'static GlobalTransform,
+        Option<&'static Faction>,
+        Option<&'static Storage>,
+        Option<&'static UnitCargo>,
+        Has<Soldier>,
+        Has<Worker>,
+    ),
+    Changed<Health>,
+>;
+
+/// Despawn what ran out of health, which frees the tile of a building and drops the goods it
+/// stored or carried. The citizens killed are gone from the population.
+fn handle_deaths(
+    mut commands: Commands,
+    q_dead: Hurt,
+    mut population: ResMut<Population>,
+    mut deaths: EventWriter<Died>,
+) {
+    for (entity, health, transform, faction, storage, cargo, is_soldier, is_worker) in q_dead.iter()
+    {
+        if !health.is_dead() {
+            continue;
+        }
+
+        let faction = faction.copied().unwrap_or(Faction::Player);
+        if faction == Faction::Player && (is_soldier || is_worker) {
+            population.lose(is_soldier);
+        }
+
+        let mut goods = Goods::default();
+        if let Some(storage) = storage {
+            goods.add(&storage.goods);
+        }
+        if let Some(cargo) = cargo {
+            goods.add(cargo);
+        }
+
+        deaths.send(Died {
+            entity,
+            faction,
+            position: transform.translation().xz(),
+            goods,
+        });
+        commands.entity(entity).despawn_recursive();
+    }
+}
+
+/// Bring the goods dropped by the dead of the player to the closest storages with space. What
+/// doesn't fit anywhere is lost.
+fn store_dropped_goods(
+    mut deaths: EventReader<Died>,
+    mut q_storages: Query<(Entity, &GlobalTransform, &mut Storage)>,
+) {
+    for Died {
+        entity,
+        faction,
+        position,
+        goods,
+    } in deaths.read()
+    {
+        if *faction != Faction::Player || goods.is_empty() {
+            continue;
+        }
+
+        let mut storages = q_storages
+            .iter_mut()
+            .filter(|(storage_entity, _, storage)| storage_entity != entity && storage.space() > 0)
+            .map(|(_, transform, storage)| {
+                (transform.translation().xz().distance(*position), storage)
+            })
+            .collect::<Vec<_>>();
+        storages.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+        let mut rest = goods.clone();
+        for (_, mut storage) in storages {
+            if rest.is_empty() {
+                break;
+            }
+            rest = storage.store(&rest);
+        }
+    }
+}
+
+fn add_projectile_meshes(
+    mut commands: Commands,
+    q_projectiles: Query<Entity, Added<Projectile>>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+    mut handles: Local<Option<(Handle<Mesh>, Handle<StandardMaterial>)>>,
+) {
+    for projectile in q_projectiles.iter() {
+        let (mesh, material) = handles
+            .get_or_insert_with(|| {
+                let mesh = meshes.add(Sphere::new(0.5));
+                let material = materials.add(StandardMaterial {
+                    base_color: Color::rgb(0.3, 0.2, 0.1),
+                    unlit: true,
+                    ..default()
+                });
+                (mesh, material)
+            })
+            .clone();
+
+        commands
+            .entity(projectile)
+            .insert((mesh, material, VisibilityBundle::default()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        core::Obstacle,
+        helpers::{
+            self,
+            testing::{self, spawn_storage, stored, TestApp},
+        },
+        jobs::{update_job_states, Job, JobKind, JobState},
+        spatial::{update_spatial_index, OccupancyChanged, SpatialIndex},
+        terrain::{ChunkCoord, TileCoord, CHUNK_SIZE},
+    };
+
+    #[test]
+    fn test_damage_dealt() {
+        let heavy = Armor {
+            kind: ArmorKind::Heavy,
+            value: 3,
+        };
+        let fortified = Armor {
+            kind: ArmorKind::Fortified,
+            value: 4,
+        };
+
+        assert_eq!(damage_dealt(12, AttackKind::Melee, None), 12);
+        assert_eq!(damage_dealt(12, AttackKind::Melee, Some(&heavy)), 6);
+        assert_eq!(damage_dealt(12, AttackKind::Ranged, Some(&heavy)), 3);
+        // Every attack does some damage.
+        assert_eq!(damage_dealt(8, AttackKind::Ranged, Some(&fortified)), 1);
+        assert_eq!(damage_dealt(0, AttackKind::Melee, None), 1);
+    }
+
+    /// An app with only the systems that resolve fights, advanced by hand a fixed time at once.
+    fn fight() -> App {
+        TestApp::default()
+            .event::<Damage>()
+            .event::<Died>()
+            .systems(
+                (
+                    acquire_targets,
+                    attack_targets,
+                    move_projectiles,
+                    apply_damage,
+                    handle_deaths,
+                )
+                    .chain(),
+            )
+            .build()
+    }
+
+    fn spawn(app: &mut App, faction: Faction, position: Vec2, bundle: impl Bundle) -> Entity {
+        let transform = Transform::from_translation(position.extend(0.0).xzy());
+        app.world
+            .spawn((faction, bundle, transform, GlobalTransform::from(transform)))
+            .id()
+    }
+
+    #[test]
+    fn test_melee_fight() {
+        let mut app = fight();
+        let swordsman = (
+            Health::new(100),
+            Armor {
+                kind: ArmorKind::Heavy,
+                value: 3,
+            },
+            Attack::new(AttackKind::Melee, 12, 1.0, 1.0),
+        );
+        let raider = (
+            Health::new(50),
+            Armor {
+                kind: ArmorKind::Unarmored,
+                value: 0,
+            },
+            Attack::new(AttackKind::Melee, 10, 1.0, 1.0),
+        );
+        let player = spawn(&mut app, Faction::Player, Vec2::ZERO, swordsman);
+        let barbarian = spawn(&mut app, Faction::Barbarians, Vec2::new(16.0, 0.0), raider);
+
+        // Both pick each other and strike at once, then once a second.
+        testing::advance(&mut app, 0.0);
+        assert_eq!(app.world.get::<Health>(barbarian).unwrap().current, 38);
+        assert_eq!(app.world.get::<Health>(player).unwrap().current, 95);
+        for _ in 0..3 {
+            testing::advance(&mut app, 1.0);
+        }
+        assert_eq!(app.world.get::<Health>(barbarian).unwrap().current, 2);
+        assert_eq!(app.world.get::<Health>(player).unwrap().current, 80);
+
+        // Attacks of the same frame all land, even the one of the fighter dying in it.
+        testing::advance(&mut app, 1.0);
+        assert!(app.world.get_entity(barbarian).is_none());
+        assert_eq!(app.world.get::<Health>(player).unwrap().current, 75);
+
+        testing::advance(&mut app, 1.0);
+        assert!(app.world.get::<AttackTarget>(player).is_none());
+    }
+
+    #[test]
+    fn test_attack_rate() {
+        let mut app = fight();
+        let attacker = (
+            Health::new(10),
+            Attack::new(AttackKind::Melee, 10, 1.0, 1.0),
+        );
+        spawn(&mut app, Faction::Barbarians, Vec2::ZERO, attacker);
+        let target = spawn(
+            &mut app,
+            Faction::Player,
+            Vec2::new(16.0, 0.0),
+            Health::new(100),
+        );
+
+        // Frames that don't line up with the cooldown still give an attack a second.
+        testing::advance(&mut app, 0.0);
+        for _ in 0..8 {
+            testing::advance(&mut app, 0.375);
+        }
+        assert_eq!(app.world.get::<Health>(target).unwrap().current, 60);
+    }
+
+    #[test]
+    fn test_leave_fight() {
+        let mut app = fight();
+        let soldier = (
+            Health::new(100),
+            Attack::new(AttackKind::Melee, 1, 1.0, 1.0),
+        );
+        let player = spawn(&mut app, Faction::Player, Vec2::ZERO, soldier);
+        let barbarian = spawn(
+            &mut app,
+            Faction::Barbarians,
+            Vec2::new(64.0, 0.0),
+            Health::new(100),
+        );
+        let tile_size = app.world.resource::<ChunkManager>().tile_size().x;
+
+        testing::advance(&mut app, 0.0);
+        assert_eq!(
+            app.world.get::<AttackTarget>(player).map(|target| **target),
+            Some(barbarian)
+        );
+
+        // Both run off together: the soldier stays close, but the enemy got too far from where
+        // the chase started.
+        let away = Vec3::new((LEASH_RANGE + 1.0) * tile_size, 0.0, 0.0);
+        for (entity, offset) in [(player, Vec3::ZERO), (barbarian, Vec3::new(64.0, 0.0, 0.0))] {
+            let transform = Transform::from_translation(away + offset);
+            app.world
+                .entity_mut(entity)
+                .insert((transform, GlobalTransform::from(transform)));
+        }
+        testing::advance(&mut app, 0.0);
+        assert!(app.world.get::<AttackTarget>(player).is_none());
+
+        // Back in reach, the soldier picks the enemy again, until told to move.
+        for entity in [player, barbarian] {
+            app.world
+                .entity_mut(entity)
+                .insert((Transform::default(), GlobalTransform::default()));
+        }
+        testing::advance(&mut app, 0.0);
+        assert!(app.world.get::<AttackTarget>(player).is_some());
+
+        app.world
+            .entity_mut(player)
+            .insert(UnitWaypoints(std::collections::VecDeque::from([(
+                Vec2::new(-160.0, 0.0),
+                vec![],
+            )])));
+        testing::advance(&mut app, 0.0);
+        assert!(app.world.get::<AttackTarget>(player).is_none());
+    }
+
+    #[test]
+    fn test_ranged_fight() {
+        let mut app = fight();
+        let archer = (
+            Health::new(60),
+            Armor {
+                kind: ArmorKind::Light,
+                value: 1,
+            },
+            Attack::new(AttackKind::Ranged, 8, 6.0, 1.0),
+        );
+        let wall = (
+            Health::new(10),
+            Armor {
+                kind: ArmorKind::Unarmored,
+                value: 0,
+            },
+        );
+        spawn(&mut app, Faction::Barbarians, Vec2::ZERO, archer);
+        let target = spawn(&mut app, Faction::Player, Vec2::new(48.0, 0.0), wall);
+        let mut q_projectiles = app.world.query::<&Projectile>();
+
+        // The arrow takes half a second to get there.
+        testing::advance(&mut app, 0.0);
+        testing::advance(&mut app, 0.25);
+        assert_eq!(q_projectiles.iter(&app.world).count(), 1);
+        assert_eq!(app.world.get::<Health>(target).unwrap().current, 10);
+
+        testing::advance(&mut app, 0.25);
+        assert_eq!(q_projectiles.iter(&app.world).count(), 0);
+        assert_eq!(app.world.get::<Health>(target).unwrap().current, 2);
+
+        testing::advance(&mut app, 1.0);
+        assert!(app.world.get_entity(target).is_none());
+    }
+
+    /// An app with only the systems that resolve deaths, and what follows from them.
+    fn deaths() -> App {
+        TestApp::default()
+            .event::<Damage>()
+            .event::<Died>()
+            .event::<OccupancyChanged>()
+            .init_resource::<SpatialIndex>()
+            .systems(
+                (
+                    apply_damage,
+                    handle_deaths,
+                    store_dropped_goods,
+                    update_spatial_index,
+                    update_job_states,
+                )
+                    .chain(),
+            )
+            .build()
+    }
+
+    fn kill(app: &mut App, target: Entity) {
+        app.world.send_event(Damage {
+            target,
+            amount: 1000,
+            kind: AttackKind::Melee,
+        });
+        app.update();
+    }
+
+    #[test]
+    fn test_building_death() {
+        let mut app = deaths();
+        let chunk = app.world.spawn(ChunkCoord(IVec2::ZERO)).id();
+        let mut storage = Storage::new(20);
+        storage.store(&Goods::from_iter([("planks", 10)]));
+        let building = spawn(
+            &mut app,
+            Faction::Player,
+            Vec2::ZERO,
+            (
+                Building,
+                Obstacle,
+                TileCoord(UVec2::new(2, 3)),
+                Health::new(10),
+                storage,
+            ),
+        );
+        app.world.entity_mut(building).set_parent(chunk);
+        let near = spawn_storage(&mut app, Vec2::new(16.0, 0.0), 4, &Goods::default());
+        let far = spawn_storage(&mut app, Vec2::new(160.0, 0.0), 20, &Goods::default());
+
+        let coord = helpers::geometry::tile_coord_to_global_coord(
+            &UVec2::new(2, 3),
+            &IVec2::ZERO,
+            &UVec2::splat(CHUNK_SIZE as u32),
+        );
+
+        app.update();
+        assert!(app.world.resource::<SpatialIndex>().is_blocked(&coord));
+
+        // The tile is free again, and the goods go to the closest storages with space.
+        kill(&mut app, building);
+        assert!(app.world.get_entity(building).is_none());
+        assert!(!app.world.resource::<SpatialIndex>().is_blocked(&coord));
+        assert_eq!(stored(&app, near), Goods::from_iter([("planks", 4)]));
+        assert_eq!(stored(&app, far), Goods::from_iter([("planks", 6)]));
+    }
+
+    #[test]
+    fn test_citizen_death() {
+        let mut app = deaths();
+        app.world.insert_resource(Population {
+            citizens: 4,
+            soldiers: 1,
+            ..default()
+        });
+        let from = spawn_storage(&mut app, Vec2::ZERO, 20, &Goods::default());
+        let to = spawn_storage(&mut app, Vec2::new(64.0, 0.0), 20, &Goods::default());
+        let worker = spawn(
+            &mut app,
+            Faction::Player,
+            Vec2::new(16.0, 0.0),
+            (
+                Unit,
+                Worker,
+                Health::new(WORKER_HEALTH),
+                UnitCargo(Goods::from_iter([("logs", 3)])),
+            ),
+        );
+        let soldier = spawn(
+            &mut app,
+            Faction::Player,
+            Vec2::new(16.0, 0.0),
+            (Unit, Soldier, Health::new(100)),
+        );
+        let job = app
+            .world
+            .spawn((
+                Job {
+                    kind: JobKind::Haul {
+                        from,
+                        to,
+                        goods: Goods::from_iter([("logs", 3)]),
+                    },
+                    building: to,
+                    position: Vec2::ZERO,
+                },
+                JobState::Claimed(worker),
+            ))
+            .id();
+
+        kill(&mut app, worker);
+        assert_eq!(app.world.resource::<Population>().citizens, 3);
+        assert_eq!(app.world.resource::<Population>().soldiers, 1);
+        assert_eq!(stored(&app, from), Goods::from_iter([("logs", 3)]));
+        // The job goes back to the other workers.
+        assert_eq!(app.world.get::<JobState>(job), Some(&JobState::Open));
+
+        kill(&mut app, soldier);
+        assert_eq!(app.world.resource::<Population>().citizens, 2);
+        assert_eq!(app.world.resource::<Population>().soldiers, 0);
+    }
+}
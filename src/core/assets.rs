@@ -1,7 +1,12 @@
 use bevy::{prelude::*, utils::HashMap};
 use bevy_asset_loader::prelude::*;
 
-use crate::building::BuildingKind;
+use crate::{
+    building::{BuildingKind, ProductionSettings},
+    quota::QuotaSettings,
+    raids::RaidSettings,
+    terrain::WorldGenSettings,
+};
 
 #[derive(AssetCollection, Resource, Clone)]
 pub struct GameAssets {
@@ -24,6 +29,13 @@ pub struct GameAssets {
         paths(
             "models/lowpoly_buildings/lumber_mill.glb#Scene0",
             "models/lowpoly_buildings/stone_quarry.glb#Scene0",
+            "models/lowpoly_buildings/sawmill.glb#Scene0",
+            "models/lowpoly_buildings/mason.glb#Scene0",
+            "models/lowpoly_buildings/warehouse.glb#Scene0",
+            "models/lowpoly_buildings/house.glb#Scene0",
+            "models/lowpoly_buildings/farm.glb#Scene0",
+            "models/lowpoly_buildings/fishing_hut.glb#Scene0",
+            "models/lowpoly_buildings/barracks.glb#Scene0",
         ),
         collection(mapped, typed)
     )]
@@ -32,10 +44,25 @@ pub struct GameAssets {
         paths(
             "images/buildings/lumber_mill.png",
             "images/buildings/stone_quarry.png",
+            "images/buildings/sawmill.png",
+            "images/buildings/mason.png",
+            "images/buildings/warehouse.png",
+            "images/buildings/house.png",
+            "images/buildings/farm.png",
+            "images/buildings/fishing_hut.png",
+            "images/buildings/barracks.png",
         ),
         collection(mapped, typed)
     )]
     pub ui_buildings: HashMap<BuildingKind, Handle<Image>>,
+    #[asset(path = "settings/world.worldgen.ron")]
+    pub world_gen: Handle<WorldGenSettings>,
+    #[asset(path = "settings/buildings.production.ron")]
+    pub production: Handle<ProductionSettings>,
+    #[asset(path = "settings/barbarians.raids.ron")]
+    pub raids: Handle<RaidSettings>,
+    #[asset(path = "settings/emperor.quota.ron")]
+    pub quota: Handle<QuotaSettings>,
 }
 
 impl MapKey for BuildingKind {
@@ -50,6 +77,13 @@ impl MapKey for BuildingKind {
         match stem.as_str() {
             "lumber_mill" => BuildingKind::LumberMill,
             "stone_quarry" => BuildingKind::StoneQuarry,
+            "sawmill" => BuildingKind::Sawmill,
+            "mason" => BuildingKind::Mason,
+            "warehouse" => BuildingKind::Warehouse,
+            "house" => BuildingKind::House,
+            "farm" => BuildingKind::Farm,
+            "fishing_hut" => BuildingKind::FishingHut,
+            "barracks" => BuildingKind::Barracks,
             _ => panic!("Unknown building kind: {}", stem),
         }
     }
@@ -0,0 +1,23 @@
+use bevy::prelude::*;
+
+/// Something happened that the player should know about.
+#[derive(Event, Debug, Clone)]
+pub struct Alert {
+    pub kind: AlertKind,
+    /// Where on the map it happened, if it happened somewhere.
+    pub position: Option<Vec2>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertKind {
+    BuildingIdle,
+    /// Every storage is full, so workers have nowhere to bring their goods.
+    StorageFull,
+    QuotaWarning,
+    /// The storages ran out of food, so the citizens go hungry.
+    FoodShortage,
+    /// An enemy is attacking a building or a citizen.
+    UnderAttack,
+    /// Raiders will soon arrive where the alert is.
+    RaidWarning,
+}
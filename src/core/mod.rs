@@ -1,21 +1,30 @@
 use bevy::prelude::*;
 use bevy_asset_loader::prelude::*;
-use bevy_panorbit_camera::PanOrbitCameraPlugin;
 
-use crate::{building::BuildingPlugin, camera::CameraPlugin, quota::QuotaPlugin, terrain::TerrainPlugin, ui::UIPlugin, units::UnitsPlugin};
+use crate::{
+    building::BuildingPlugin, camera::CameraPlugin, combat::CombatPlugin, fog::FogOfWarPlugin,
+    jobs::JobsPlugin, military::MilitaryPlugin, minimap::MinimapPlugin,
+    population::PopulationPlugin, quota::QuotaPlugin, raids::RaidsPlugin, roads::RoadsPlugin,
+    selection::SelectionPlugin, spatial::SpatialPlugin, terrain::TerrainPlugin, ui::UIPlugin,
+    units::UnitsPlugin,
+};
 
 #[cfg(feature = "debug")]
 use crate::debug::DebugModePlugin;
 
 pub use assets::*;
 pub use components::*;
+pub use events::*;
 pub use resources::*;
+pub use settings::*;
 pub use states::*;
 use systems::*;
 
 mod assets;
 mod components;
+mod events;
 mod resources;
+mod settings;
 mod states;
 mod systems;
 
@@ -35,13 +44,22 @@ impl Plugin for LethalEmpirePlugin {
             ..default()
         }));
 
-        app.add_plugins(PanOrbitCameraPlugin)
-            .add_plugins(CameraPlugin)
+        app.add_plugins(CameraPlugin)
             .add_plugins(TerrainPlugin::new(0))
+            .add_plugins(FogOfWarPlugin)
             .add_plugins(BuildingPlugin)
             .add_plugins(UIPlugin)
             .add_plugins(QuotaPlugin)
             .add_plugins(UnitsPlugin)
+            .add_plugins(SelectionPlugin)
+            .add_plugins(MinimapPlugin)
+            .add_plugins(RoadsPlugin)
+            .add_plugins(PopulationPlugin)
+            .add_plugins(JobsPlugin)
+            .add_plugins(SpatialPlugin)
+            .add_plugins(MilitaryPlugin)
+            .add_plugins(CombatPlugin)
+            .add_plugins(RaidsPlugin)
             .init_state::<GameStates>()
             .add_loading_state(
                 LoadingState::new(GameStates::AssetLoading)
@@ -50,6 +68,7 @@ impl Plugin for LethalEmpirePlugin {
             )
             .init_resource::<ToolMode>()
             .init_resource::<CursorActive>()
+            .add_event::<Alert>()
             .add_systems(OnEnter(GameStates::Playing), setup)
             .add_systems(
                 Update,
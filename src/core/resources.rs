@@ -5,6 +5,7 @@ pub enum ToolMode {
     #[default]
     Select,
     Build,
+    Road,
 }
 
 #[derive(Resource, Debug, Deref, DerefMut)]
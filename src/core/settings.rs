@@ -0,0 +1,94 @@
+use std::marker::PhantomData;
+
+use bevy::{
+    asset::{io::Reader, ron, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    utils::BoxedFuture,
+};
+use serde::de::DeserializeOwned;
+
+use super::GameAssets;
+
+/// Settings loaded from a `.ron` asset. The systems use a copy of them kept in a resource, which
+/// holds the defaults until the asset is loaded and is replaced whenever the asset changes.
+pub trait SettingsAsset: Asset + Resource + Clone + DeserializeOwned {
+    /// The extensions of the asset files, like `quota.ron`.
+    const EXTENSIONS: &'static [&'static str];
+    /// What the settings are called in the log.
+    const NAME: &'static str;
+
+    /// The handle of the asset among the game assets.
+    fn handle(game_assets: &GameAssets) -> &Handle<Self>;
+
+    /// Check the rules the format can't express. Settings that fail it are not loaded.
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Loads the settings of any kind from their RON.
+pub struct RonAssetLoader<T>(PhantomData<T>);
+
+impl<T> Default for RonAssetLoader<T> {
+    fn default() -> Self {
+        RonAssetLoader(PhantomData)
+    }
+}
+
+impl<T: SettingsAsset> AssetLoader for RonAssetLoader<T> {
+    type Asset = T;
+    type Settings = ();
+    type Error = Box<dyn std::error::Error + Send + Sync>;
+
+    fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext,
+    ) -> BoxedFuture<'a, Result<Self::Asset, Self::Error>> {
+        Box::pin(async move {
+            let mut bytes = Vec::new();
+            reader.read_to_end(&mut bytes).await?;
+            let settings = ron::de::from_bytes::<T>(&bytes)?;
+            settings.validate()?;
+
+            Ok(settings)
+        })
+    }
+
+    fn extensions(&self) -> &[&str] {
+        T::EXTENSIONS
+    }
+}
+
+/// Use the loaded settings.
+pub fn setup_settings<T: SettingsAsset>(
+    mut commands: Commands,
+    game_assets: Res<GameAssets>,
+    settings: Res<Assets<T>>,
+) {
+    if let Some(settings) = settings.get(T::handle(&game_assets)) {
+        commands.insert_resource(settings.clone());
+    }
+}
+
+/// Use the settings again whenever their asset changes.
+pub fn update_settings<T: SettingsAsset>(
+    mut commands: Commands,
+    mut events: EventReader<AssetEvent<T>>,
+    game_assets: Res<GameAssets>,
+    settings: Res<Assets<T>>,
+) {
+    let handle = T::handle(&game_assets);
+    let modified = events
+        .read()
+        .any(|event| matches!(event, AssetEvent::Modified { id } if *id == handle.id()));
+    if !modified {
+        return;
+    }
+
+    if let Some(settings) = settings.get(handle) {
+        info!("{} settings changed", T::NAME);
+        commands.insert_resource(settings.clone());
+    }
+}
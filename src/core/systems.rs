@@ -1,5 +1,6 @@
 use bevy::prelude::*;
-use bevy_panorbit_camera::PanOrbitCamera;
+
+use crate::camera::RtsCamera;
 
 use super::ToolMode;
 
@@ -16,11 +17,7 @@ pub fn setup(mut commands: Commands) {
             transform: Transform::from_xyz(-2.5, 4.5, 9.0).looking_at(Vec3::ZERO, Vec3::Y),
             ..default()
         },
-        PanOrbitCamera {
-            button_orbit: MouseButton::Right,
-            button_pan: MouseButton::Middle,
-            ..default()
-        },
+        RtsCamera::default(),
     ));
 }
 
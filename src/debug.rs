@@ -1,7 +1,11 @@
 use std::f32::consts::FRAC_PI_2;
 
 use crate::{
-    building::{Building, BUILDING_RADIUS}, core::GameStates, helpers, quota::ResourceCount, terrain::{ChunkCoord, ChunkManager}
+    building::{Building, BuildingKind, BuildingLevel, ProductionSettings},
+    core::GameStates,
+    goods::{Goods, Storage},
+    helpers,
+    terrain::{ChunkCoord, ChunkManager},
 };
 use bevy::prelude::*;
 
@@ -92,27 +96,33 @@ fn draw_chunks(mut gizmos: Gizmos, q_chunks: Query<&ChunkCoord>, chunk_manager:
     }
 }
 
-fn cheat_add_resources(
-    mut resource_count: ResMut<ResourceCount>,
-    input: Res<ButtonInput<KeyCode>>,
-) {
+fn cheat_add_resources(mut q_storages: Query<&mut Storage>, input: Res<ButtonInput<KeyCode>>) {
     if input.just_pressed(KeyCode::NumpadAdd) {
-        **resource_count += 1;
+        let Some(mut storage) = q_storages.iter_mut().next() else {
+            return;
+        };
+        storage.store(&Goods::from_iter([
+            ("logs", 1),
+            ("stone", 1),
+            ("planks", 1),
+            ("blocks", 1),
+        ]));
     }
 }
 
 fn draw_building_radius(
-    q_buildings: Query<&GlobalTransform, With<Building>>,
+    q_buildings: Query<(&GlobalTransform, &BuildingKind, &BuildingLevel), With<Building>>,
     mut gizmos: Gizmos,
     chunk_manager: Res<ChunkManager>,
+    production: Res<ProductionSettings>,
 ) {
-    for transform in q_buildings.iter() {
+    for (transform, kind, level) in q_buildings.iter() {
         let tile_size = chunk_manager.tile_size();
-        let radius = tile_size.x.max(tile_size.y) * BUILDING_RADIUS as f32;
+        let level = production.level(kind, **level).unwrap_or_default();
+        let radius = tile_size.x.max(tile_size.y) * level.radius as f32;
 
         let position = transform.translation().xz().extend(0.0).xzy();
 
         gizmos.circle(position, Direction3d::Y, radius, Color::WHITE);
     }
 }
-
@@ -0,0 +1,428 @@
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::GameStates,
+    helpers,
+    terrain::{ChunkManager, CHUNK_SIZE},
+};
+
+/// The radius, in tiles, around the center of the map that is explored when the game starts.
+const START_EXPLORED_RADIUS: u32 = 24;
+
+#[derive(Default, Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[repr(u32)]
+pub enum TileVisibility {
+    #[default]
+    Unexplored,
+    Explored,
+    Visible,
+}
+
+/// How far, in tiles, an entity can see.
+#[derive(Component, Deref)]
+pub struct Vision(pub u32);
+
+/// Entities that are only shown while the tile they are on is visible, e.g. enemy units.
+#[derive(Component)]
+pub struct HiddenInFog;
+
+/// Entities that are shown once the tile they are on has been explored, e.g. trees and rocks.
+#[derive(Component)]
+pub struct HiddenUntilExplored;
+
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FogOfWarSet;
+
+/// The visibility of every tile of the map, stored per chunk.
+#[derive(Resource, Debug, Clone, Serialize, Deserialize)]
+pub struct FogOfWar {
+    size: UVec2,
+    chunks: HashMap<IVec2, Vec<TileVisibility>>,
+    /// How many entities currently have each tile in their vision range. It is counted again
+    /// from the viewers when the fog is deserialized, see `forget_viewers`.
+    #[serde(skip)]
+    visible: HashMap<IVec2, u32>,
+    /// The tile each entity with vision is on and how far it sees, as last counted in `visible`.
+    #[serde(skip)]
+    viewers: HashMap<Entity, (IVec2, u32)>,
+    /// Whether the viewers were counted since the fog was created or deserialized.
+    #[serde(skip)]
+    viewers_counted: bool,
+    /// The smallest rectangle, in global tile coordinates, that contains every explored tile.
+    explored_bounds: Option<IRect>,
+    /// The chunks that had tiles change during this frame.
+    #[serde(skip)]
+    changed: HashSet<IVec2>,
+}
+
+impl Default for FogOfWar {
+    fn default() -> Self {
+        Self {
+            size: UVec2::splat(CHUNK_SIZE as u32),
+            chunks: HashMap::new(),
+            visible: HashMap::new(),
+            viewers: HashMap::new(),
+            viewers_counted: false,
+            explored_bounds: None,
+            changed: HashSet::new(),
+        }
+    }
+}
+
+impl FogOfWar {
+    /// The visibility of a tile given by its global coordinate.
+    pub fn get(&self, global_coord: &IVec2) -> TileVisibility {
+        let chunk_coord = helpers::geometry::global_coord_to_chunk_coord(global_coord, &self.size);
+        let tile_coord = helpers::geometry::global_coord_to_tile_coord(global_coord, &self.size);
+
+        self.get_tile(&chunk_coord, &tile_coord)
+    }
+
+    /// The visibility of a tile given by its chunk and its coordinate in the chunk.
+    pub fn get_tile(&self, chunk_coord: &IVec2, tile_coord: &UVec2) -> TileVisibility {
+        let index = helpers::geometry::tile_coord_to_index(tile_coord, &self.size);
+
+        self.chunks
+            .get(chunk_coord)
+            .map_or(TileVisibility::Unexplored, |tiles| tiles[index])
+    }
+
+    /// The visibility of every tile of a chunk, or `None` if nothing in it was explored yet.
+    pub fn chunk(&self, chunk_coord: &IVec2) -> Option<&[TileVisibility]> {
+        self.chunks.get(chunk_coord).map(Vec::as_slice)
+    }
+
+    pub fn is_explored(&self, global_coord: &IVec2) -> bool {
+        !matches!(self.get(global_coord), TileVisibility::Unexplored)
+    }
+
+    pub fn is_visible(&self, global_coord: &IVec2) -> bool {
+        matches!(self.get(global_coord), TileVisibility::Visible)
+    }
+
+    /// The smallest rectangle, in global tile coordinates, that contains every explored tile.
+    pub fn explored_bounds(&self) -> Option<IRect> {
+        self.explored_bounds
+    }
+
+    /// The chunks that had tiles change during this frame.
+    pub fn changed(&self) -> impl Iterator<Item = &IVec2> {
+        self.changed.iter()
+    }
+
+    /// The visibility of the tiles of a chunk with a one tile border taken from the neighbouring
+    /// chunks, so `(size.x + 2) * (size.y + 2)` entries.
+    pub fn padded_chunk(&self, chunk_coord: &IVec2) -> Vec<u32> {
+        let size = self.size.as_ivec2();
+        let origin =
+            helpers::geometry::tile_coord_to_global_coord(&UVec2::ZERO, chunk_coord, &self.size);
+
+        let mut padded = Vec::with_capacity(((size.x + 2) * (size.y + 2)) as usize);
+        for y in -1..=size.y {
+            for x in -1..=size.x {
+                padded.push(self.get(&(origin + IVec2::new(x, y))) as u32);
+            }
+        }
+
+        padded
+    }
+
+    /// Mark the tiles in a circle as explored, without making them visible.
+    pub fn explore(&mut self, center: &IVec2, radius: u32) {
+        for global_coord in tiles_in_radius(center, radius) {
+            if !self.is_explored(&global_coord) {
+                self.set(&global_coord, TileVisibility::Explored);
+            }
+        }
+    }
+
+    /// Move the vision of an entity to the tile it is on now. Only the tiles that enter or leave
+    /// its range change, and nothing happens while it stays on the same tile.
+    pub fn update_viewer(&mut self, entity: Entity, center: &IVec2, radius: u32) {
+        if self.viewers.get(&entity) == Some(&(*center, radius)) {
+            return;
+        }
+
+        self.remove_viewer(entity);
+        for global_coord in tiles_in_radius(center, radius) {
+            let count = self.visible.entry(global_coord).or_default();
+            *count += 1;
+            if *count == 1 {
+                self.set(&global_coord, TileVisibility::Visible);
+            }
+        }
+        self.viewers.insert(entity, (*center, radius));
+    }
+
+    /// Stop counting the vision of an entity. Tiles nothing else sees become explored.
+    pub fn remove_viewer(&mut self, entity: Entity) {
+        let Some((center, radius)) = self.viewers.remove(&entity) else {
+            return;
+        };
+
+        for global_coord in tiles_in_radius(&center, radius) {
+            let Some(count) = self.visible.get_mut(&global_coord) else {
+                continue;
+            };
+            *count -= 1;
+            if *count == 0 {
+                self.visible.remove(&global_coord);
+                self.set(&global_coord, TileVisibility::Explored);
+            }
+        }
+    }
+
+    /// Stop counting the vision of every entity, and make the tiles they saw explored. A
+    /// deserialized fog has no viewers, so they have to be added again from scratch.
+    pub fn forget_viewers(&mut self) {
+        let visible = self
+            .chunks
+            .iter()
+            .flat_map(|(chunk_coord, tiles)| {
+                tiles
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, tile)| **tile == TileVisibility::Visible)
+                    .map(|(index, _)| {
+                        let tile_coord = helpers::geometry::index_to_tile_coord(index, &self.size);
+                        helpers::geometry::tile_coord_to_global_coord(
+                            &tile_coord,
+                            chunk_coord,
+                            &self.size,
+                        )
+                    })
+            })
+            .collect::<Vec<_>>();
+
+        for global_coord in visible {
+            self.set(&global_coord, TileVisibility::Explored);
+        }
+        self.visible.clear();
+        self.viewers.clear();
+    }
+
+    pub fn clear_changed(&mut self) {
+        self.changed.clear();
+    }
+
+    fn set(&mut self, global_coord: &IVec2, visibility: TileVisibility) {
+        let chunk_coord = helpers::geometry::global_coord_to_chunk_coord(global_coord, &self.size);
+        let tile_coord = helpers::geometry::global_coord_to_tile_coord(global_coord, &self.size);
+        let index = helpers::geometry::tile_coord_to_index(&tile_coord, &self.size);
+        let length = (self.size.x * self.size.y) as usize;
+        if self.get_tile(&chunk_coord, &tile_coord) == visibility {
+            return;
+        }
+
+        self.chunks
+            .entry(chunk_coord)
+            .or_insert_with(|| vec![TileVisibility::Unexplored; length])[index] = visibility;
+        self.changed.insert(chunk_coord);
+
+        self.explored_bounds = Some(match self.explored_bounds {
+            Some(bounds) => bounds.union_point(*global_coord),
+            None => IRect::from_corners(*global_coord, *global_coord),
+        });
+    }
+}
+
+fn tiles_in_radius(center: &IVec2, radius: u32) -> impl Iterator<Item = IVec2> + '_ {
+    let radius = radius as i32;
+
+    (-radius..=radius)
+        .flat_map(move |y| (-radius..=radius).map(move |x| IVec2::new(x, y)))
+        .filter(move |offset| offset.length_squared() <= radius * radius)
+        .map(move |offset| *center + offset)
+}
+
+pub struct FogOfWarPlugin;
+
+impl Plugin for FogOfWarPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FogOfWar>()
+            .add_systems(OnEnter(GameStates::Playing), setup_fog_of_war)
+            .add_systems(
+                Update,
+                (
+                    update_fog_of_war.in_set(FogOfWarSet),
+                    hide_entities_in_fog.after(FogOfWarSet),
+                )
+                    .run_if(in_state(GameStates::Playing)),
+            )
+            .add_systems(PostUpdate, clear_fog_of_war_changes);
+    }
+}
+
+fn setup_fog_of_war(mut fog: ResMut<FogOfWar>) {
+    fog.explore(&IVec2::ZERO, START_EXPLORED_RADIUS);
+}
+
+fn update_fog_of_war(
+    q_vision: Query<(Entity, Ref<GlobalTransform>, Ref<Vision>)>,
+    mut removed: RemovedComponents<Vision>,
+    chunk_manager: Res<ChunkManager>,
+    mut fog: ResMut<FogOfWar>,
+) {
+    let size = chunk_manager.size();
+    let tile_size = chunk_manager.tile_size();
+
+    // A new fog, like one that was just deserialized, counts every viewer again, the others only
+    // the ones that moved.
+    let recount = !fog.viewers_counted;
+    if recount {
+        fog.forget_viewers();
+        fog.viewers_counted = true;
+    }
+
+    for entity in removed.read() {
+        fog.remove_viewer(entity);
+    }
+
+    for (entity, transform, vision) in q_vision.iter() {
+        if !recount && !transform.is_changed() && !vision.is_changed() {
+            continue;
+        }
+
+        let center = helpers::geometry::world_pos_to_global_coord(
+            &transform.translation().xz(),
+            &size,
+            &tile_size,
+        );
+
+        fog.update_viewer(entity, &center, **vision);
+    }
+}
+
+fn clear_fog_of_war_changes(mut fog: ResMut<FogOfWar>) {
+    fog.clear_changed();
+}
+
+fn hide_entities_in_fog(
+    fog: Res<FogOfWar>,
+    chunk_manager: Res<ChunkManager>,
+    mut q_hidden: Query<(&GlobalTransform, &mut Visibility), With<HiddenInFog>>,
+) {
+    let size = chunk_manager.size();
+    let tile_size = chunk_manager.tile_size();
+
+    for (transform, mut visibility) in q_hidden.iter_mut() {
+        let global_coord = helpers::geometry::world_pos_to_global_coord(
+            &transform.translation().xz(),
+            &size,
+            &tile_size,
+        );
+
+        let expected = if fog.is_visible(&global_coord) {
+            Visibility::Inherited
+        } else {
+            Visibility::Hidden
+        };
+        if *visibility != expected {
+            *visibility = expected;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::asset::ron;
+
+    use super::*;
+    use crate::helpers::testing::TestApp;
+
+    #[test]
+    fn test_fog_of_war_update_viewer() {
+        let mut fog = FogOfWar::default();
+        let viewer = Entity::from_raw(0);
+        let tile = IVec2::new(3, -5);
+
+        assert_eq!(fog.get(&tile), TileVisibility::Unexplored);
+
+        fog.update_viewer(viewer, &tile, 0);
+        assert_eq!(fog.get(&tile), TileVisibility::Visible);
+        assert_eq!(fog.changed().count(), 1);
+
+        // Staying on the same tile changes nothing.
+        fog.clear_changed();
+        fog.update_viewer(viewer, &tile, 0);
+        assert_eq!(fog.changed().count(), 0);
+
+        // A tile stays visible while anything still sees it.
+        fog.update_viewer(Entity::from_raw(1), &tile, 1);
+        fog.update_viewer(viewer, &IVec2::ZERO, 0);
+        assert_eq!(fog.get(&tile), TileVisibility::Visible);
+        assert_eq!(fog.get(&IVec2::ZERO), TileVisibility::Visible);
+
+        fog.remove_viewer(Entity::from_raw(1));
+        assert_eq!(fog.get(&tile), TileVisibility::Explored);
+        assert_eq!(fog.get(&(tile + IVec2::X)), TileVisibility::Explored);
+        assert_eq!(fog.get(&IVec2::ZERO), TileVisibility::Visible);
+    }
+
+    #[test]
+    fn test_fog_of_war_explore() {
+        let mut fog = FogOfWar::default();
+
+        fog.explore(&IVec2::ZERO, 2);
+
+        assert!(fog.is_explored(&IVec2::new(2, 0)));
+        assert!(fog.is_explored(&IVec2::new(-1, 1)));
+        assert!(!fog.is_explored(&IVec2::new(2, 2)));
+        assert!(!fog.is_visible(&IVec2::ZERO));
+        assert_eq!(
+            fog.explored_bounds(),
+            Some(IRect::from_corners(IVec2::splat(-2), IVec2::splat(2)))
+        );
+    }
+
+    #[test]
+    fn test_fog_of_war_padded_chunk() {
+        let mut fog = FogOfWar::default();
+        let size = CHUNK_SIZE as i32;
+        // The first tile of the chunk to the right of the origin chunk.
+        let tile = helpers::geometry::tile_coord_to_global_coord(
+            &UVec2::ZERO,
+            &IVec2::new(1, 0),
+            &fog.size,
+        );
+
+        fog.update_viewer(Entity::from_raw(0), &tile, 0);
+        let padded = fog.padded_chunk(&IVec2::ZERO);
+
+        let index = (size + 2 + size + 1) as usize;
+        assert_eq!(padded[index], TileVisibility::Visible as u32);
+        assert_eq!(padded.iter().filter(|v| **v != 0).count(), 1);
+    }
+
+    #[test]
+    fn test_fog_of_war_deserialized() {
+        let mut app = TestApp::default()
+            .init_resource::<FogOfWar>()
+            .systems(update_fog_of_war)
+            .build();
+        let viewer = app
+            .world
+            .spawn((GlobalTransform::default(), Vision(1)))
+            .id();
+        app.update();
+
+        let serialized = ron::to_string(app.world.resource::<FogOfWar>()).unwrap();
+        let fog = ron::from_str::<FogOfWar>(&serialized).unwrap();
+        assert!(fog.is_visible(&IVec2::ZERO));
+        app.insert_resource(fog);
+        app.update();
+        assert!(app.world.resource::<FogOfWar>().is_visible(&IVec2::ZERO));
+
+        // The viewer is counted once, so the tiles fall back to explored when it is gone.
+        app.world.despawn(viewer);
+        app.update();
+        assert_eq!(
+            app.world.resource::<FogOfWar>().get(&IVec2::ZERO),
+            TileVisibility::Explored
+        );
+    }
+}
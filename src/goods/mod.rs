@@ -0,0 +1,337 @@
+use std::{collections::BTreeMap, fmt, ops::DerefMut};
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+use serde::{Deserialize, Serialize};
+
+use crate::{building::STORAGE_REACH, terrain::ChunkManager};
+
+/// The goods in the warehouse the player starts with.
+pub const STARTING_GOODS: &[(&str, u32)] = &[("logs", 5), ("fish", 20)];
+
+/// A kind of good, like `logs` or `planks`. Goods are only named in the data files, so new ones
+/// can be added without touching the code.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Good(pub String);
+
+impl Good {
+    pub fn new(name: &str) -> Self {
+        Good(name.to_string())
+    }
+}
+
+impl fmt::Display for Good {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// An amount of each of several goods, e.g. a cost, a recipe or what is in the stockpile.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deref, DerefMut, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Goods(pub BTreeMap<Good, u32>);
+
+impl Goods {
+    pub fn get(&self, good: &Good) -> u32 {
+        self.0.get(good).copied().unwrap_or(0)
+    }
+
+    /// Whether there is at least as much of every good as in `other`.
+    pub fn contains(&self, other: &Goods) -> bool {
+        other.iter().all(|(good, amount)| self.get(good) >= *amount)
+    }
+
+    /// Add the goods of `other`. Amounts stop at `u32::MAX`, like the quotas do.
+    pub fn add(&mut self, other: &Goods) {
+        for (good, amount) in other.iter().filter(|(_, amount)| **amount > 0) {
+            let current = self.0.entry(good.clone()).or_default();
+            *current = current.saturating_add(*amount);
+        }
+    }
+
+    /// Remove the goods of `other` if they are all there, otherwise leave everything untouched.
+    /// Goods that run out are dropped, so they don't show up as `0`.
+    pub fn take(&mut self, other: &Goods) -> bool {
+        if !self.contains(other) {
+            return false;
+        }
+
+        for (good, amount) in other.iter() {
+            if let Some(current) = self.0.get_mut(good) {
+                *current -= amount;
+                if *current == 0 {
+                    self.0.remove(good);
+                }
+            }
+        }
+
+        true
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.values().all(|amount| *amount == 0)
+    }
+
+    /// How many goods there are in total, of any kind.
+    pub fn total(&self) -> u32 {
+        self.0
+            .values()
+            .fold(0, |total, amount| total.saturating_add(*amount))
+    }
+
+    /// The goods repeated `count` times, e.g. the cost of several road tiles. Amounts stop at
+    /// `u32::MAX`.
+    pub fn times(&self, count: u32) -> Goods {
+        self.iter()
+            .map(|(good, amount)| (good.clone(), amount.saturating_mul(count)))
+            .collect()
+    }
+}
+
+impl<'a> FromIterator<(&'a str, u32)> for Goods {
+    fn from_iter<T: IntoIterator<Item = (&'a str, u32)>>(iter: T) -> Self {
+        Goods(
+            iter.into_iter()
+                .map(|(name, amount)| (Good::new(name), amount))
+                .collect(),
+        )
+    }
+}
+
+impl FromIterator<(Good, u32)> for Goods {
+    fn from_iter<T: IntoIterator<Item = (Good, u32)>>(iter: T) -> Self {
+        Goods(iter.into_iter().collect())
+    }
+}
+
+impl fmt::Display for Goods {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let goods = self
+            .iter()
+            .map(|(good, amount)| format!("{} {}", amount, good))
+            .collect::<Vec<_>>();
+
+        write!(f, "{}", goods.join(", "))
+    }
+}
+
+/// A building that holds goods, up to its capacity.
+#[derive(Component, Debug, Clone, Default)]
+pub struct Storage {
+    pub capacity: u32,
+    pub goods: Goods,
+}
+
+impl Storage {
+    pub fn new(capacity: u32) -> Self {
+        Storage {
+            capacity,
+            goods: Goods::default(),
+        }
+    }
+
+    pub fn space(&self) -> u32 {
+        self.capacity.saturating_sub(self.goods.total())
+    }
+
+    /// Store as much of the goods as fits and return what didn't.
+    pub fn store(&mut self, goods: &Goods) -> Goods {
+        let mut rest = Goods::default();
+        for (good, amount) in goods.iter() {
+            let stored = (*amount).min(self.space());
+            self.goods.add(&Goods::from_iter([(good.clone(), stored)]));
+            if stored < *amount {
+                rest.add(&Goods::from_iter([(good.clone(), amount - stored)]));
+            }
+        }
+
+        rest
+    }
+}
+
+/// Take goods from several storages, emptying the first ones first. Nothing is taken unless all
+/// of it is there.
+pub fn take_from_storages<S: DerefMut<Target = Storage>>(
+    storages: &mut [S],
+    goods: &Goods,
+) -> bool {
+    let mut available = Goods::default();
+    for storage in storages.iter() {
+        available.add(&storage.goods);
+    }
+    if !available.contains(goods) {
+        return false;
+    }
+
+    for (good, amount) in goods.iter() {
+        take_good_from_storages(storages, good, *amount);
+    }
+
+    true
+}
+
+/// Take up to `amount` goods of any of the given kinds from several storages, the first kinds and
+/// the first storages first, and return how many were taken.
+pub fn take_any_from_storages<S: DerefMut<Target = Storage>>(
+    storages: &mut [S],
+    goods: &[Good],
+    amount: u32,
+) -> u32 {
+    let mut remaining = amount;
+    for good in goods.iter() {
+        remaining -= take_good_from_storages(storages, good, remaining);
+    }
+
+    amount - remaining
+}
+
+/// Take up to `amount` of one good from several storages, the first ones first, and return how
+/// much was taken.
+fn take_good_from_storages<S: DerefMut<Target = Storage>>(
+    storages: &mut [S],
+    good: &Good,
+    amount: u32,
+) -> u32 {
+    let mut remaining = amount;
+    for storage in storages.iter_mut() {
+        let taken = storage.goods.get(good).min(remaining);
+        storage
+            .goods
+            .take(&Goods::from_iter([(good.clone(), taken)]));
+        remaining -= taken;
+    }
+
+    amount - remaining
+}
+
+/// The storages within `reach` world units of a point, nearest first.
+pub fn storages_in_reach<'a>(
+    storages: impl Iterator<Item = (&'a GlobalTransform, Mut<'a, Storage>)>,
+    point: &Vec2,
+    reach: f32,
+) -> Vec<Mut<'a, Storage>> {
+    let mut storages = storages
+        .map(|(transform, storage)| (transform.translation().xz().distance(*point), storage))
+        .filter(|(distance, _)| *distance <= reach)
+        .collect::<Vec<_>>();
+    storages.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+    storages.into_iter().map(|(_, storage)| storage).collect()
+}
+
+/// The storages, and the size of the tiles their reach is measured in.
+#[derive(SystemParam)]
+pub struct StoragesInReach<'w, 's> {
+    chunk_manager: Res<'w, ChunkManager>,
+    q_storages: Query<'w, 's, (&'static GlobalTransform, &'static mut Storage)>,
+}
+
+impl StoragesInReach<'_, '_> {
+    /// The storages within `STORAGE_REACH` tiles of a point, nearest first.
+    pub fn near(&mut self, point: &Vec2) -> Vec<Mut<'_, Storage>> {
+        let tile_size = self.chunk_manager.tile_size();
+        let reach = STORAGE_REACH as f32 * tile_size.x.max(tile_size.y);
+
+        storages_in_reach(self.q_storages.iter_mut(), point, reach)
+    }
+}
+
+/// The goods in every storage added together, for showing and checking the totals.
+#[derive(Resource, Debug, Default, Deref, DerefMut)]
+pub struct Stockpile(pub Goods);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_goods_take() {
+        let mut goods = Goods::from_iter([("logs", 5), ("stone", 2)]);
+
+        assert!(!goods.take(&Goods::from_iter([("logs", 3), ("planks", 1)])));
+        assert_eq!(goods.get(&Good::new("logs")), 5);
+
+        assert!(goods.take(&Goods::from_iter([("logs", 3), ("stone", 2)])));
+        assert_eq!(goods.get(&Good::new("logs")), 2);
+        assert_eq!(goods.get(&Good::new("stone")), 0);
+        assert_eq!(goods.to_string(), "2 logs");
+        assert_eq!(goods, Goods::from_iter([("logs", 2)]));
+    }
+
+    #[test]
+    fn test_storage_store() {
+        let mut storage = Storage::new(5);
+
+        let rest = storage.store(&Goods::from_iter([("logs", 3), ("stone", 4)]));
+
+        assert_eq!(storage.goods, Goods::from_iter([("logs", 3), ("stone", 2)]));
+        assert_eq!(rest, Goods::from_iter([("stone", 2)]));
+        assert_eq!(storage.space(), 0);
+    }
+
+    #[test]
+    fn test_take_from_storages() {
+        let mut first = Storage::new(10);
+        first.store(&Goods::from_iter([("logs", 2)]));
+        let mut second = Storage::new(10);
+        second.store(&Goods::from_iter([("logs", 3), ("stone", 1)]));
+        let mut storages = [&mut first, &mut second];
+
+        assert!(!take_from_storages(
+            &mut storages,
+            &Goods::from_iter([("logs", 6)])
+        ));
+        assert!(take_from_storages(
+            &mut storages,
+            &Goods::from_iter([("logs", 4)])
+        ));
+
+        assert_eq!(first.goods.get(&Good::new("logs")), 0);
+        assert_eq!(second.goods.get(&Good::new("logs")), 1);
+        assert_eq!(second.goods.get(&Good::new("stone")), 1);
+    }
+
+    #[test]
+    fn test_take_any_from_storages() {
+        let mut first = Storage::new(10);
+        first.store(&Goods::from_iter([("wheat", 2), ("logs", 4)]));
+        let mut second = Storage::new(10);
+        second.store(&Goods::from_iter([("fish", 1), ("wheat", 3)]));
+        let mut storages = [&mut first, &mut second];
+        let food = [Good::new("fish"), Good::new("wheat")];
+
+        assert_eq!(take_any_from_storages(&mut storages, &food, 4), 4);
+        assert_eq!(first.goods, Goods::from_iter([("logs", 4)]));
+        assert_eq!(second.goods, Goods::from_iter([("wheat", 2)]));
+
+        let mut storages = [&mut first, &mut second];
+        assert_eq!(take_any_from_storages(&mut storages, &food, 5), 2);
+        assert_eq!(first.goods.get(&Good::new("logs")), 4);
+    }
+
+    #[test]
+    fn test_goods_times() {
+        let goods = Goods::from_iter([("logs", 2), ("stone", 1)]);
+
+        assert_eq!(
+            goods.times(3),
+            Goods::from_iter([("logs", 6), ("stone", 3)])
+        );
+        assert!(goods.times(0).is_empty());
+
+        let mut many = goods.times(u32::MAX);
+        many.add(&goods);
+        assert_eq!(
+            many,
+            Goods::from_iter([("logs", u32::MAX), ("stone", u32::MAX)])
+        );
+        assert_eq!(many.total(), u32::MAX);
+    }
+
+    #[test]
+    fn test_goods_deserialize() {
+        let goods = bevy::asset::ron::de::from_str::<Goods>(r#"{"planks": 2, "logs": 1}"#).unwrap();
+
+        assert_eq!(goods, Goods::from_iter([("logs", 1), ("planks", 2)]));
+    }
+}
@@ -1,4 +1,4 @@
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 
 pub fn screen_to_world(
     camera: &Camera,
@@ -12,3 +12,19 @@ pub fn screen_to_world(
 
     Some(point)
 }
+
+/// The camera and the window, to find the point on the ground under the cursor.
+#[derive(SystemParam)]
+pub struct GroundCursor<'w, 's> {
+    q_camera: Query<'w, 's, (&'static Camera, &'static GlobalTransform)>,
+    windows: Query<'w, 's, &'static Window>,
+}
+
+impl GroundCursor<'_, '_> {
+    /// The point on the ground under the cursor, if the cursor is over the window.
+    pub fn point(&self) -> Option<Vec3> {
+        let (camera, camera_transform) = self.q_camera.get_single().ok()?;
+
+        screen_to_world(camera, camera_transform, self.windows.get_single().ok()?)
+    }
+}
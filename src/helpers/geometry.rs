@@ -18,24 +18,6 @@ pub fn world_pos_to_chunk_coord(world_pos: &Vec2, size: &UVec2, tile_size: &Vec2
     return chunk_coord;
 }
 
-pub fn world_area_to_chunk_coords(world_pos: &Vec2, tiles: u32, size: &UVec2, tile_size: &Vec2) -> Vec<IVec2> {
-    let chunk_coord = world_pos_to_chunk_coord(world_pos, size, tile_size);
-    let chunk_size = size.as_vec2();
-    let chunk_radius = tiles as f32 / chunk_size.x.max(chunk_size.y);
-    let chunk_radius = chunk_radius.ceil() as i32;
-    let mut coords = Vec::new();
-
-    for y in -chunk_radius..=chunk_radius {
-        for x in -chunk_radius..=chunk_radius {
-            let coord = chunk_coord + IVec2::new(x, y);
-
-            coords.push(coord);
-        }
-    }
-
-    return coords;
-}
-
 /// Convert a world position to a global tile coordinate.
 pub fn world_pos_to_global_coord(world_pos: &Vec2, size: &UVec2, tile_size: &Vec2) -> IVec2 {
     let chunk_coord = world_pos_to_chunk_coord(world_pos, size, tile_size);
@@ -117,10 +99,79 @@ pub fn tile_coord_to_index(tile_coord: &UVec2, size: &UVec2) -> usize {
     return index as usize;
 }
 
+/// The coordinates on the line between two coordinates, both included, using Bresenham's
+/// algorithm.
+pub fn line_coords(start: &IVec2, end: &IVec2) -> Vec<IVec2> {
+    let delta = IVec2::new((end.x - start.x).abs(), -(end.y - start.y).abs());
+    let step = IVec2::new((end.x - start.x).signum(), (end.y - start.y).signum());
+
+    let mut coords = vec![*start];
+    let mut coord = *start;
+    let mut error = delta.x + delta.y;
+    while coord != *end {
+        let doubled = 2 * error;
+        if doubled >= delta.y {
+            error += delta.y;
+            coord.x += step.x;
+        }
+        if doubled <= delta.x {
+            error += delta.x;
+            coord.y += step.y;
+        }
+        coords.push(coord);
+    }
+
+    coords
+}
+
+/// The coordinates of the rectangle with two opposite corners, both included, row by row
+/// starting from `start`. They are made as they are taken, so a huge rectangle can be cut short
+/// without making all of it.
+pub fn area_coords(start: &IVec2, end: &IVec2) -> impl Iterator<Item = IVec2> {
+    let step = IVec2::new(
+        if end.x < start.x { -1 } else { 1 },
+        if end.y < start.y { -1 } else { 1 },
+    );
+    let delta = (*end - *start).abs();
+    let start = *start;
+
+    (0..=delta.y).flat_map(move |y| (0..=delta.x).map(move |x| start + IVec2::new(x, y) * step))
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
 
+    #[test]
+    fn test_line_coords() {
+        let coords = line_coords(&IVec2::new(1, 6), &IVec2::new(6, 2));
+
+        assert_eq!(coords.first(), Some(&IVec2::new(1, 6)));
+        assert_eq!(coords.last(), Some(&IVec2::new(6, 2)));
+        assert_eq!(coords.len(), 6);
+
+        assert_eq!(line_coords(&IVec2::ONE, &IVec2::ONE), vec![IVec2::ONE]);
+    }
+
+    #[test]
+    fn test_area_coords() {
+        let coords = area_coords(&IVec2::new(2, 1), &IVec2::new(0, 2)).collect::<Vec<_>>();
+
+        assert_eq!(coords.len(), 6);
+        assert_eq!(coords.first(), Some(&IVec2::new(2, 1)));
+        assert_eq!(coords.last(), Some(&IVec2::new(0, 2)));
+        assert!(coords.contains(&IVec2::new(1, 1)));
+
+        assert_eq!(
+            area_coords(&IVec2::ONE, &IVec2::ONE).collect::<Vec<_>>(),
+            vec![IVec2::ONE]
+        );
+        let first = area_coords(&IVec2::ZERO, &IVec2::splat(100_000))
+            .take(3)
+            .collect::<Vec<_>>();
+        assert_eq!(first, vec![IVec2::ZERO, IVec2::X, IVec2::new(2, 0)]);
+    }
+
     #[test]
     fn test_chunk_coord_to_world_pos() {
         let chunk_coord = IVec2::new(1, 1);
@@ -1,4 +1,7 @@
+pub mod camera;
 pub mod geometry;
 pub mod hash;
+pub mod pathfinding;
 pub mod sampling;
-pub mod camera;
+#[cfg(test)]
+pub mod testing;
@@ -0,0 +1,236 @@
+use std::{cmp::Ordering, collections::BinaryHeap, f32::consts::SQRT_2};
+
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+
+const NEIGHBOURS: [IVec2; 8] = [
+    IVec2::new(1, 0),
+    IVec2::new(-1, 0),
+    IVec2::new(0, 1),
+    IVec2::new(0, -1),
+    IVec2::new(1, 1),
+    IVec2::new(1, -1),
+    IVec2::new(-1, 1),
+    IVec2::new(-1, -1),
+];
+
+#[derive(PartialEq)]
+struct Integration {
+    cost: f32,
+    coord: IVec2,
+}
+
+impl Eq for Integration {}
+
+impl Ord for Integration {
+    // Reversed, so the binary heap pops the lowest cost first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+impl PartialOrd for Integration {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// The cheapest way to a goal from every tile in an area, for any number of units going there.
+/// The goal can be several tiles, in which case the way leads to the closest of them.
+///
+/// The integration field is the cost of the cheapest path from each tile to the goal, and the
+/// flow field the neighbour each tile leads to on that path. Tiles outside the area or that can't
+/// reach the goal are in neither.
+#[derive(Debug, Clone)]
+pub struct FlowField {
+    min: IVec2,
+    max: IVec2,
+    costs: HashMap<IVec2, f32>,
+    flow: HashMap<IVec2, IVec2>,
+}
+
+impl FlowField {
+    /// Build the fields over the tiles from `min` to `max`, both included. `cost` is the cost of
+    /// walking onto a tile, or `None` if it can't be walked on, and the goals can always be walked
+    /// on, since units end up on buildings and resources.
+    pub fn new(
+        goals: &[IVec2],
+        min: &IVec2,
+        max: &IVec2,
+        cost: impl Fn(&IVec2) -> Option<f32>,
+    ) -> Self {
+        let goals = goals.iter().copied().collect::<HashSet<_>>();
+        let in_area = |coord: &IVec2| coord.cmpge(*min).all() && coord.cmple(*max).all();
+        let mut tile_costs = HashMap::<IVec2, Option<f32>>::new();
+        let mut tile_cost = |coord: &IVec2| match goals.contains(coord) {
+            true => Some(cost(coord).unwrap_or(1.0)),
+            false if in_area(coord) => *tile_costs.entry(*coord).or_insert_with(|| cost(coord)),
+            false => None,
+        };
+
+        let mut costs = goals
+            .iter()
+            .map(|goal| (*goal, 0.0))
+            .collect::<HashMap<_, _>>();
+        let mut open = goals
+            .iter()
+            .map(|goal| Integration {
+                cost: 0.0,
+                coord: *goal,
+            })
+            .collect::<BinaryHeap<_>>();
+
+        // Walk back from the goal, so the step onto a tile costs what that tile costs.
+        while let Some(Integration {
+            cost: current_cost,
+            coord,
+        }) = open.pop()
+        {
+            if costs.get(&coord).is_some_and(|cost| *cost < current_cost) {
+                continue;
+            }
+            let Some(step_cost) = tile_cost(&coord) else {
+                continue;
+            };
+
+            for offset in NEIGHBOURS {
+                let neighbour = coord + offset;
+                if tile_cost(&neighbour).is_none() {
+                    continue;
+                }
+
+                let is_diagonal = offset.x != 0 && offset.y != 0;
+                if is_diagonal
+                    && (tile_cost(&(coord + IVec2::new(offset.x, 0))).is_none()
+                        || tile_cost(&(coord + IVec2::new(0, offset.y))).is_none())
+                {
+                    continue;
+                }
+
+                let step_cost = match is_diagonal {
+                    true => step_cost * SQRT_2,
+                    false => step_cost,
+                };
+                let new_cost = current_cost + step_cost;
+                if costs.get(&neighbour).is_some_and(|cost| *cost <= new_cost) {
+                    continue;
+                }
+
+                costs.insert(neighbour, new_cost);
+                open.push(Integration {
+                    cost: new_cost,
+                    coord: neighbour,
+                });
+            }
+        }
+
+        let flow = costs
+            .keys()
+            .filter(|coord| !goals.contains(*coord))
+            .filter_map(|coord| {
+                NEIGHBOURS
+                    .iter()
+                    .map(|offset| *coord + *offset)
+                    .filter(|neighbour| {
+                        let offset = *neighbour - *coord;
+                        offset.x == 0
+                            || offset.y == 0
+                            || (costs.contains_key(&(*coord + IVec2::new(offset.x, 0)))
+                                && costs.contains_key(&(*coord + IVec2::new(0, offset.y))))
+                    })
+                    .filter_map(|neighbour| Some((neighbour, *costs.get(&neighbour)?)))
+                    .min_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .map(|(neighbour, _)| (*coord, neighbour))
+            })
+            .collect();
+
+        FlowField {
+            min: *min,
+            max: *max,
+            costs,
+            flow,
+        }
+    }
+
+    /// The first and last tiles of the area the fields were built over.
+    pub fn area(&self) -> (IVec2, IVec2) {
+        (self.min, self.max)
+    }
+
+    /// Whether the tile is in the area the fields were built over.
+    pub fn contains(&self, coord: &IVec2) -> bool {
+        coord.cmpge(self.min).all() && coord.cmple(self.max).all()
+    }
+
+    /// The cost of the cheapest path from the tile to the goal.
+    pub fn cost(&self, coord: &IVec2) -> Option<f32> {
+        self.costs.get(coord).copied()
+    }
+
+    /// The tile to walk to next from the tile to get to the goal.
+    pub fn next(&self, coord: &IVec2) -> Option<IVec2> {
+        self.flow.get(coord).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flow_field_around_wall() {
+        // A wall at x = 2 from y = -3 to y = 3.
+        let cost = |coord: &IVec2| match coord.x == 2 && coord.y.abs() <= 3 {
+            true => None,
+            false => Some(1.0),
+        };
+        let goal = IVec2::new(4, 0);
+        let field = FlowField::new(&[goal], &IVec2::splat(-8), &IVec2::splat(8), cost);
+
+        assert_eq!(field.cost(&goal), Some(0.0));
+        assert_eq!(field.cost(&IVec2::new(2, 0)), None);
+        assert!(field.contains(&IVec2::new(-8, 8)));
+        assert!(!field.contains(&IVec2::new(-9, 0)));
+
+        // Following the flow from any tile gets to the goal without walking on the wall.
+        for start in [IVec2::ZERO, IVec2::new(-8, -8), IVec2::new(1, 3)] {
+            let mut coord = start;
+            let mut steps = 0;
+            while coord != goal {
+                let next = field.next(&coord).unwrap();
+                assert!(cost(&next).is_some());
+                assert!(field.cost(&next).unwrap() < field.cost(&coord).unwrap());
+                coord = next;
+                steps += 1;
+                assert!(steps < 64);
+            }
+        }
+    }
+
+    #[test]
+    fn test_flow_field_goals() {
+        let goals = [IVec2::new(4, 0), IVec2::new(-4, 0)];
+        let field = FlowField::new(&goals, &IVec2::splat(-8), &IVec2::splat(8), |_| Some(1.0));
+
+        // Every tile heads to the goal closest to it.
+        assert_eq!(field.cost(&IVec2::new(-4, 0)), Some(0.0));
+        assert_eq!(field.next(&IVec2::new(2, 0)), Some(IVec2::new(3, 0)));
+        assert_eq!(field.next(&IVec2::new(-2, 0)), Some(IVec2::new(-3, 0)));
+        assert_eq!(field.next(&IVec2::new(4, 0)), None);
+    }
+
+    #[test]
+    fn test_flow_field_unreachable() {
+        let cost = |coord: &IVec2| match coord.abs().max_element() == 3 {
+            true => None,
+            false => Some(1.0),
+        };
+        let field = FlowField::new(&[IVec2::ZERO], &IVec2::splat(-8), &IVec2::splat(8), cost);
+
+        assert!(field.next(&IVec2::new(1, 1)).is_some());
+        assert_eq!(field.cost(&IVec2::new(5, 0)), None);
+        assert_eq!(field.next(&IVec2::new(5, 0)), None);
+    }
+}
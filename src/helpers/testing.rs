@@ -0,0 +1,143 @@
+use std::{fmt::Debug, time::Duration};
+
+use bevy::{asset::ron, prelude::*, utils::HashMap};
+use serde::de::DeserializeOwned;
+
+use crate::{
+    building::{BuildingKind, ProductionSettings},
+    core::{Alert, GameAssets},
+    fog::FogOfWar,
+    goods::{Goods, Storage},
+    population::Population,
+    terrain::{ChunkCoord, ChunkManager, TileKind, TileMapping},
+};
+
+/// Builds the headless apps the tests run their systems in. Every app has the resources most
+/// systems read and the assets the units are drawn with, and its clock only moves when advanced.
+pub struct TestApp {
+    app: App,
+}
+
+impl Default for TestApp {
+    fn default() -> Self {
+        let mut app = App::new();
+        app.add_event::<Alert>()
+            .init_resource::<Time>()
+            .init_resource::<Assets<Mesh>>()
+            .init_resource::<Assets<StandardMaterial>>()
+            .init_resource::<ChunkManager>()
+            .init_resource::<ProductionSettings>()
+            .init_resource::<Population>();
+
+        Self { app }
+    }
+}
+
+impl TestApp {
+    /// Run the systems on every update.
+    pub fn systems<M>(mut self, systems: impl IntoSystemConfigs<M>) -> Self {
+        self.app.add_systems(Update, systems);
+        self
+    }
+
+    pub fn plugins<M>(mut self, plugins: impl bevy::app::Plugins<M>) -> Self {
+        self.app.add_plugins(plugins);
+        self
+    }
+
+    pub fn event<E: Event>(mut self) -> Self {
+        self.app.add_event::<E>();
+        self
+    }
+
+    pub fn resource<R: Resource>(mut self, resource: R) -> Self {
+        self.app.insert_resource(resource);
+        self
+    }
+
+    pub fn init_resource<R: Resource + FromWorld>(mut self) -> Self {
+        self.app.init_resource::<R>();
+        self
+    }
+
+    /// Explore the tiles around a global tile coordinate.
+    pub fn explored(mut self, coord: IVec2, radius: u32) -> Self {
+        self.app.init_resource::<FogOfWar>();
+        self.app
+            .world
+            .resource_mut::<FogOfWar>()
+            .explore(&coord, radius);
+        self
+    }
+
+    /// Spawn the chunk at the origin, covered in grass.
+    pub fn grass(mut self) -> Self {
+        let size = self.app.world.resource::<ChunkManager>().size();
+        let tiles = TileMapping(vec![TileKind::Grass; (size.x * size.y) as usize]);
+        let chunk = self
+            .app
+            .world
+            .spawn((ChunkCoord(IVec2::ZERO), tiles, SpatialBundle::default()))
+            .id();
+        self.app
+            .world
+            .resource_mut::<ChunkManager>()
+            .insert(IVec2::ZERO, chunk);
+        self
+    }
+
+    /// Give the app placeholder game assets, with a model for each kind of building.
+    pub fn game_assets(mut self, buildings: &[BuildingKind]) -> Self {
+        self.app.insert_resource(GameAssets {
+            tiles: Vec::new(),
+            tree: default(),
+            tree_dead: default(),
+            rock: default(),
+            buildings: HashMap::from_iter(buildings.iter().map(|kind| (kind.clone(), default()))),
+            ui_buildings: HashMap::default(),
+            world_gen: default(),
+            production: default(),
+            raids: default(),
+            quota: default(),
+        });
+        self
+    }
+
+    pub fn build(self) -> App {
+        self.app
+    }
+}
+
+/// Move the clock of the app on and run its systems once.
+pub fn advance(app: &mut App, seconds: f32) {
+    app.world
+        .resource_mut::<Time>()
+        .advance_by(Duration::from_secs_f32(seconds));
+    app.update();
+}
+
+/// Spawn a storage at a position on the ground, holding the goods.
+pub fn spawn_storage(app: &mut App, position: Vec2, capacity: u32, goods: &Goods) -> Entity {
+    let mut storage = Storage::new(capacity);
+    storage.store(goods);
+    let transform = Transform::from_translation(position.extend(0.0).xzy());
+    app.world
+        .spawn((storage, transform, GlobalTransform::from(transform)))
+        .id()
+}
+
+/// The goods held by a storage.
+pub fn stored(app: &App, storage: Entity) -> Goods {
+    app.world.get::<Storage>(storage).unwrap().goods.clone()
+}
+
+/// Check that a settings asset, at a path inside `assets`, holds the default settings, so the
+/// game plays the same before the asset is loaded.
+pub fn assert_asset_matches_default<T: DeserializeOwned + Default + PartialEq + Debug>(path: &str) {
+    let path = format!("{}/assets/{}", env!("CARGO_MANIFEST_DIR"), path);
+    let contents = std::fs::read_to_string(path).unwrap();
+
+    let settings = ron::de::from_str::<T>(&contents).unwrap();
+
+    assert_eq!(settings, T::default());
+}
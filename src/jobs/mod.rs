@@ -0,0 +1,671 @@
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, utils::HashSet};
+
+use crate::{
+    building::{
+        Building, BuildingBuilder, BuildingKind, BuildingPriority, BuildingUpgrading,
+        BuildingWorkers, ProductionSettings, ResourceClaimed,
+    },
+    core::GameStates,
+    fog::Vision,
+    goods::{Goods, Storage},
+    population::Population,
+    selection::Selectable,
+    terrain::{ChunkManager, ResourceKind},
+    units::{
+        Unit, UnitCargo, UnitMeshes, UnitProgress, UnitState, UnitVelocity, UnitWaypointAction,
+        UnitWaypoints, UNIT_SELECT_RADIUS, UNIT_SPEED, UNIT_VISION,
+    },
+};
+
+/// How many goods a worker carries from a full storage to another one.
+pub const HAUL_AMOUNT: u32 = 10;
+/// How many tiles farther a worker walks for a job of a building one priority higher.
+const PRIORITY_DISTANCE: f32 = 24.0;
+/// How far, in world units, from its building the first worker comes out. Later ones come out
+/// further away, so they keep their distance.
+const WORKER_SPAWN_RADIUS: f32 = 4.0;
+/// The angle, in radians, that spreads points around a circle most evenly.
+const GOLDEN_ANGLE: f32 = 2.399_963;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JobKind {
+    /// Gather the resource and bring the goods to the storage with space closest to it.
+    Gather {
+        resource: Entity,
+        goods: Goods,
+        work_time: f32,
+    },
+    /// Carry goods from one storage to another.
+    Haul {
+        from: Entity,
+        to: Entity,
+        goods: Goods,
+    },
+    /// Stay at the building until its upgrade is built.
+    Build,
+}
+
+/// Something a building needs a worker for. Jobs are entities of their own, so that workers and
+/// buildings can refer to them.
+#[derive(Component, Debug, Clone)]
+pub struct Job {
+    pub kind: JobKind,
+    /// The building that posted the job, whose priority it has.
+    pub building: Entity,
+    /// Where the worker goes first.
+    pub position: Vec2,
+}
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobState {
+    /// Posted and waiting for a worker.
+    Open,
+    /// A worker is on the way to start it.
+    Claimed(Entity),
+    /// A worker is doing it.
+    InProgress(Entity),
+    Completed,
+    /// The job can't be done anymore, e.g. because its resource is gone.
+    Failed,
+}
+
+impl JobState {
+    pub fn worker(&self) -> Option<Entity> {
+        match self {
+            JobState::Claimed(worker) | JobState::InProgress(worker) => Some(*worker),
+            _ => None,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, JobState::Completed | JobState::Failed)
+    }
+}
+
+/// A citizen without a building to work at, who takes the jobs the buildings post.
+#[derive(Component)]
+pub struct Worker;
+
+/// The job the worker is doing.
+#[derive(Component, Deref)]
+pub struct WorkerJob(pub Entity);
+
+pub struct JobsPlugin;
+
+impl Plugin for JobsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            Update,
+            (
+                sync_workers,
+                post_haul_jobs,
+                post_build_jobs,
+                (update_job_states, release_workers, finish_jobs, assign_jobs).chain(),
+            )
+                .run_if(in_state(GameStates::Playing)),
+        );
+    }
+}
+
+/// How much a worker wants a job: the higher the priority of its building and the closer it is,
+/// the better. `distance` is in tiles.
+pub fn job_score(priority: BuildingPriority, distance: f32) -> f32 {
+    priority.rank() as f32 * PRIORITY_DISTANCE - distance
+}
+
+/// Keep a worker for every free citizen. New workers come out of a house, or the first storage
+/// while there is none, spread around it so they don't all stand on one spot. Only idle workers
+/// leave, the ones with a job, goods to bring back or somewhere to walk to finish first.
+fn sync_workers(
+    mut commands: Commands,
+    q_workers: Query<(Entity, &UnitCargo, &UnitWaypoints, Has<WorkerJob>), With<Worker>>,
+    q_buildings: Query<(&GlobalTransform, &BuildingKind, Has<Storage>), With<Building>>,
+    production: Res<ProductionSettings>,
+    population: Res<Population>,
+    mut unit_meshes: UnitMeshes,
+) {
+    let target = population.free() as usize;
+    let count = q_workers.iter().count();
+
+    if count > target {
+        let idle = q_workers.iter().filter(|(_, cargo, waypoints, has_job)| {
+            !has_job && cargo.is_empty() && waypoints.is_empty()
+        });
+        for (worker, ..) in idle.take(count - target) {
+            commands.entity(worker).despawn_recursive();
+        }
+        return;
+    }
+
+    let home = q_buildings
+        .iter()
+        .find(|(_, kind, _)| production.housing(kind) > 0)
+        .or_else(|| q_buildings.iter().find(|(_, _, is_storage)| *is_storage));
+    let Some((home, ..)) = home else {
+        return;
+    };
+    let point = home.translation().xz();
+
+    for index in count..target {
+        let position = point + worker_spawn_offset(index);
+
+        commands.spawn((
+            Unit,
+            Worker,
+            UnitVelocity(UNIT_SPEED),
+            Vision(UNIT_VISION),
+            Selectable(UNIT_SELECT_RADIUS),
+            UnitWaypoints(VecDeque::new()),
+            UnitCargo::default(),
+            UnitState::default(),
+            UnitProgress::default(),
+            unit_meshes.bundle(Color::WHITE, position),
+        ));
+    }
+}
+
+/// Where the worker with this number comes out, around the building it comes out of. Each one
+/// is turned by the golden angle from the last, so no two workers share a spot.
+fn worker_spawn_offset(index: usize) -> Vec2 {
+    let angle = index as f32 * GOLDEN_ANGLE;
+    let radius = WORKER_SPAWN_RADIUS * (1.0 + index as f32).sqrt();
+
+    Vec2::from_angle(angle) * radius
+}
+
+/// Full storages post a job to carry some of their most plentiful good to the closest storage
+/// with plenty of room, one job at a time.
+fn post_haul_jobs(
+    mut commands: Commands,
+    q_storages: Query<(Entity, &GlobalTransform, &Storage)>,
+    q_jobs: Query<&Job>,
+) {
+    let hauling = q_jobs
+        .iter()
+        .filter_map(|job| match job.kind {
+            JobKind::Haul { from, .. } => Some(from),
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    for (from, transform, storage) in q_storages.iter() {
+        if storage.space() > 0 || hauling.contains(&from) {
+            continue;
+        }
+        let position = transform.translation().xz();
+
+        // The storage it goes to keeps room to spare, so the goods are not carried back.
+        let to = q_storages
+            .iter()
+            .filter(|(_, _, other)| other.space() >= HAUL_AMOUNT * 2)
+            .map(|(entity, transform, _)| (entity, transform.translation().xz()))
+            .min_by(|(_, a), (_, b)| a.distance(position).total_cmp(&b.distance(position)));
+        let Some((to, _)) = to else {
+            continue;
+        };
+        let Some((good, amount)) = storage
+            .goods
+            .iter()
+            .filter(|(_, amount)| **amount > 0)
+            .max_by_key(|(_, amount)| **amount)
+        else {
+            continue;
+        };
+
+        commands.spawn((
+            Job {
+                kind: JobKind::Haul {
+                    from,
+                    to,
+                    goods: Goods::from_iter([(good.clone(), (*amount).min(HAUL_AMOUNT))]),
+                },
+                building: from,
+                position,
+            },
+            JobState::Open,
+        ));
+    }
+}
+
+/// The buildings being upgraded.
+type UpgradingBuildings<'w, 's> =
+    Query<'w, 's, (Entity, &'static GlobalTransform), (With<Building>, With<BuildingUpgrading>)>;
+
+/// Upgrading buildings post a job for a worker to build the upgrade.
+fn post_build_jobs(mut commands: Commands, q_buildings: UpgradingBuildings, q_jobs: Query<&Job>) {
+    let building = q_jobs
+        .iter()
+        .filter(|job| matches!(job.kind, JobKind::Build))
+        .map(|job| job.building)
+        .collect::<HashSet<_>>();
+
+    for (entity, transform) in q_buildings.iter() {
+        if building.contains(&entity) {
+            continue;
+        }
+
+        commands.spawn((
+            Job {
+                kind: JobKind::Build,
+                building: entity,
+                position: transform.translation().xz(),
+            },
+            JobState::Open,
+        ));
+    }
+}
+
+/// Fail the jobs whose targets are gone, give back the jobs of workers that left and finish the
+/// build jobs of the upgrades that are done.
+pub(crate) fn update_job_states(
+    mut commands: Commands,
+    mut q_jobs: Query<(&Job, &mut JobState)>,
+    q_workers: Query<(), With<Worker>>,
+    q_buildings: Query<(Has<BuildingUpgrading>, Has<BuildingBuilder>), With<Building>>,
+    q_resources: Query<(), With<ResourceKind>>,
+    q_storages: Query<(), With<Storage>>,
+) {
+    for (job, mut state) in q_jobs.iter_mut() {
+        if state.is_finished() {
+            continue;
+        }
+
+        if state
+            .worker()
+            .is_some_and(|worker| !q_workers.contains(worker))
+        {
+            *state = match (*state, &job.kind) {
+                (JobState::Claimed(_), _) => JobState::Open,
+                // The upgrade stops until another worker comes to build it.
+                (JobState::InProgress(_), JobKind::Build) => {
+                    if let Some(mut building) = commands.get_entity(job.building) {
+                        building.remove::<BuildingBuilder>();
+                    }
+                    JobState::Open
+                }
+                _ => JobState::Failed,
+            };
+        }
+
+        let is_valid = match &job.kind {
+            // The resource is gone once the worker gathered it.
+            JobKind::Gather { resource, .. } => {
+                matches!(*state, JobState::InProgress(_)) || q_resources.contains(*resource)
+            }
+            JobKind::Haul { from, to, .. } => {
+                q_storages.contains(*from) && q_storages.contains(*to)
+            }
+            JobKind::Build => q_buildings.contains(job.building),
+        };
+        if !is_valid {
+            *state = JobState::Failed;
+            continue;
+        }
+
+        if let (JobKind::Build, Ok((is_upgrading, has_builder))) =
+            (&job.kind, q_buildings.get(job.building))
+        {
+            if !is_upgrading {
+                *state = JobState::Completed;
+            } else if matches!(*state, JobState::InProgress(_)) && !has_builder {
+                commands.entity(job.building).insert(BuildingBuilder);
+            }
+        }
+    }
+}
+
+/// Workers whose job is finished are idle again. The ones of a failed job stop where they are.
+fn release_workers(
+    mut commands: Commands,
+    mut q_workers: Query<(Entity, &WorkerJob, &mut UnitWaypoints), With<Worker>>,
+    q_jobs: Query<&JobState>,
+) {
+    for (worker, job, mut waypoints) in q_workers.iter_mut() {
+        let state = q_jobs.get(**job).ok();
+        if state.is_some_and(|state| !state.is_finished()) {
+            continue;
+        }
+
+        if !matches!(state, Some(JobState::Completed)) {
+            waypoints.clear();
+        }
+        commands.entity(worker).remove::<WorkerJob>();
+    }
+}
+
+/// Remove the finished jobs, giving back what they held on to.
+fn finish_jobs(
+    mut commands: Commands,
+    q_jobs: Query<(Entity, &Job, &JobState)>,
+    mut q_buildings: Query<&mut BuildingWorkers>,
+) {
+    for (job, Job { kind, building, .. }, state) in q_jobs.iter() {
+        if !state.is_finished() {
+            continue;
+        }
+
+        match kind {
+            JobKind::Gather { resource, .. } => {
+                if let Ok(mut workers) = q_buildings.get_mut(*building) {
+                    **workers = workers.saturating_sub(1);
+                }
+                if let (JobState::Failed, Some(mut resource)) =
+                    (state, commands.get_entity(*resource))
+                {
+                    resource.remove::<ResourceClaimed>();
+                }
+            }
+            JobKind::Build => {
+                if let Some(mut building) = commands.get_entity(*building) {
+                    building.remove::<BuildingBuilder>();
+                }
+            }
+            JobKind::Haul { .. } => {}
+        }
+        commands.entity(job).despawn();
+    }
+}
+
+/// The workers without a job, with what they carry.
+type IdleWorkers<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Transform,
+        &'static mut UnitWaypoints,
+        &'static UnitCargo,
+    ),
+    (With<Worker>, Without<WorkerJob>),
+>;
+
+/// Give every idle worker the open job with the best score, with a waypoint at every place it
+/// does something at: the job, and the storage it brings the goods to. The way between them is
+/// found by the flow fields as the worker walks. Workers walking somewhere on the orders of the
+/// player, or bringing back goods, are left alone.
+fn assign_jobs(
+    mut commands: Commands,
+    mut q_workers: IdleWorkers,
+    mut q_jobs: Query<(Entity, &Job, &mut JobState)>,
+    q_priorities: Query<&BuildingPriority>,
+    q_storages: Query<(Entity, &GlobalTransform, &Storage)>,
+    chunk_manager: Res<ChunkManager>,
+) {
+    let tile_size = chunk_manager.tile_size();
+    let mut workers = q_workers
+        .iter_mut()
+        .filter(|(_, _, waypoints, cargo)| waypoints.is_empty() && cargo.is_empty())
+        .collect::<Vec<_>>();
+    workers.sort_by_key(|(worker, ..)| *worker);
+
+    for (worker, transform, waypoints, _) in workers.iter_mut() {
+        let point = transform.translation.xz();
+        let best = q_jobs
+            .iter_mut()
+            .filter(|(_, _, state)| matches!(**state, JobState::Open))
+            .map(|(entity, job, state)| {
+                let priority = q_priorities.get(job.building).copied().unwrap_or_default();
+                let distance = point.distance(job.position) / tile_size.x.max(tile_size.y);
+                (job_score(priority, distance), entity, job, state)
+            })
+            .max_by(|(a, ..), (b, ..)| a.total_cmp(b));
+        let Some((_, job_entity, job, mut state)) = best else {
+            break;
+        };
+
+        let planned = match &job.kind {
+            JobKind::Gather {
+                resource,
+                goods,
+                work_time,
+            } => {
+                let storage = q_storages
+                    .iter()
+                    .filter(|(_, _, storage)| storage.space() > 0)
+                    .map(|(entity, transform, _)| (entity, transform.translation().xz()))
+                    .min_by(|(_, a), (_, b)| {
+                        a.distance(job.position)
+                            .total_cmp(&b.distance(job.position))
+                    });
+
+                storage.map(|(storage, storage_position)| {
+                    VecDeque::from([
+                        (
+                            job.position,
+                            vec![
+                                UnitWaypointAction::StartJob(job_entity),
+                                UnitWaypointAction::Gather(*resource, goods.clone()),
+                                UnitWaypointAction::Work(*work_time),
+                            ],
+                        ),
+                        (
+                            storage_position,
+                            vec![
+                                UnitWaypointAction::Deposit(storage),
+                                UnitWaypointAction::CompleteJob(job_entity),
+                            ],
+                        ),
+                    ])
+                })
+            }
+            JobKind::Haul { from, to, goods } => {
+                q_storages.get(*to).ok().map(|(_, transform, _)| {
+                    VecDeque::from([
+                        (
+                            job.position,
+                            vec![
+                                UnitWaypointAction::StartJob(job_entity),
+                                UnitWaypointAction::Take(*from, goods.clone()),
+                            ],
+                        ),
+                        (
+                            transform.translation().xz(),
+                            vec![
+                                UnitWaypointAction::Deposit(*to),
+                                UnitWaypointAction::CompleteJob(job_entity),
+                            ],
+                        ),
+                    ])
+                })
+            }
+            JobKind::Build => Some(VecDeque::from([(
+                job.position,
+                vec![UnitWaypointAction::StartJob(job_entity)],
+            )])),
+        };
+
+        // A job that can't be planned, e.g. because every storage is full, is posted again later.
+        let Some(planned) = planned else {
+            *state = JobState::Failed;
+            continue;
+        };
+
+        ***waypoints = planned;
+        *state = JobState::Claimed(*worker);
+        commands.entity(*worker).insert(WorkerJob(job_entity));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::testing::{spawn_storage, TestApp};
+
+    /// A headless app running the job life cycle, from assigning the jobs to releasing the workers.
+    fn jobs_app() -> App {
+        TestApp::default()
+            .systems((update_job_states, release_workers, finish_jobs, assign_jobs).chain())
+            .build()
+    }
+
+    fn spawn_worker(app: &mut App, position: Vec2) -> Entity {
+        app.world
+            .spawn((
+                Unit,
+                Worker,
+                Transform::from_translation(position.extend(2.0).xzy()),
+                UnitWaypoints(VecDeque::new()),
+                UnitCargo::default(),
+            ))
+            .id()
+    }
+
+    /// A gathering building with a job posted for a tree, and a storage to bring the logs to.
+    fn post_gather_job(app: &mut App) -> (Entity, Entity, Entity) {
+        let building = app
+            .world
+            .spawn((BuildingPriority::default(), BuildingWorkers(1)))
+            .id();
+        let resource = app.world.spawn((ResourceKind::Tree, ResourceClaimed)).id();
+        spawn_storage(app, Vec2::new(64.0, 0.0), 10, &Goods::default());
+        let job = app
+            .world
+            .spawn((
+                Job {
+                    kind: JobKind::Gather {
+                        resource,
+                        goods: Goods::from_iter([("logs", 2)]),
+                        work_time: 1.0,
+                    },
+                    building,
+                    position: Vec2::new(32.0, 0.0),
+                },
+                JobState::Open,
+            ))
+            .id();
+
+        (building, resource, job)
+    }
+
+    #[test]
+    fn test_job_completed() {
+        let mut app = jobs_app();
+        let (building, _, job) = post_gather_job(&mut app);
+        let worker = spawn_worker(&mut app, Vec2::ZERO);
+
+        app.update();
+
+        // The worker goes to the job, then to the storage.
+        assert_eq!(
+            app.world.get::<JobState>(job),
+            Some(&JobState::Claimed(worker))
+        );
+        assert_eq!(
+            app.world.get::<WorkerJob>(worker).map(|job| **job),
+            Some(job)
+        );
+        let waypoints = app.world.get::<UnitWaypoints>(worker).unwrap();
+        let points = waypoints
+            .iter()
+            .map(|(point, _)| *point)
+            .collect::<Vec<_>>();
+        assert_eq!(points, vec![Vec2::new(32.0, 0.0), Vec2::new(64.0, 0.0)]);
+
+        *app.world.get_mut::<JobState>(job).unwrap() = JobState::Completed;
+        app.update();
+
+        assert!(app.world.get_entity(job).is_none());
+        assert!(app.world.get::<WorkerJob>(worker).is_none());
+        assert_eq!(**app.world.get::<BuildingWorkers>(building).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_job_failed() {
+        let mut app = jobs_app();
+        let (building, resource, job) = post_gather_job(&mut app);
+        let worker = spawn_worker(&mut app, Vec2::ZERO);
+        app.update();
+
+        // The tree was cut down by someone else before the worker got there.
+        app.world.despawn(resource);
+        app.update();
+
+        assert!(app.world.get_entity(job).is_none());
+        assert!(app.world.get::<WorkerJob>(worker).is_none());
+        assert!(app.world.get::<UnitWaypoints>(worker).unwrap().is_empty());
+        assert_eq!(**app.world.get::<BuildingWorkers>(building).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_job_worker_despawned() {
+        let mut app = jobs_app();
+        let (building, resource, job) = post_gather_job(&mut app);
+        let worker = spawn_worker(&mut app, Vec2::ZERO);
+        app.update();
+
+        // A claimed job goes back to the other workers when its worker leaves.
+        app.world.despawn(worker);
+        let other = spawn_worker(&mut app, Vec2::ZERO);
+        app.update();
+
+        assert_eq!(
+            app.world.get::<JobState>(job),
+            Some(&JobState::Claimed(other))
+        );
+        assert_eq!(
+            app.world.get::<WorkerJob>(other).map(|job| **job),
+            Some(job)
+        );
+
+        // Once started it can't be, since the worker took the goods with it.
+        *app.world.get_mut::<JobState>(job).unwrap() = JobState::InProgress(other);
+        app.world.despawn(other);
+        app.update();
+
+        assert!(app.world.get_entity(job).is_none());
+        assert!(app.world.get::<ResourceClaimed>(resource).is_none());
+        assert_eq!(**app.world.get::<BuildingWorkers>(building).unwrap(), 0);
+
+        // A started upgrade waits for the next worker, without anyone building it meanwhile.
+        let worker = spawn_worker(&mut app, Vec2::ZERO);
+        let upgrading = app
+            .world
+            .spawn((
+                Building,
+                BuildingUpgrading(Timer::from_seconds(10.0, TimerMode::Once)),
+                BuildingBuilder,
+            ))
+            .id();
+        let job = app
+            .world
+            .spawn((
+                Job {
+                    kind: JobKind::Build,
+                    building: upgrading,
+                    position: Vec2::ZERO,
+                },
+                JobState::InProgress(worker),
+            ))
+            .id();
+        app.world.despawn(worker);
+        app.update();
+
+        assert_eq!(app.world.get::<JobState>(job), Some(&JobState::Open));
+        assert!(app.world.get::<BuildingBuilder>(upgrading).is_none());
+    }
+
+    #[test]
+    fn test_job_score() {
+        // A job of a high priority building is worth walking farther for.
+        assert!(
+            job_score(BuildingPriority::High, 30.0) > job_score(BuildingPriority::Normal, 10.0)
+        );
+        assert!(
+            job_score(BuildingPriority::Normal, 30.0) < job_score(BuildingPriority::Normal, 10.0)
+        );
+        assert!(job_score(BuildingPriority::Low, 0.0) < job_score(BuildingPriority::Normal, 20.0));
+    }
+
+    #[test]
+    fn test_worker_spawn_offset() {
+        let offsets = (0..16).map(worker_spawn_offset).collect::<Vec<_>>();
+
+        for (index, offset) in offsets.iter().enumerate() {
+            assert!(offsets[..index]
+                .iter()
+                .all(|other| other.distance(*offset) >= 1.0));
+        }
+    }
+}
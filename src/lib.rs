@@ -2,13 +2,24 @@
 
 extern crate test;
 
-pub mod core;
 pub(crate) mod building;
-pub(crate) mod helpers;
-pub(crate) mod terrain;
 pub(crate) mod camera;
-pub(crate) mod ui;
+pub(crate) mod combat;
+pub mod core;
+pub(crate) mod fog;
+pub(crate) mod goods;
+pub(crate) mod helpers;
+pub(crate) mod jobs;
+pub(crate) mod military;
+pub(crate) mod minimap;
+pub(crate) mod population;
 pub(crate) mod quota;
+pub(crate) mod raids;
+pub(crate) mod roads;
+pub(crate) mod selection;
+pub(crate) mod spatial;
+pub mod terrain;
+pub(crate) mod ui;
 pub(crate) mod units;
 
 #[cfg(feature = "debug")]
@@ -0,0 +1,423 @@
+use std::collections::VecDeque;
+
+use bevy::{prelude::*, utils::HashSet};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    building::{
+        Building, BuildingKind, BuildingProducing, BuildingUpgrading, ProductionSettings,
+        STORAGE_REACH,
+    },
+    combat::{Armor, ArmorKind, Attack, AttackKind, Faction, Health},
+    core::GameStates,
+    fog::Vision,
+    goods::{storages_in_reach, take_from_storages, Goods, Storage},
+    population::Population,
+    selection::{Selectable, Selected},
+    terrain::ChunkManager,
+    units::{
+        Unit, UnitCargo, UnitMeshes, UnitMoveOrder, UnitProgress, UnitState, UnitVelocity,
+        UnitWaypoints, UNIT_SELECT_RADIUS, UNIT_VISION,
+    },
+};
+
+/// How many soldiers a building can have queued for training, including the one in training.
+pub const TRAINING_QUEUE_LIMIT: usize = 5;
+
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum SoldierKind {
+    Swordsman,
+    Archer,
+}
+
+impl SoldierKind {
+    pub const ALL: [SoldierKind; 2] = [SoldierKind::Swordsman, SoldierKind::Archer];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            SoldierKind::Swordsman => "Swordsman",
+            SoldierKind::Archer => "Archer",
+        }
+    }
+
+    /// The key that trains a soldier of this kind at the selected building.
+    pub fn key(&self) -> KeyCode {
+        match self {
+            SoldierKind::Swordsman => KeyCode::KeyF,
+            SoldierKind::Archer => KeyCode::KeyG,
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            SoldierKind::Swordsman => Color::rgb(0.8, 0.2, 0.2),
+            SoldierKind::Archer => Color::rgb(0.2, 0.5, 0.8),
+        }
+    }
+}
+
+/// How tough a soldier is and how it fights.
+#[derive(Component, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SoldierStats {
+    pub health: u32,
+    /// The damage of each attack, before the armor of the target.
+    pub damage: u32,
+    pub attack: AttackKind,
+    /// How far, in tiles, the attacks of the soldier reach.
+    pub range: f32,
+    /// How many attacks the soldier makes per second.
+    pub attack_speed: f32,
+    /// How much is taken off the damage of every attack the soldier takes.
+    pub armor: u32,
+    pub armor_kind: ArmorKind,
+    /// How fast the soldier walks, in world units per second.
+    pub speed: f32,
+}
+
+impl SoldierStats {
+    /// The health, armor and attack of a soldier with these stats, ready to fight.
+    pub fn combat(&self) -> (Health, Armor, Attack) {
+        (
+            Health::new(self.health),
+            Armor {
+                kind: self.armor_kind,
+                value: self.armor,
+            },
+            Attack::new(
+                self.attack,
+                self.damage,
+                self.range,
+                1.0 / self.attack_speed,
+            ),
+        )
+    }
+}
+
+/// What training a soldier of a kind takes, and what it is like.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SoldierRules {
+    /// The goods paid when the soldier is queued.
+    pub cost: Goods,
+    /// How many seconds the training takes.
+    pub time: f32,
+    pub stats: SoldierStats,
+}
+
+/// A citizen who fights instead of working. Soldiers only move where they are ordered to.
+#[derive(Component)]
+pub struct Soldier;
+
+/// The soldier was ordered to attack-move to the point: it fights the enemies it meets on the
+/// way instead of walking past them.
+#[derive(Component, Debug, Deref)]
+pub struct AttackMove(pub Vec2);
+
+/// The soldiers a building was told to train, already paid for, in order. The first one is in
+/// training, and leaves once its time is up and a free citizen can join.
+#[derive(Component, Debug, Default)]
+pub struct TrainingQueue {
+    pub soldiers: VecDeque<SoldierKind>,
+    /// How many seconds the first soldier has been training.
+    pub elapsed: f32,
+    /// The first soldier is trained and waits for a free citizen.
+    pub waiting: bool,
+}
+
+/// Queue a soldier for training at a building.
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrainSoldier {
+    pub building: Entity,
+    pub kind: SoldierKind,
+}
+
+pub struct MilitaryPlugin;
+
+impl Plugin for MilitaryPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<TrainSoldier>().add_systems(
+            Update,
+            (
+                send_train_orders,
+                queue_soldiers,
+                train_soldiers,
+                handle_attack_moves,
+            )
+                .chain()
+                .run_if(in_state(GameStates::Playing)),
+        );
+    }
+}
+
+/// The selected buildings, with their kind.
+type SelectedBuildings<'w, 's> =
+    Query<'w, 's, (Entity, &'static BuildingKind), (With<Building>, With<Selected>)>;
+
+/// Train a soldier at the selected buildings with the key of its kind.
+fn send_train_orders(
+    input: Res<ButtonInput<KeyCode>>,
+    q_selected: SelectedBuildings,
+    production: Res<ProductionSettings>,
+    mut orders: EventWriter<TrainSoldier>,
+) {
+    for kind in SoldierKind::ALL {
+        if !input.just_pressed(kind.key()) {
+            continue;
+        }
+
+        for (building, building_kind) in q_selected.iter() {
+            if production.trains(building_kind).contains(&kind) {
+                orders.send(TrainSoldier { building, kind });
+            }
+        }
+    }
+}
+
+/// Pay for the soldiers from the storages in reach of their building and queue them, while there
+/// is room in the queue.
+fn queue_soldiers(
+    mut commands: Commands,
+    mut orders: EventReader<TrainSoldier>,
+    mut q_buildings: Query<(&GlobalTransform, &BuildingKind, &mut TrainingQueue), With<Building>>,
+    mut q_storages: Query<(&GlobalTransform, &mut Storage)>,
+    chunk_manager: Res<ChunkManager>,
+    production: Res<ProductionSettings>,
+) {
+    let tile_size = chunk_manager.tile_size();
+    let reach = STORAGE_REACH as f32 * tile_size.x.max(tile_size.y);
+
+    for TrainSoldier { building, kind } in orders.read() {
+        let Ok((transform, building_kind, mut queue)) = q_buildings.get_mut(*building) else {
+            continue;
+        };
+        let Some(rules) = production.soldier(kind) else {
+            continue;
+        };
+        if !production.trains(building_kind).contains(kind)
+            || queue.soldiers.len() >= TRAINING_QUEUE_LIMIT
+        {
+            continue;
+        }
+
+        let point = transform.translation().xz();
+        let mut storages = storages_in_reach(q_storages.iter_mut(), &point, reach);
+        if !take_from_storages(&mut storages, &rules.cost) {
+            warn!("Not enough goods to train a {}", kind.name().to_lowercase());
+            continue;
+        }
+
+        queue.soldiers.push_back(*kind);
+        // The soldier was paid for, so undoing the placement would no longer refund everything.
+        commands.entity(*building).insert(BuildingProducing);
+    }
+}
+
+/// The buildings training soldiers, unless they are being upgraded.
+type TrainingBuildings<'w, 's> = Query<
+    'w,
+    's,
+    (&'static GlobalTransform, &'static mut TrainingQueue),
+    (With<Building>, Without<BuildingUpgrading>),
+>;
+
+/// Train the first soldier of every queue, and send it out next to its building once a free
+/// citizen joins. Buildings being upgraded don't train.
+fn train_soldiers(
+    mut commands: Commands,
+    mut q_buildings: TrainingBuildings,
+    production: Res<ProductionSettings>,
+    mut population: ResMut<Population>,
+    mut unit_meshes: UnitMeshes,
+    time: Res<Time>,
+) {
+    for (transform, mut queue) in q_buildings.iter_mut() {
+        let Some(kind) = queue.soldiers.front().copied() else {
+            continue;
+        };
+        let Some(rules) = production.soldier(&kind) else {
+            queue.soldiers.pop_front();
+            continue;
+        };
+
+        queue.elapsed += time.delta_seconds();
+        if queue.elapsed < rules.time {
+            continue;
+        }
+
+        let waiting = !population.recruit();
+        if queue.waiting != waiting {
+            queue.waiting = waiting;
+        }
+        if waiting {
+            continue;
+        }
+
+        queue.soldiers.pop_front();
+        queue.elapsed = 0.0;
+
+        let point = transform.translation().xz();
+        commands.spawn((
+            (
+                Unit,
+                Soldier,
+                kind,
+                rules.stats.clone(),
+                UnitVelocity(rules.stats.speed),
+                Vision(UNIT_VISION),
+                Selectable(UNIT_SELECT_RADIUS),
+            ),
+            UnitWaypoints(VecDeque::new()),
+            UnitCargo::default(),
+            UnitState::default(),
+            UnitProgress::default(),
+            Faction::Player,
+            rules.stats.combat(),
+            unit_meshes.bundle(kind.color(), point),
+        ));
+    }
+}
+
+/// Soldiers remember whether their last order was an attack-move until they get there.
+fn handle_attack_moves(
+    mut commands: Commands,
+    mut orders: EventReader<UnitMoveOrder>,
+    q_soldiers: Query<(Entity, &UnitWaypoints, Has<AttackMove>), With<Soldier>>,
+) {
+    let mut ordered = HashSet::new();
+    for order in orders.read() {
+        for unit in order.units.iter() {
+            if !q_soldiers.contains(*unit) {
+                continue;
+            }
+            ordered.insert(*unit);
+
+            match order.attack {
+                true => commands.entity(*unit).insert(AttackMove(order.target)),
+                false => commands.entity(*unit).remove::<AttackMove>(),
+            };
+        }
+    }
+
+    for (soldier, waypoints, is_attack_moving) in q_soldiers.iter() {
+        // Soldiers ordered this frame don't have the waypoint of the order yet.
+        if is_attack_moving && waypoints.is_empty() && !ordered.contains(&soldier) {
+            commands.entity(soldier).remove::<AttackMove>();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::testing::{advance, spawn_storage, stored, TestApp};
+
+    /// A headless app queuing and training soldiers, with barracks next to a storage.
+    fn training_app(goods: Goods) -> (App, Entity, Entity) {
+        let mut app = TestApp::default()
+            .event::<TrainSoldier>()
+            .systems((queue_soldiers, train_soldiers).chain())
+            .build();
+        let storage = spawn_storage(&mut app, Vec2::ZERO, 100, &goods);
+        let barracks = app
+            .world
+            .spawn((
+                Building,
+                BuildingKind::Barracks,
+                GlobalTransform::default(),
+                TrainingQueue::default(),
+            ))
+            .id();
+
+        (app, barracks, storage)
+    }
+
+    fn soldiers(app: &mut App) -> usize {
+        app.world
+            .query_filtered::<(), With<Soldier>>()
+            .iter(&app.world)
+            .count()
+    }
+
+    #[test]
+    fn test_queue_soldiers() {
+        let cost = ProductionSettings::default()
+            .soldier(&SoldierKind::Swordsman)
+            .unwrap()
+            .cost
+            .clone();
+        let mut goods = Goods::default();
+        for _ in 0..TRAINING_QUEUE_LIMIT + 1 {
+            goods.add(&cost);
+        }
+        let (mut app, barracks, storage) = training_app(goods);
+
+        // An archer needs logs, which the storage doesn't have.
+        app.world.send_event(TrainSoldier {
+            building: barracks,
+            kind: SoldierKind::Archer,
+        });
+        app.update();
+        assert!(app
+            .world
+            .get::<TrainingQueue>(barracks)
+            .unwrap()
+            .soldiers
+            .is_empty());
+        assert!(app.world.get::<BuildingProducing>(barracks).is_none());
+
+        for _ in 0..TRAINING_QUEUE_LIMIT + 1 {
+            app.world.send_event(TrainSoldier {
+                building: barracks,
+                kind: SoldierKind::Swordsman,
+            });
+        }
+        app.update();
+
+        // Only the soldiers that fit in the queue are paid for.
+        assert_eq!(
+            app.world
+                .get::<TrainingQueue>(barracks)
+                .unwrap()
+                .soldiers
+                .len(),
+            TRAINING_QUEUE_LIMIT
+        );
+        assert_eq!(stored(&app, storage), cost);
+        assert!(app.world.get::<BuildingProducing>(barracks).is_some());
+    }
+
+    #[test]
+    fn test_train_soldiers() {
+        let (mut app, barracks, _) = training_app(Goods::default());
+        app.insert_resource(Population {
+            citizens: 1,
+            employed: 1,
+            ..default()
+        });
+        app.world
+            .get_mut::<TrainingQueue>(barracks)
+            .unwrap()
+            .soldiers
+            .push_back(SoldierKind::Swordsman);
+        let time = ProductionSettings::default()
+            .soldier(&SoldierKind::Swordsman)
+            .unwrap()
+            .time;
+
+        advance(&mut app, time / 2.0);
+        assert_eq!(soldiers(&mut app), 0);
+        assert!(!app.world.get::<TrainingQueue>(barracks).unwrap().waiting);
+
+        // Trained, but every citizen is busy.
+        advance(&mut app, time / 2.0);
+        assert_eq!(soldiers(&mut app), 0);
+        assert!(app.world.get::<TrainingQueue>(barracks).unwrap().waiting);
+
+        app.world.resource_mut::<Population>().employed = 0;
+        advance(&mut app, 0.1);
+        assert_eq!(soldiers(&mut app), 1);
+        assert_eq!(app.world.resource::<Population>().soldiers, 1);
+        let queue = app.world.get::<TrainingQueue>(barracks).unwrap();
+        assert!(queue.soldiers.is_empty());
+        assert!(!queue.waiting);
+    }
+}
@@ -0,0 +1,501 @@
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::RenderAssetUsages,
+        render_resource::{Extent3d, TextureDimension, TextureFormat},
+        texture::ImageSampler,
+    },
+    ui::RelativeCursorPosition,
+    utils::HashMap,
+};
+
+use crate::{
+    building::Building,
+    camera::FocusCamera,
+    combat::Faction,
+    core::GameStates,
+    fog::{FogOfWar, HiddenInFog, TileVisibility},
+    helpers,
+    selection::Selected,
+    terrain::{
+        tile_color, ChunkCoord, ChunkManager, ResourceKind, TileKind, TileMapping, CHUNK_SIZE,
+    },
+    units::{Unit, UnitMoveOrder},
+};
+
+/// How many chunks around the center of the explored area the minimap shows.
+const MINIMAP_CHUNK_RADIUS: i32 = 8;
+/// The size of the minimap on the screen, in pixels.
+const MINIMAP_NODE_SIZE: f32 = 220.0;
+/// How often, in seconds, the minimap image is redrawn.
+const MINIMAP_REFRESH_TIME: f32 = 0.1;
+/// How far, in world units, the frustum is drawn for screen corners that look above the horizon.
+const MINIMAP_FRUSTUM_DISTANCE: f32 = 4096.0;
+
+const UNEXPLORED_COLOR: [u8; 4] = [0, 0, 0, 255];
+const BUILDING_COLOR: [u8; 4] = [255, 255, 255, 255];
+const UNIT_COLOR: [u8; 4] = [250, 210, 40, 255];
+const ENEMY_COLOR: [u8; 4] = [220, 30, 30, 255];
+const FRUSTUM_COLOR: [u8; 4] = [255, 255, 255, 255];
+/// How much darker the explored tiles that aren't visible are drawn.
+const EXPLORED_BRIGHTNESS: f32 = 0.45;
+
+#[derive(Component)]
+struct MinimapNode;
+
+/// The image shown by the minimap and the tiles of the chunks it shows that were generated so far.
+#[derive(Resource)]
+struct Minimap {
+    image: Handle<Image>,
+    /// The chunk in the middle of the minimap.
+    center: IVec2,
+    /// The global coordinate of the tile in the top left pixel.
+    origin: IVec2,
+    size: UVec2,
+    tiles: HashMap<IVec2, Vec<TileKind>>,
+    timer: Timer,
+}
+
+impl Minimap {
+    /// Show the chunks around another chunk, dropping the tiles of the ones that aren't shown
+    /// anymore.
+    fn recenter(&mut self, center: IVec2) {
+        self.center = center;
+        self.origin = window_origin(&center);
+        self.tiles
+            .retain(|chunk_coord, _| shows(&center, chunk_coord));
+    }
+
+    fn global_coord_to_pixel(&self, global_coord: &IVec2) -> IVec2 {
+        *global_coord - self.origin
+    }
+
+    /// The global coordinate under a point of the minimap, with `(0, 0)` the top left corner and
+    /// `(1, 1)` the bottom right one.
+    fn normalized_to_global_coord(&self, normalized: &Vec2) -> IVec2 {
+        let pixel = (*normalized * self.size.as_vec2()).floor().as_ivec2();
+
+        self.origin + pixel.clamp(IVec2::ZERO, self.size.as_ivec2() - 1)
+    }
+}
+
+pub struct MinimapPlugin;
+
+impl Plugin for MinimapPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(OnEnter(GameStates::Playing), setup_minimap)
+            .add_systems(
+                Update,
+                (cache_minimap_tiles, draw_minimap, handle_minimap_clicks)
+                    .chain()
+                    .run_if(in_state(GameStates::Playing)),
+            );
+    }
+}
+
+/// The global coordinate of the top left tile of the minimap centred on a chunk.
+fn window_origin(center: &IVec2) -> IVec2 {
+    helpers::geometry::tile_coord_to_global_coord(
+        &UVec2::ZERO,
+        &(*center - MINIMAP_CHUNK_RADIUS),
+        &UVec2::splat(CHUNK_SIZE as u32),
+    )
+}
+
+/// Whether the minimap centred on a chunk shows another one.
+fn shows(center: &IVec2, chunk_coord: &IVec2) -> bool {
+    (*chunk_coord - *center).abs().max_element() <= MINIMAP_CHUNK_RADIUS
+}
+
+fn setup_minimap(mut commands: Commands, mut images: ResMut<Assets<Image>>) {
+    let chunk_size = UVec2::splat(CHUNK_SIZE as u32);
+    let chunks = (2 * MINIMAP_CHUNK_RADIUS + 1) as u32;
+    let size = chunk_size * chunks;
+
+    let mut image = Image::new_fill(
+        Extent3d {
+            width: size.x,
+            height: size.y,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &UNEXPLORED_COLOR,
+        TextureFormat::Rgba8UnormSrgb,
+        RenderAssetUsages::default(),
+    );
+    image.sampler = ImageSampler::nearest();
+    let image = images.add(image);
+
+    commands.insert_resource(Minimap {
+        image: image.clone(),
+        center: IVec2::ZERO,
+        origin: window_origin(&IVec2::ZERO),
+        size,
+        tiles: HashMap::new(),
+        timer: Timer::from_seconds(MINIMAP_REFRESH_TIME, TimerMode::Repeating),
+    });
+
+    commands.spawn((
+        MinimapNode,
+        Interaction::default(),
+        RelativeCursorPosition::default(),
+        ImageBundle {
+            style: Style {
+                position_type: PositionType::Absolute,
+                right: Val::Px(10.0),
+                bottom: Val::Px(10.0),
+                width: Val::Px(MINIMAP_NODE_SIZE),
+                height: Val::Px(MINIMAP_NODE_SIZE),
+                border: UiRect::all(Val::Px(3.0)),
+                ..default()
+            },
+            background_color: Color::WHITE.into(),
+            image: UiImage::new(image),
+            ..default()
+        },
+        BorderColor(Color::BLACK),
+    ));
+}
+
+/// Keep the minimap centred on the explored area, and the tiles of the chunks it shows. The
+/// chunks further out aren't drawn, so they aren't kept, however far the player explores.
+fn cache_minimap_tiles(
+    q_chunks: Query<(&ChunkCoord, Ref<TileMapping>)>,
+    fog: Res<FogOfWar>,
+    chunk_manager: Res<ChunkManager>,
+    mut minimap: ResMut<Minimap>,
+) {
+    let center = fog.explored_bounds().map_or(IVec2::ZERO, |bounds| {
+        helpers::geometry::global_coord_to_chunk_coord(&bounds.center(), &chunk_manager.size())
+    });
+    let recentered = center != minimap.center;
+    if recentered {
+        minimap.recenter(center);
+    }
+
+    for (chunk_coord, mapping) in q_chunks.iter() {
+        if !(recentered || mapping.is_changed()) || !shows(&center, chunk_coord) {
+            continue;
+        }
+        minimap.tiles.insert(**chunk_coord, mapping.to_vec());
+    }
+}
+
+/// The buildings and units marked on the minimap.
+type Markers<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static GlobalTransform,
+        Option<&'static Faction>,
+        Has<Building>,
+        Has<HiddenInFog>,
+    ),
+    Or<(With<Building>, With<Unit>)>,
+>;
+
+fn draw_minimap(
+    mut minimap: ResMut<Minimap>,
+    mut images: ResMut<Assets<Image>>,
+    fog: Res<FogOfWar>,
+    chunk_manager: Res<ChunkManager>,
+    q_camera: Query<(&Camera, &GlobalTransform)>,
+    q_markers: Markers,
+    time: Res<Time>,
+) {
+    if !minimap.timer.tick(time.delta()).just_finished() {
+        return;
+    }
+    let Some(image) = images.get_mut(&minimap.image) else {
+        return;
+    };
+
+    let size = chunk_manager.size();
+    let tile_size = chunk_manager.tile_size();
+    let to_pixel = |position: Vec2| {
+        let global_coord =
+            helpers::geometry::world_pos_to_global_coord(&position, &size, &tile_size);
+        minimap.global_coord_to_pixel(&global_coord)
+    };
+
+    for pixel in image.data.chunks_exact_mut(4) {
+        pixel.copy_from_slice(&UNEXPLORED_COLOR);
+    }
+
+    let width = minimap.size.x as usize;
+    for (chunk_coord, tiles) in minimap.tiles.iter() {
+        let Some(visibility) = fog.chunk(chunk_coord) else {
+            continue;
+        };
+
+        for (index, (tile, visibility)) in tiles.iter().zip(visibility.iter()).enumerate() {
+            let tile_coord = helpers::geometry::index_to_tile_coord(index, &size);
+            let global_coord =
+                helpers::geometry::tile_coord_to_global_coord(&tile_coord, chunk_coord, &size);
+            let pixel = minimap.global_coord_to_pixel(&global_coord);
+            if !in_bounds(&pixel, &minimap.size) {
+                continue;
+            }
+
+            let offset = (pixel.y as usize * width + pixel.x as usize) * 4;
+            image.data[offset..offset + 4].copy_from_slice(&tile_pixel(tile, visibility));
+        }
+    }
+
+    for (transform, faction, is_building, hidden_in_fog) in q_markers.iter() {
+        let position = transform.translation().xz();
+        let global_coord =
+            helpers::geometry::world_pos_to_global_coord(&position, &size, &tile_size);
+        if hidden_in_fog && !fog.is_visible(&global_coord) {
+            continue;
+        }
+
+        // Buildings are drawn as 3x3 squares and units as 2x2 ones.
+        let pixel = minimap.global_coord_to_pixel(&global_coord);
+        let (range, color) = match (is_building, faction) {
+            (true, _) => (-1..=1, BUILDING_COLOR),
+            (false, Some(Faction::Barbarians)) => (0..=1, ENEMY_COLOR),
+            (false, _) => (0..=1, UNIT_COLOR),
+        };
+        for y in range.clone() {
+            for x in range.clone() {
+                put_pixel(
+                    &mut image.data,
+                    &minimap.size,
+                    pixel + IVec2::new(x, y),
+                    color,
+                );
+            }
+        }
+    }
+
+    let corners = q_camera
+        .get_single()
+        .ok()
+        .and_then(|(camera, camera_transform)| camera_ground_corners(camera, camera_transform));
+    if let Some(corners) = corners {
+        let corners = corners.map(to_pixel);
+        for (index, start) in corners.iter().enumerate() {
+            let end = corners[(index + 1) % corners.len()];
+            draw_line(&mut image.data, &minimap.size, *start, end, FRUSTUM_COLOR);
+        }
+    }
+}
+
+fn handle_minimap_clicks(
+    q_minimap: Query<(&Interaction, &RelativeCursorPosition), With<MinimapNode>>,
+    q_selected_units: Query<Entity, (With<Unit>, With<Selected>)>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    minimap: Res<Minimap>,
+    chunk_manager: Res<ChunkManager>,
+    mut focus_events: EventWriter<FocusCamera>,
+    mut move_orders: EventWriter<UnitMoveOrder>,
+) {
+    let Ok((interaction, cursor)) = q_minimap.get_single() else {
+        return;
+    };
+    let Some(normalized) = cursor.normalized else {
+        return;
+    };
+
+    let global_coord = minimap.normalized_to_global_coord(&normalized);
+    let position = helpers::geometry::global_coord_to_world_pos(
+        &global_coord,
+        &chunk_manager.size(),
+        &chunk_manager.tile_size(),
+    );
+
+    // Holding the left button drags the camera around, even when leaving the minimap.
+    if matches!(interaction, Interaction::Pressed) {
+        focus_events.send(FocusCamera(position));
+    }
+
+    if cursor.mouse_over() && mouse_button_input.just_pressed(MouseButton::Right) {
+        let units = q_selected_units.iter().collect::<Vec<_>>();
+        if !units.is_empty() {
+            move_orders.send(UnitMoveOrder {
+                units,
+                target: position,
+                attack: false,
+            });
+        }
+    }
+}
+
+/// The points on the ground seen in the corners of the screen, clockwise from the top left.
+fn camera_ground_corners(camera: &Camera, camera_transform: &GlobalTransform) -> Option<[Vec2; 4]> {
+    let viewport = camera.logical_viewport_size()?;
+    let corners = [
+        Vec2::ZERO,
+        Vec2::new(viewport.x, 0.0),
+        viewport,
+        Vec2::new(0.0, viewport.y),
+    ];
+
+    let mut points = [Vec2::ZERO; 4];
+    for (point, corner) in points.iter_mut().zip(corners) {
+        let ray = camera.viewport_to_world(camera_transform, corner)?;
+        let distance = ray
+            .intersect_plane(Vec3::ZERO, Plane3d::new(Vec3::Y))
+            .unwrap_or(MINIMAP_FRUSTUM_DISTANCE);
+
+        *point = ray.get_point(distance.min(MINIMAP_FRUSTUM_DISTANCE)).xz();
+    }
+
+    Some(points)
+}
+
+/// The colour of a tile on the minimap, darkened by the fog of war.
+fn tile_pixel(tile: &TileKind, visibility: &TileVisibility) -> [u8; 4] {
+    let brightness = match visibility {
+        TileVisibility::Unexplored => return UNEXPLORED_COLOR,
+        TileVisibility::Explored => EXPLORED_BRIGHTNESS,
+        TileVisibility::Visible => 1.0,
+    };
+
+    let [r, g, b] = tile_color(tile, &ResourceKind::None).0;
+    let shade = |channel: u8| (channel as f32 * brightness) as u8;
+
+    [shade(r), shade(g), shade(b), 255]
+}
+
+fn in_bounds(pixel: &IVec2, size: &UVec2) -> bool {
+    pixel.cmpge(IVec2::ZERO).all() && pixel.cmplt(size.as_ivec2()).all()
+}
+
+fn put_pixel(data: &mut [u8], size: &UVec2, pixel: IVec2, color: [u8; 4]) {
+    if !in_bounds(&pixel, size) {
+        return;
+    }
+
+    let offset = (pixel.y as usize * size.x as usize + pixel.x as usize) * 4;
+    data[offset..offset + 4].copy_from_slice(&color);
+}
+
+/// A line between two pixels, skipping the parts outside of the image.
+fn draw_line(data: &mut [u8], size: &UVec2, start: IVec2, end: IVec2, color: [u8; 4]) {
+    for pixel in helpers::geometry::line_coords(&start, &end) {
+        put_pixel(data, size, pixel, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::testing::TestApp;
+
+    fn pixel_at(data: &[u8], size: &UVec2, pixel: IVec2) -> [u8; 4] {
+        let offset = (pixel.y as usize * size.x as usize + pixel.x as usize) * 4;
+        data[offset..offset + 4].try_into().unwrap()
+    }
+
+    #[test]
+    fn test_draw_line_covers_endpoints() {
+        let size = UVec2::new(8, 8);
+        let mut data = vec![0; (size.x * size.y * 4) as usize];
+
+        draw_line(
+            &mut data,
+            &size,
+            IVec2::new(1, 6),
+            IVec2::new(6, 2),
+            FRUSTUM_COLOR,
+        );
+
+        assert_eq!(pixel_at(&data, &size, IVec2::new(1, 6)), FRUSTUM_COLOR);
+        assert_eq!(pixel_at(&data, &size, IVec2::new(6, 2)), FRUSTUM_COLOR);
+        assert_eq!(data.chunks(4).filter(|p| *p == FRUSTUM_COLOR).count(), 6);
+    }
+
+    #[test]
+    fn test_draw_line_clips_outside_image() {
+        let size = UVec2::new(4, 4);
+        let mut data = vec![0; (size.x * size.y * 4) as usize];
+
+        draw_line(
+            &mut data,
+            &size,
+            IVec2::new(-10, 1),
+            IVec2::new(10, 1),
+            FRUSTUM_COLOR,
+        );
+
+        assert_eq!(data.chunks(4).filter(|p| *p == FRUSTUM_COLOR).count(), 4);
+    }
+
+    #[test]
+    fn test_minimap_normalized_to_global_coord() {
+        let minimap = Minimap {
+            image: Handle::default(),
+            center: IVec2::ZERO,
+            origin: IVec2::new(-16, -16),
+            size: UVec2::splat(32),
+            tiles: HashMap::new(),
+            timer: Timer::from_seconds(MINIMAP_REFRESH_TIME, TimerMode::Repeating),
+        };
+
+        assert_eq!(
+            minimap.normalized_to_global_coord(&Vec2::ZERO),
+            IVec2::new(-16, -16)
+        );
+        assert_eq!(
+            minimap.normalized_to_global_coord(&Vec2::splat(0.5)),
+            IVec2::ZERO
+        );
+        assert_eq!(
+            minimap.normalized_to_global_coord(&Vec2::ONE),
+            IVec2::new(15, 15)
+        );
+        assert_eq!(
+            minimap.global_coord_to_pixel(&IVec2::ZERO),
+            IVec2::splat(16)
+        );
+    }
+
+    #[test]
+    fn test_cache_minimap_tiles_only_shown_chunks() {
+        let mut app = TestApp::default()
+            .init_resource::<FogOfWar>()
+            .resource(Minimap {
+                image: Handle::default(),
+                center: IVec2::ZERO,
+                origin: window_origin(&IVec2::ZERO),
+                size: UVec2::ONE,
+                tiles: HashMap::new(),
+                timer: Timer::from_seconds(MINIMAP_REFRESH_TIME, TimerMode::Repeating),
+            })
+            .systems(cache_minimap_tiles)
+            .build();
+
+        let shown = IVec2::splat(-MINIMAP_CHUNK_RADIUS);
+        let hidden = IVec2::new(MINIMAP_CHUNK_RADIUS + 1, 0);
+        app.world
+            .spawn((ChunkCoord(shown), TileMapping(vec![TileKind::Grass])));
+        app.world
+            .spawn((ChunkCoord(hidden), TileMapping(vec![TileKind::Grass])));
+        app.update();
+
+        let minimap = app.world.resource::<Minimap>();
+        assert!(minimap.tiles.contains_key(&shown));
+        assert!(!minimap.tiles.contains_key(&hidden));
+
+        // Exploring further east moves the minimap along, and clicks on it with it.
+        let size = UVec2::splat(CHUNK_SIZE as u32);
+        let east =
+            helpers::geometry::tile_coord_to_global_coord(&UVec2::ZERO, &IVec2::new(3, 0), &size);
+        app.world
+            .resource_mut::<FogOfWar>()
+            .explore(&IVec2::ZERO, 2);
+        app.world.resource_mut::<FogOfWar>().explore(&east, 2);
+        app.update();
+
+        let minimap = app.world.resource::<Minimap>();
+        assert_eq!(minimap.center, IVec2::new(1, 0));
+        assert!(minimap.tiles.contains_key(&hidden));
+        assert!(!minimap.tiles.contains_key(&shown));
+        assert_eq!(
+            minimap.normalized_to_global_coord(&Vec2::ZERO),
+            window_origin(&IVec2::new(1, 0))
+        );
+    }
+}
@@ -0,0 +1,374 @@
+use bevy::prelude::*;
+
+use crate::{
+    building::{
+        Building, BuildingKind, BuildingLevel, BuildingOrder, BuildingStaff, BuildingUnderstaffed,
+        ProductionSettings,
+    },
+    core::{Alert, AlertKind, GameStates},
+    goods::{take_any_from_storages, Storage},
+};
+
+/// How many citizens the empire starts with.
+pub const STARTING_CITIZENS: u32 = 4;
+/// How many citizens live in the camp before any house is built.
+pub const BASE_HOUSING: u32 = 4;
+/// How many seconds it takes for a new citizen to arrive while there is room for them.
+const POPULATION_GROWTH_TIME: f32 = 20.0;
+/// How many seconds pass between the meals of the citizens, at which each eats one food.
+const MEAL_TIME: f32 = 30.0;
+/// How fast the citizens work when they got nothing to eat at their last meal.
+const HUNGRY_WORK_SPEED: f32 = 0.5;
+/// After how many short meals in a row no new citizens arrive.
+const STARVING_MEALS: u32 = 2;
+
+/// The citizens of the empire. Every building that harvests or processes goods needs some of
+/// them to work there.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct Population {
+    pub citizens: u32,
+    /// How many citizens the houses shelter.
+    pub capacity: u32,
+    /// How many citizens work at a building.
+    pub employed: u32,
+    /// How many citizens joined the army. They don't work, but still need a home and food.
+    pub soldiers: u32,
+    /// How much of the food the citizens needed at their last meal they got, from 0 to 1.
+    pub fed: f32,
+    /// How many meals in a row the citizens didn't get all the food they needed.
+    pub short_meals: u32,
+}
+
+impl Population {
+    /// The citizens without a building to work at.
+    pub fn free(&self) -> u32 {
+        self.citizens.saturating_sub(self.employed + self.soldiers)
+    }
+
+    /// The citizens that can work at a building, which soldiers don't.
+    pub fn civilians(&self) -> u32 {
+        self.citizens.saturating_sub(self.soldiers)
+    }
+
+    /// A free citizen joins the army, if there is one.
+    pub fn recruit(&mut self) -> bool {
+        if self.free() == 0 {
+            return false;
+        }
+
+        self.soldiers += 1;
+        true
+    }
+
+    /// A citizen died, leaving the army with it if they were a soldier.
+    pub fn lose(&mut self, is_soldier: bool) {
+        self.citizens = self.citizens.saturating_sub(1);
+        if is_soldier {
+            self.soldiers = self.soldiers.saturating_sub(1);
+        }
+    }
+
+    /// A citizen arrives if there is room and food for them, or leaves if there are more
+    /// citizens than homes, e.g. after a house was demolished. Soldiers don't leave the army, so
+    /// only civilians leave.
+    pub fn grow(&mut self) {
+        if self.citizens < self.capacity && !self.is_starving() {
+            self.citizens += 1;
+        } else if self.citizens > self.capacity && self.civilians() > 0 {
+            self.citizens -= 1;
+        }
+    }
+
+    /// The citizens had a meal, eating `eaten` of the food they needed.
+    pub fn eat(&mut self, eaten: u32) {
+        self.fed = match self.citizens {
+            0 => 1.0,
+            citizens => (eaten as f32 / citizens as f32).min(1.0),
+        };
+        self.short_meals = match eaten < self.citizens {
+            true => self.short_meals + 1,
+            false => 0,
+        };
+    }
+
+    /// Whether the citizens went hungry long enough that no new ones arrive.
+    pub fn is_starving(&self) -> bool {
+        self.short_meals >= STARVING_MEALS
+    }
+
+    /// Multiplier of how fast the citizens work, slower the less they got at their last meal.
+    pub fn work_speed(&self) -> f32 {
+        HUNGRY_WORK_SPEED + (1.0 - HUNGRY_WORK_SPEED) * self.fed
+    }
+}
+
+impl Default for Population {
+    fn default() -> Self {
+        Population {
+            citizens: STARTING_CITIZENS,
+            capacity: BASE_HOUSING,
+            employed: 0,
+            soldiers: 0,
+            fed: 1.0,
+            short_meals: 0,
+        }
+    }
+}
+
+#[derive(Resource, Deref, DerefMut)]
+struct PopulationGrowth(Timer);
+
+impl Default for PopulationGrowth {
+    fn default() -> Self {
+        PopulationGrowth(Timer::from_seconds(
+            POPULATION_GROWTH_TIME,
+            TimerMode::Repeating,
+        ))
+    }
+}
+
+#[derive(Resource, Deref, DerefMut)]
+struct MealTimer(Timer);
+
+impl Default for MealTimer {
+    fn default() -> Self {
+        MealTimer(Timer::from_seconds(MEAL_TIME, TimerMode::Repeating))
+    }
+}
+
+pub struct PopulationPlugin;
+
+impl Plugin for PopulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Population>()
+            .init_resource::<PopulationGrowth>()
+            .init_resource::<MealTimer>()
+            .add_systems(
+                Update,
+                (update_housing, eat_food, grow_population, assign_staff)
+                    .chain()
+                    .run_if(in_state(GameStates::Playing)),
+            );
+    }
+}
+
+/// Split the citizens between the buildings in order, giving each as many as it needs while
+/// there are any left.
+pub fn assign_citizens(citizens: u32, needs: &[u32]) -> Vec<u32> {
+    let mut free = citizens;
+
+    needs
+        .iter()
+        .map(|need| {
+            let assigned = (*need).min(free);
+            free -= assigned;
+            assigned
+        })
+        .collect()
+}
+
+fn update_housing(
+    q_buildings: Query<&BuildingKind, With<Building>>,
+    production: Res<ProductionSettings>,
+    mut population: ResMut<Population>,
+) {
+    let capacity = BASE_HOUSING
+        + q_buildings
+            .iter()
+            .map(|kind| production.housing(kind))
+            .sum::<u32>();
+
+    if population.capacity != capacity {
+        population.capacity = capacity;
+    }
+}
+
+/// At every meal each citizen eats one food from any storage, going hungry if there is not
+/// enough of it.
+fn eat_food(
+    mut meal_timer: ResMut<MealTimer>,
+    mut q_storages: Query<&mut Storage>,
+    production: Res<ProductionSettings>,
+    mut population: ResMut<Population>,
+    mut alerts: EventWriter<Alert>,
+    time: Res<Time>,
+) {
+    if !meal_timer.tick(time.delta()).just_finished() {
+        return;
+    }
+
+    let mut storages = q_storages.iter_mut().collect::<Vec<_>>();
+    let eaten = take_any_from_storages(&mut storages, &production.food, population.citizens);
+    population.eat(eaten);
+
+    if population.short_meals > 0 {
+        alerts.send(Alert {
+            kind: AlertKind::FoodShortage,
+            position: None,
+        });
+    }
+}
+
+fn grow_population(
+    mut growth: ResMut<PopulationGrowth>,
+    mut population: ResMut<Population>,
+    time: Res<Time>,
+) {
+    if growth.tick(time.delta()).just_finished() {
+        population.grow();
+    }
+}
+
+/// The buildings, in the order they were placed, with their staff.
+type StaffedBuildings<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static BuildingOrder,
+        &'static BuildingKind,
+        &'static BuildingLevel,
+        &'static mut BuildingStaff,
+        Has<BuildingUnderstaffed>,
+    ),
+    With<Building>,
+>;
+
+/// Give the citizens to the buildings, the oldest buildings first, and mark the ones that are
+/// left without enough of them.
+fn assign_staff(
+    mut commands: Commands,
+    mut q_buildings: StaffedBuildings,
+    production: Res<ProductionSettings>,
+    mut population: ResMut<Population>,
+) {
+    let mut buildings = q_buildings.iter_mut().collect::<Vec<_>>();
+    buildings.sort_by_key(|(_, order, ..)| **order);
+
+    let needs = buildings
+        .iter()
+        .map(|(_, _, kind, level, ..)| production.staff(kind, ***level))
+        .collect::<Vec<_>>();
+    let assigned = assign_citizens(population.civilians(), &needs);
+
+    for ((building, _, _, _, staff, is_understaffed), (need, assigned)) in
+        buildings.iter_mut().zip(needs.iter().zip(assigned.iter()))
+    {
+        if ***staff != *assigned {
+            ***staff = *assigned;
+        }
+
+        match (assigned < need, *is_understaffed) {
+            (true, false) => {
+                commands.entity(*building).insert(BuildingUnderstaffed);
+            }
+            (false, true) => {
+                commands.entity(*building).remove::<BuildingUnderstaffed>();
+            }
+            _ => {}
+        }
+    }
+
+    let employed = assigned.iter().sum::<u32>();
+    if population.employed != employed {
+        population.employed = employed;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assign_citizens() {
+        assert_eq!(assign_citizens(5, &[2, 0, 3, 1]), vec![2, 0, 3, 0]);
+        assert_eq!(assign_citizens(3, &[2, 2]), vec![2, 1]);
+        assert_eq!(assign_citizens(0, &[1]), vec![0]);
+    }
+
+    #[test]
+    fn test_population_grow() {
+        let mut population = Population {
+            citizens: 3,
+            capacity: 4,
+            employed: 1,
+            ..default()
+        };
+
+        population.grow();
+        assert_eq!(population.citizens, 4);
+        assert_eq!(population.free(), 3);
+
+        population.grow();
+        assert_eq!(population.citizens, 4);
+
+        population.capacity = 2;
+        population.grow();
+        assert_eq!(population.citizens, 3);
+    }
+
+    #[test]
+    fn test_population_grow_soldiers() {
+        let mut population = Population {
+            citizens: 3,
+            capacity: 1,
+            soldiers: 2,
+            ..default()
+        };
+
+        population.grow();
+        assert_eq!(population.citizens, 2);
+
+        // Soldiers stay even without a home.
+        population.grow();
+        assert_eq!(population.citizens, 2);
+        assert_eq!(population.soldiers, 2);
+        assert_eq!(population.civilians(), 0);
+    }
+
+    #[test]
+    fn test_population_eat() {
+        let mut population = Population {
+            citizens: 4,
+            capacity: 8,
+            ..default()
+        };
+
+        population.eat(2);
+        assert_eq!(population.work_speed(), 0.75);
+        population.grow();
+        assert_eq!(population.citizens, 5);
+
+        population.eat(0);
+        assert_eq!(population.work_speed(), HUNGRY_WORK_SPEED);
+        assert!(population.is_starving());
+        population.grow();
+        assert_eq!(population.citizens, 5);
+
+        population.eat(5);
+        assert_eq!(population.work_speed(), 1.0);
+        assert!(!population.is_starving());
+    }
+
+    #[test]
+    fn test_population_recruit() {
+        let mut population = Population {
+            citizens: 3,
+            employed: 1,
+            ..default()
+        };
+
+        assert!(population.recruit());
+        assert_eq!(population.free(), 1);
+        assert_eq!(population.civilians(), 2);
+        assert!(population.recruit());
+        assert!(!population.recruit());
+        assert_eq!(population.soldiers, 2);
+        assert_eq!(population.free(), 0);
+
+        population.lose(true);
+        assert_eq!((population.citizens, population.soldiers), (2, 1));
+        population.lose(false);
+        assert_eq!((population.citizens, population.soldiers), (1, 1));
+    }
+}
@@ -1,10 +1,17 @@
-use bevy::prelude::*;
+use bevy::{ecs::system::SystemParam, prelude::*};
 
-use crate::core::GameStates;
+use crate::{
+    core::{setup_settings, update_settings, Alert, AlertKind, GameStates, RonAssetLoader},
+    goods::{take_from_storages, Goods, Stockpile, Storage},
+};
+
+pub use settings::*;
+
+mod settings;
 
 const QUOTA_TIME: f32 = 600.0;
-const QUOTA_INITIAL: u32 = 10;
-const RESOURCE_INITIAL: u32 = 5;
+/// How many seconds before the deadline the player is warned if they can't pay the quota yet.
+const QUOTA_WARNING_TIME: f32 = 60.0;
 
 #[derive(Resource, Deref, DerefMut)]
 pub struct QuotaTimer(pub Timer);
@@ -15,54 +22,110 @@ impl Default for QuotaTimer {
     }
 }
 
+/// The goods to pay at the end of the current cycle.
 #[derive(Resource, Deref, DerefMut)]
-pub struct Quota(pub u32);
+pub struct Quota {
+    /// How many quotas were paid so far.
+    pub cycle: u32,
+    #[deref]
+    pub goods: Goods,
+}
 
-impl Default for Quota {
-    fn default() -> Self {
-        Quota(QUOTA_INITIAL)
+impl Quota {
+    fn for_cycle(cycle: u32, settings: &QuotaSettings) -> Self {
+        Quota {
+            cycle,
+            goods: settings.quota(cycle),
+        }
     }
 }
 
-#[derive(Resource, Deref, DerefMut)]
-pub struct ResourceCount(pub u32);
-
-impl Default for ResourceCount {
+impl Default for Quota {
     fn default() -> Self {
-        ResourceCount(RESOURCE_INITIAL)
+        Quota::for_cycle(0, &QuotaSettings::default())
     }
 }
 
 #[derive(Resource, Default, Deref, DerefMut)]
 pub struct QuotaSuccess(pub bool);
 
+/// How many quota cycles went by, whether their quota was paid or not.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct QuotaCycles(pub u32);
+
 pub struct QuotaPlugin;
 
 impl Plugin for QuotaPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<QuotaTimer>()
+        app.init_asset::<QuotaSettings>()
+            .init_asset_loader::<RonAssetLoader<QuotaSettings>>()
+            .init_resource::<QuotaSettings>()
+            .init_resource::<QuotaTimer>()
             .init_resource::<Quota>()
-            .init_resource::<ResourceCount>()
+            .init_resource::<Stockpile>()
             .init_resource::<QuotaSuccess>()
-            .add_systems(Update, update_quota.run_if(in_state(GameStates::Playing)));
+            .init_resource::<QuotaCycles>()
+            .add_systems(
+                OnEnter(GameStates::Playing),
+                setup_settings::<QuotaSettings>,
+            )
+            .add_systems(
+                Update,
+                (
+                    update_settings::<QuotaSettings>,
+                    apply_quota_settings,
+                    update_quota,
+                )
+                    .chain()
+                    .run_if(in_state(GameStates::Playing)),
+            );
     }
 }
 
+/// Ask for the current quota by the quota settings whenever they change.
+fn apply_quota_settings(settings: Res<QuotaSettings>, mut quota: ResMut<Quota>) {
+    if settings.is_changed() {
+        *quota = Quota::for_cycle(quota.cycle, &settings);
+    }
+}
+
+/// The current quota cycle: its time, what it asks for, and how the last ones went.
+#[derive(SystemParam)]
+struct QuotaState<'w> {
+    timer: ResMut<'w, QuotaTimer>,
+    quota: ResMut<'w, Quota>,
+    success: ResMut<'w, QuotaSuccess>,
+    cycles: ResMut<'w, QuotaCycles>,
+    settings: Res<'w, QuotaSettings>,
+}
+
 fn update_quota(
     time: Res<Time>,
-    mut timer: ResMut<QuotaTimer>,
-    mut quota: ResMut<Quota>,
-    mut resource_count: ResMut<ResourceCount>,
-    mut quota_success: ResMut<QuotaSuccess>,
+    mut state: QuotaState,
+    stockpile: Res<Stockpile>,
+    mut q_storages: Query<&mut Storage>,
+    mut alerts: EventWriter<Alert>,
 ) {
-    timer.tick(time.delta());
-    if timer.finished() {
-        if **resource_count < **quota {
-            quota_success.0 = false;
+    let remaining = state.timer.remaining_secs();
+    state.timer.tick(time.delta());
+
+    let warning =
+        remaining > QUOTA_WARNING_TIME && state.timer.remaining_secs() <= QUOTA_WARNING_TIME;
+    if warning && !stockpile.contains(&state.quota) {
+        alerts.send(Alert {
+            kind: AlertKind::QuotaWarning,
+            position: None,
+        });
+    }
+
+    if state.timer.finished() {
+        **state.cycles += state.timer.times_finished_this_tick();
+        let mut storages = q_storages.iter_mut().collect::<Vec<_>>();
+        if take_from_storages(&mut storages, &state.quota) {
+            *state.quota = Quota::for_cycle(state.quota.cycle + 1, &state.settings);
+            state.success.0 = true;
         } else {
-            **resource_count -= **quota;
-            quota.0 *= 5;
-            quota_success.0 = true;
+            state.success.0 = false;
         }
     }
 }
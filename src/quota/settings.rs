@@ -0,0 +1,91 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::{GameAssets, SettingsAsset},
+    goods::Goods,
+};
+
+/// What the Emperor asks for every quota cycle, loaded from a `.quota.ron` asset.
+#[derive(Asset, Resource, TypePath, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuotaSettings {
+    /// What the Emperor asks for in the first cycles. Later cycles ask for the last one,
+    /// multiplied by `growth` for every cycle past the end of the list.
+    pub cycles: Vec<Goods>,
+    pub growth: u32,
+}
+
+impl QuotaSettings {
+    /// The goods to pay at the end of the cycle, counting from 0.
+    pub fn quota(&self, cycle: u32) -> Goods {
+        let Some(last) = self.cycles.len().checked_sub(1) else {
+            return Goods::default();
+        };
+        let index = (cycle as usize).min(last);
+        // Far enough in, the quota is more than can ever be paid: it stops growing there instead
+        // of overflowing.
+        let growth = self
+            .growth
+            .checked_pow(cycle.saturating_sub(last as u32))
+            .unwrap_or(u32::MAX);
+
+        self.cycles[index]
+            .iter()
+            .map(|(good, amount)| (good.clone(), amount.saturating_mul(growth)))
+            .collect()
+    }
+}
+
+impl Default for QuotaSettings {
+    fn default() -> Self {
+        QuotaSettings {
+            cycles: vec![
+                Goods::from_iter([("logs", 10)]),
+                Goods::from_iter([("logs", 20), ("stone", 10)]),
+                Goods::from_iter([("logs", 20), ("planks", 10)]),
+                Goods::from_iter([("planks", 20), ("blocks", 10)]),
+            ],
+            growth: 5,
+        }
+    }
+}
+
+impl SettingsAsset for QuotaSettings {
+    const EXTENSIONS: &'static [&'static str] = &["quota.ron"];
+    const NAME: &'static str = "Quota";
+
+    fn handle(game_assets: &GameAssets) -> &Handle<Self> {
+        &game_assets.quota
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::testing;
+
+    #[test]
+    fn test_quota_asset_matches_default() {
+        testing::assert_asset_matches_default::<QuotaSettings>("settings/emperor.quota.ron");
+    }
+
+    #[test]
+    fn test_quota_for_cycle() {
+        let settings = QuotaSettings::default();
+
+        assert_eq!(settings.quota(0), Goods::from_iter([("logs", 10)]));
+        assert_eq!(
+            settings.quota(3),
+            Goods::from_iter([("planks", 20), ("blocks", 10)])
+        );
+        assert_eq!(
+            settings.quota(5),
+            Goods::from_iter([("planks", 500), ("blocks", 250)])
+        );
+        // The quota stops growing instead of overflowing.
+        assert_eq!(
+            settings.quota(40),
+            Goods::from_iter([("planks", u32::MAX), ("blocks", u32::MAX)])
+        );
+    }
+}
@@ -0,0 +1,417 @@
+use std::collections::VecDeque;
+
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use crate::{
+    building::{Building, ProductionSettings},
+    combat::{AttackTarget, Faction},
+    core::{setup_settings, update_settings, Alert, AlertKind, GameStates, RonAssetLoader},
+    fog::{FogOfWar, HiddenInFog},
+    helpers,
+    military::AttackMove,
+    quota::QuotaCycles,
+    terrain::{ChunkManager, TerrainSeed, TileKind, TileMapping},
+    units::{Unit, UnitCargo, UnitMeshes, UnitProgress, UnitState, UnitVelocity, UnitWaypoints},
+};
+
+pub use settings::*;
+
+mod settings;
+
+const RAIDER_COLOR: Color = Color::rgb(0.3, 0.05, 0.05);
+
+/// A barbarian come to burn down the buildings of the player.
+#[derive(Component)]
+pub struct Raider;
+
+/// When the next raid comes, and where from once the player was warned about it.
+#[derive(Resource, Debug, Default)]
+pub struct RaidSchedule {
+    /// How many raids came so far.
+    pub raids: u32,
+    /// How many seconds went by since the last raid, or the start of the game.
+    pub elapsed: f32,
+    /// The side the next raid comes from and the tile it arrives at, decided when the player is
+    /// warned about it.
+    pub next: Option<(RaidSide, IVec2)>,
+}
+
+impl RaidSchedule {
+    /// How many seconds are left before the next raid.
+    pub fn remaining(&self, rules: &RaidRules) -> f32 {
+        let due = match self.raids {
+            0 => rules.first_raid,
+            _ => rules.interval,
+        };
+        (due - self.elapsed).max(0.0)
+    }
+}
+
+pub struct RaidsPlugin;
+
+impl Plugin for RaidsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<RaidSettings>()
+            .init_asset_loader::<RonAssetLoader<RaidSettings>>()
+            .init_resource::<RaidSettings>()
+            .init_resource::<RaidSchedule>()
+            .add_systems(OnEnter(GameStates::Playing), setup_settings::<RaidSettings>)
+            .add_systems(
+                Update,
+                (
+                    update_settings::<RaidSettings>,
+                    schedule_raids,
+                    direct_raiders,
+                )
+                    .chain()
+                    .run_if(in_state(GameStates::Playing)),
+            );
+    }
+}
+
+/// The tile a raid coming from a side arrives at: the first land tile just past the explored
+/// area, starting across from `center` and looking further along the side.
+pub fn raid_origin(
+    side: RaidSide,
+    bounds: &IRect,
+    center: &IVec2,
+    is_land: impl Fn(&IVec2) -> bool,
+) -> IVec2 {
+    let center = center.clamp(bounds.min, bounds.max);
+    let (origin, along, length) = match side {
+        RaidSide::North => (
+            IVec2::new(center.x, bounds.min.y - 1),
+            IVec2::X,
+            bounds.width(),
+        ),
+        RaidSide::South => (
+            IVec2::new(center.x, bounds.max.y + 1),
+            IVec2::X,
+            bounds.width(),
+        ),
+        RaidSide::West => (
+            IVec2::new(bounds.min.x - 1, center.y),
+            IVec2::Y,
+            bounds.height(),
+        ),
+        RaidSide::East => (
+            IVec2::new(bounds.max.x + 1, center.y),
+            IVec2::Y,
+            bounds.height(),
+        ),
+    };
+
+    (0..=length)
+        .flat_map(|step| [origin + along * step, origin - along * step])
+        .find(|coord| is_land(coord))
+        .unwrap_or(origin)
+}
+
+/// Where the raids go: the buildings of the player, and the map around the explored area the
+/// raids arrive at.
+#[derive(SystemParam)]
+struct RaidFront<'w, 's> {
+    chunk_manager: Res<'w, ChunkManager>,
+    q_mappings: Query<'w, 's, &'static TileMapping>,
+    q_buildings: Query<'w, 's, &'static GlobalTransform, With<Building>>,
+    fog: Res<'w, FogOfWar>,
+    seed: Res<'w, TerrainSeed>,
+}
+
+impl RaidFront<'_, '_> {
+    /// The side the raid with this number comes from and the tile it arrives at. There is none
+    /// while the player has no buildings, or nothing is explored.
+    fn origin(&self, direction: RaidDirection, raid: u32) -> Option<(RaidSide, IVec2)> {
+        let bounds = self.fog.explored_bounds()?;
+        let buildings = self
+            .q_buildings
+            .iter()
+            .map(|transform| transform.translation().xz())
+            .collect::<Vec<_>>();
+        if buildings.is_empty() {
+            return None;
+        }
+
+        let center = buildings.iter().sum::<Vec2>() / buildings.len() as f32;
+        let center = helpers::geometry::world_pos_to_global_coord(
+            &center,
+            &self.chunk_manager.size(),
+            &self.chunk_manager.tile_size(),
+        );
+        let side = direction.side(**self.seed, raid);
+        let is_land = |coord: &IVec2| {
+            !matches!(
+                self.chunk_manager.tile_kind(coord, &self.q_mappings),
+                Some(TileKind::Water)
+            )
+        };
+
+        Some((side, raid_origin(side, &bounds, &center, is_land)))
+    }
+
+    fn world_pos(&self, global_coord: &IVec2) -> Vec2 {
+        helpers::geometry::global_coord_to_world_pos(
+            global_coord,
+            &self.chunk_manager.size(),
+            &self.chunk_manager.tile_size(),
+        )
+    }
+}
+
+/// The raiders of the waves, which grow with every quota cycle gone by, and how they look.
+#[derive(SystemParam)]
+struct RaidWaves<'w, 's> {
+    production: Res<'w, ProductionSettings>,
+    cycles: Res<'w, QuotaCycles>,
+    unit_meshes: UnitMeshes<'w, 's>,
+}
+
+/// Warn the player of the next raid once it is close, and send its wave when it is due. The wave
+/// arrives at the edge of the explored area on its side, and grows with every quota cycle gone
+/// by. While the player has no buildings to raid, the raid that is due waits for one.
+fn schedule_raids(
+    mut commands: Commands,
+    mut schedule: ResMut<RaidSchedule>,
+    settings: Res<RaidSettings>,
+    front: RaidFront,
+    mut waves: RaidWaves,
+    mut alerts: EventWriter<Alert>,
+    time: Res<Time>,
+) {
+    let Some(rules) = settings.rules() else {
+        return;
+    };
+    schedule.elapsed += time.delta_seconds();
+
+    let remaining = schedule.remaining(rules);
+    if schedule.next.is_none() && remaining <= rules.warning {
+        if let Some((side, origin)) = front.origin(rules.direction, schedule.raids) {
+            schedule.next = Some((side, origin));
+            alerts.send(Alert {
+                kind: AlertKind::RaidWarning,
+                position: Some(front.world_pos(&origin)),
+            });
+        }
+    }
+
+    if remaining > 0.0 {
+        return;
+    }
+    let Some((side, origin)) = schedule.next.take() else {
+        return;
+    };
+    schedule.raids += 1;
+    schedule.elapsed = 0.0;
+
+    let tile_size = front.chunk_manager.tile_size();
+    let point = front.world_pos(&origin);
+    // The raiders line up along the side they come from.
+    let along = match side {
+        RaidSide::North | RaidSide::South => Vec2::X,
+        RaidSide::East | RaidSide::West => Vec2::Y,
+    };
+    let wave = rules.wave(**waves.cycles);
+    let count = wave.iter().map(|(_, count)| count).sum::<u32>();
+    info!(
+        "{} raiders coming from the {}",
+        count,
+        side.name().to_lowercase()
+    );
+
+    let raiders = wave
+        .into_iter()
+        .flat_map(|(kind, count)| std::iter::repeat_n(kind, count as usize));
+    for (index, kind) in raiders.enumerate() {
+        let Some(stats) = waves
+            .production
+            .soldier(&kind)
+            .map(|rules| rules.stats.clone())
+        else {
+            continue;
+        };
+        let offset = (index as f32 - (count as f32 - 1.0) / 2.0) * tile_size.x * 0.5;
+        let position = point + along * offset;
+
+        commands.spawn((
+            (
+                Unit,
+                Raider,
+                Faction::Barbarians,
+                stats.combat(),
+                UnitVelocity(stats.speed),
+                HiddenInFog,
+            ),
+            UnitWaypoints(VecDeque::new()),
+            UnitCargo::default(),
+            UnitState::default(),
+            UnitProgress::default(),
+            waves.unit_meshes.bundle(RAIDER_COLOR, position),
+        ));
+    }
+}
+
+/// The raiders without an enemy to fight.
+type IdleRaiders<'w, 's> = Query<
+    'w,
+    's,
+    (Entity, &'static Transform, &'static mut UnitWaypoints),
+    (With<Raider>, Without<AttackTarget>),
+>;
+
+/// Raiders with nothing to do head for the closest building of the player, fighting whatever
+/// they meet on the way.
+fn direct_raiders(
+    mut commands: Commands,
+    mut q_raiders: IdleRaiders,
+    q_buildings: Query<&GlobalTransform, With<Building>>,
+) {
+    for (raider, transform, mut waypoints) in q_raiders.iter_mut() {
+        if !waypoints.is_empty() {
+            continue;
+        }
+
+        let position = transform.translation.xz();
+        let closest = q_buildings
+            .iter()
+            .map(|transform| transform.translation().xz())
+            .min_by(|a, b| a.distance(position).total_cmp(&b.distance(position)));
+        let Some(target) = closest else {
+            continue;
+        };
+
+        waypoints.push_back((target, vec![]));
+        commands.entity(raider).insert(AttackMove(target));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::testing::{advance, TestApp};
+
+    #[test]
+    fn test_raid_origin() {
+        let bounds = IRect::new(-10, -10, 10, 20);
+        let center = IVec2::new(2, 3);
+        let land = |_: &IVec2| true;
+
+        assert_eq!(
+            raid_origin(RaidSide::North, &bounds, &center, land),
+            IVec2::new(2, -11)
+        );
+        assert_eq!(
+            raid_origin(RaidSide::South, &bounds, &center, land),
+            IVec2::new(2, 21)
+        );
+        assert_eq!(
+            raid_origin(RaidSide::West, &bounds, &center, land),
+            IVec2::new(-11, 3)
+        );
+        // A center outside the explored area is brought back onto it.
+        assert_eq!(
+            raid_origin(RaidSide::East, &bounds, &IVec2::new(0, 50), land),
+            IVec2::new(11, 20)
+        );
+
+        // Raids don't arrive on water, but on the closest land along the side.
+        let coast = |coord: &IVec2| coord.x >= 5;
+        assert_eq!(
+            raid_origin(RaidSide::North, &bounds, &center, coast),
+            IVec2::new(5, -11)
+        );
+    }
+
+    #[test]
+    fn test_raid_schedule() {
+        let settings = RaidSettings::default();
+        let rules = settings.rules().unwrap();
+        let mut schedule = RaidSchedule {
+            elapsed: 100.0,
+            ..default()
+        };
+
+        assert_eq!(schedule.remaining(rules), rules.first_raid - 100.0);
+        schedule.raids = 1;
+        assert_eq!(schedule.remaining(rules), rules.interval - 100.0);
+        schedule.elapsed = rules.interval + 1.0;
+        assert_eq!(schedule.remaining(rules), 0.0);
+    }
+
+    /// A headless app scheduling the raids of the default settings and directing the raiders,
+    /// with a building of the player in the middle of an explored area.
+    fn raids_app() -> App {
+        TestApp::default()
+            .explored(IVec2::ZERO, 4)
+            .init_resource::<RaidSettings>()
+            .init_resource::<RaidSchedule>()
+            .init_resource::<QuotaCycles>()
+            .resource(TerrainSeed(1))
+            .systems((schedule_raids, direct_raiders).chain())
+            .build()
+    }
+
+    fn raiders(app: &mut App) -> Vec<(Entity, Vec2)> {
+        app.world
+            .query_filtered::<(Entity, &Transform), With<Raider>>()
+            .iter(&app.world)
+            .map(|(raider, transform)| (raider, transform.translation.xz()))
+            .collect()
+    }
+
+    #[test]
+    fn test_raid_wave() {
+        let mut app = raids_app();
+        let rules = RaidSettings::default().rules().unwrap().clone();
+
+        // Without buildings to raid, the raid that is due waits.
+        advance(&mut app, rules.first_raid);
+        assert!(raiders(&mut app).is_empty());
+        assert_eq!(app.world.resource::<RaidSchedule>().raids, 0);
+
+        app.world.spawn((Building, GlobalTransform::default()));
+        advance(&mut app, 1.0);
+
+        let count = rules
+            .wave(0)
+            .iter()
+            .map(|(_, count)| *count as usize)
+            .sum::<usize>();
+        assert_eq!(raiders(&mut app).len(), count);
+        let schedule = app.world.resource::<RaidSchedule>();
+        assert_eq!(schedule.raids, 1);
+        assert_eq!(schedule.elapsed, 0.0);
+        assert!(schedule.next.is_none());
+    }
+
+    #[test]
+    fn test_direct_raiders() {
+        let mut app = raids_app();
+        let near = Vec2::new(64.0, 0.0);
+        app.world.spawn((
+            Building,
+            GlobalTransform::from_translation(near.extend(0.0).xzy()),
+        ));
+        app.world.spawn((
+            Building,
+            GlobalTransform::from_translation(Vec3::new(-256.0, 0.0, 0.0)),
+        ));
+        let raider = app
+            .world
+            .spawn((
+                Raider,
+                Transform::from_translation(Vec3::new(128.0, 0.0, 0.0)),
+                UnitWaypoints(VecDeque::new()),
+            ))
+            .id();
+
+        app.update();
+
+        let waypoints = app.world.get::<UnitWaypoints>(raider).unwrap();
+        assert_eq!(**waypoints, VecDeque::from([(near, vec![])]));
+        assert_eq!(
+            app.world.get::<AttackMove>(raider).map(|target| **target),
+            Some(near)
+        );
+        assert_eq!(raiders(&mut app), vec![(raider, Vec2::new(128.0, 0.0))]);
+    }
+}
@@ -0,0 +1,230 @@
+use bevy::{prelude::*, utils::HashMap};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    core::{GameAssets, SettingsAsset},
+    helpers,
+    military::SoldierKind,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+}
+
+/// A side of the explored area. North is towards negative global tile y.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum RaidSide {
+    North,
+    East,
+    South,
+    West,
+}
+
+impl RaidSide {
+    pub const ALL: [RaidSide; 4] = [
+        RaidSide::North,
+        RaidSide::East,
+        RaidSide::South,
+        RaidSide::West,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RaidSide::North => "NORTH",
+            RaidSide::East => "EAST",
+            RaidSide::South => "SOUTH",
+            RaidSide::West => "WEST",
+        }
+    }
+}
+
+/// Where the raids come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum RaidDirection {
+    /// Every raid comes from the same side.
+    Side(RaidSide),
+    /// Every raid comes from a side picked from the seed of the world and its number, so the same
+    /// raid of a world always comes from the same side.
+    Any,
+}
+
+impl RaidDirection {
+    /// The side the raid with this number, counting from 0, comes from in the world of the seed.
+    pub fn side(&self, seed: u64, raid: u32) -> RaidSide {
+        match self {
+            RaidDirection::Side(side) => *side,
+            RaidDirection::Any => {
+                let hash = helpers::hash::seed_from_coord(seed, &IVec2::new(raid as i32, 0));
+                RaidSide::ALL[(hash % RaidSide::ALL.len() as u64) as usize]
+            }
+        }
+    }
+}
+
+/// Raiders of one kind in every wave.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RaiderGroup {
+    pub kind: SoldierKind,
+    /// How many of them come with the raids of the first quota cycle.
+    pub count: u32,
+    /// How many more of them come for every quota cycle that went by.
+    pub per_cycle: u32,
+}
+
+/// How often the raids come at a difficulty, and how strong they are.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RaidRules {
+    /// How many seconds into the game the first raid comes.
+    pub first_raid: f32,
+    /// How many seconds there are between two raids after that.
+    pub interval: f32,
+    /// How many seconds before a raid the player is told where it comes from.
+    pub warning: f32,
+    pub direction: RaidDirection,
+    pub wave: Vec<RaiderGroup>,
+}
+
+impl RaidRules {
+    /// The raiders of each kind in a wave after the number of quota cycles.
+    pub fn wave(&self, cycles: u32) -> Vec<(SoldierKind, u32)> {
+        self.wave
+            .iter()
+            .map(|group| (group.kind, group.count + group.per_cycle * cycles))
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+}
+
+/// The raids of every difficulty, and the one played, loaded from a `.raids.ron` asset.
+#[derive(Asset, Resource, TypePath, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RaidSettings {
+    pub difficulty: Difficulty,
+    pub difficulties: HashMap<Difficulty, RaidRules>,
+}
+
+impl RaidSettings {
+    /// The rules of the difficulty played, if it has any. Without them there are no raids.
+    pub fn rules(&self) -> Option<&RaidRules> {
+        self.difficulties.get(&self.difficulty)
+    }
+}
+
+impl Default for RaidSettings {
+    fn default() -> Self {
+        RaidSettings {
+            difficulty: Difficulty::Normal,
+            difficulties: HashMap::from_iter([
+                (
+                    Difficulty::Easy,
+                    RaidRules {
+                        first_raid: 900.0,
+                        interval: 480.0,
+                        warning: 60.0,
+                        direction: RaidDirection::Side(RaidSide::North),
+                        wave: vec![RaiderGroup {
+                            kind: SoldierKind::Swordsman,
+                            count: 2,
+                            per_cycle: 1,
+                        }],
+                    },
+                ),
+                (
+                    Difficulty::Normal,
+                    RaidRules {
+                        first_raid: 600.0,
+                        interval: 300.0,
+                        warning: 45.0,
+                        direction: RaidDirection::Any,
+                        wave: vec![
+                            RaiderGroup {
+                                kind: SoldierKind::Swordsman,
+                                count: 3,
+                                per_cycle: 1,
+                            },
+                            RaiderGroup {
+                                kind: SoldierKind::Archer,
+                                count: 0,
+                                per_cycle: 1,
+                            },
+                        ],
+                    },
+                ),
+                (
+                    Difficulty::Hard,
+                    RaidRules {
+                        first_raid: 420.0,
+                        interval: 240.0,
+                        warning: 30.0,
+                        direction: RaidDirection::Any,
+                        wave: vec![
+                            RaiderGroup {
+                                kind: SoldierKind::Swordsman,
+                                count: 4,
+                                per_cycle: 2,
+                            },
+                            RaiderGroup {
+                                kind: SoldierKind::Archer,
+                                count: 2,
+                                per_cycle: 1,
+                            },
+                        ],
+                    },
+                ),
+            ]),
+        }
+    }
+}
+
+impl SettingsAsset for RaidSettings {
+    const EXTENSIONS: &'static [&'static str] = &["raids.ron"];
+    const NAME: &'static str = "Raid";
+
+    fn handle(game_assets: &GameAssets) -> &Handle<Self> {
+        &game_assets.raids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::testing;
+
+    #[test]
+    fn test_raid_asset_matches_default() {
+        testing::assert_asset_matches_default::<RaidSettings>("settings/barbarians.raids.ron");
+    }
+
+    #[test]
+    fn test_raid_wave() {
+        let settings = RaidSettings::default();
+        let rules = settings.rules().unwrap();
+
+        assert_eq!(rules.wave(0), vec![(SoldierKind::Swordsman, 3)]);
+        assert_eq!(
+            rules.wave(2),
+            vec![(SoldierKind::Swordsman, 5), (SoldierKind::Archer, 2)]
+        );
+    }
+
+    #[test]
+    fn test_raid_direction() {
+        assert_eq!(
+            RaidDirection::Side(RaidSide::West).side(1, 7),
+            RaidSide::West
+        );
+        // The same raid of a world always comes from the same side.
+        assert_eq!(RaidDirection::Any.side(1, 3), RaidDirection::Any.side(1, 3));
+        let sides = (0..16)
+            .map(|raid| RaidDirection::Any.side(1, raid))
+            .collect::<Vec<_>>();
+        assert!(sides.iter().any(|side| *side != sides[0]));
+        // Other worlds are raided from other sides.
+        let other = (0..16)
+            .map(|raid| RaidDirection::Any.side(2, raid))
+            .collect::<Vec<_>>();
+        assert_ne!(sides, other);
+    }
+}
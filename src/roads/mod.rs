@@ -0,0 +1,307 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::{input::common_conditions::input_just_released, prelude::*, utils::HashSet};
+
+use crate::{
+    building::{BuildGround, ProductionSettings, STORAGE_REACH},
+    core::{CursorActive, GameStates, ToolMode},
+    goods::{take_from_storages, Goods, Storage, StoragesInReach},
+    helpers::{self, camera::GroundCursor},
+    terrain::{ChunkManager, TileCoord},
+};
+
+/// How much faster units walk on road tiles.
+pub const ROAD_SPEED_BONUS: f32 = 1.5;
+/// The cost of walking onto a road tile when looking for a path, where other land costs 1.
+pub const ROAD_PATH_COST: f32 = 1.0 / ROAD_SPEED_BONUS;
+/// How high above the terrain the road decals are, to keep them from flickering with it.
+const ROAD_HEIGHT: f32 = 0.05;
+const ROAD_COLOR: Color = Color::rgb(0.45, 0.35, 0.22);
+const ROAD_VALID_COLOR: Color = Color::rgb(0.35, 0.75, 0.35);
+const ROAD_INVALID_COLOR: Color = Color::rgb(0.85, 0.25, 0.25);
+
+/// The global tile coordinates of every road tile.
+#[derive(Resource, Debug, Default, Deref, DerefMut)]
+pub struct Roads(pub HashSet<IVec2>);
+
+/// The decal of a road tile, a child of its chunk.
+#[derive(Component)]
+pub struct Road;
+
+/// The tiles of the road being dragged, in order. Empty when nothing is being dragged.
+#[derive(Resource, Debug, Default, Deref, DerefMut)]
+struct RoadDrag(Vec<IVec2>);
+
+#[derive(Resource)]
+struct RoadAssets {
+    mesh: Handle<Mesh>,
+    material: Handle<StandardMaterial>,
+}
+
+/// What happens to a tile of the dragged road when it is released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RoadTile {
+    /// A new road tile, which is paid for.
+    New,
+    /// Already a road, so it is free.
+    Existing,
+    /// Unexplored, water, an obstacle or more than the storages in reach can pay for.
+    Blocked,
+}
+
+pub struct RoadsPlugin;
+
+impl Plugin for RoadsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Roads>()
+            .init_resource::<RoadDrag>()
+            .add_systems(OnEnter(GameStates::Playing), setup_road_assets)
+            .add_systems(
+                Update,
+                (
+                    (
+                        drag_road_tool,
+                        place_road.run_if(input_just_released(MouseButton::Left)),
+                        draw_road_tool,
+                    )
+                        .chain()
+                        .run_if(|tool_mode: Res<ToolMode>| matches!(*tool_mode, ToolMode::Road)),
+                    select_road_tool,
+                )
+                    .run_if(in_state(GameStates::Playing)),
+            );
+    }
+}
+
+fn setup_road_assets(
+    mut commands: Commands,
+    chunk_manager: Res<ChunkManager>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<StandardMaterial>>,
+) {
+    let tile_size = chunk_manager.tile_size();
+
+    commands.insert_resource(RoadAssets {
+        mesh: meshes.add(Plane3d::default().mesh().size(tile_size.x, tile_size.y)),
+        material: materials.add(StandardMaterial {
+            base_color: ROAD_COLOR,
+            perceptual_roughness: 1.0,
+            ..default()
+        }),
+    });
+}
+
+fn select_road_tool(
+    mut tool_mode: ResMut<ToolMode>,
+    mut drag: ResMut<RoadDrag>,
+    input: Res<ButtonInput<KeyCode>>,
+) {
+    if input.just_pressed(KeyCode::KeyT) {
+        *tool_mode = ToolMode::Road;
+    }
+
+    if !matches!(*tool_mode, ToolMode::Road) && !drag.is_empty() {
+        drag.clear();
+    }
+}
+
+/// Start the road on the tile under the cursor and follow the cursor while the button is held.
+/// Dragging back over the road shortens it again.
+fn drag_road_tool(
+    q_camera: Query<(&Camera, &GlobalTransform)>,
+    windows: Query<&Window>,
+    mouse_button_input: Res<ButtonInput<MouseButton>>,
+    cursor_active: Res<CursorActive>,
+    chunk_manager: Res<ChunkManager>,
+    mut drag: ResMut<RoadDrag>,
+) {
+    let Ok((camera, camera_transform)) = q_camera.get_single() else {
+        return;
+    };
+    let Some(point) = helpers::camera::screen_to_world(camera, camera_transform, windows.single())
+    else {
+        return;
+    };
+
+    let size = chunk_manager.size();
+    let tile_size = chunk_manager.tile_size();
+    let coord = helpers::geometry::world_pos_to_global_coord(&point.xz(), &size, &tile_size);
+
+    if mouse_button_input.just_pressed(MouseButton::Left) && **cursor_active {
+        **drag = vec![coord];
+        return;
+    }
+
+    if !mouse_button_input.pressed(MouseButton::Left) {
+        return;
+    }
+    let Some(last) = drag.last().copied() else {
+        return;
+    };
+    if last == coord {
+        return;
+    }
+
+    match drag.iter().position(|tile| *tile == coord) {
+        Some(index) => drag.truncate(index + 1),
+        None => drag.extend(
+            helpers::geometry::line_coords(&last, &coord)
+                .into_iter()
+                .skip(1),
+        ),
+    }
+}
+
+/// Build the valid tiles of the dragged road, when the button is released.
+fn place_road(
+    mut commands: Commands,
+    mut drag: ResMut<RoadDrag>,
+    mut roads: ResMut<Roads>,
+    ground: BuildGround,
+    mut storages: StoragesInReach,
+    road_assets: Res<RoadAssets>,
+    production: Res<ProductionSettings>,
+) {
+    if drag.is_empty() {
+        return;
+    }
+    let path = std::mem::take(&mut **drag);
+
+    let chunk_manager = &ground.chunk_manager;
+    let size = chunk_manager.size();
+    let tile_size = chunk_manager.tile_size();
+    let start = helpers::geometry::global_coord_to_world_pos(&path[0], &size, &tile_size);
+    let mut storages = storages.near(&start);
+    let mut available = Goods::default();
+    for storage in storages.iter() {
+        available.add(&storage.goods);
+    }
+
+    let tiles = road_tiles(&path, &available, &production.road_cost, &roads, |coord| {
+        ground.is_free(coord)
+    });
+    let new_tiles = path
+        .iter()
+        .zip(tiles.iter())
+        .filter(|(_, tile)| matches!(tile, RoadTile::New))
+        .map(|(coord, _)| *coord)
+        .collect::<Vec<_>>();
+    if new_tiles.is_empty() {
+        return;
+    }
+
+    if !take_from_storages(
+        &mut storages,
+        &production.road_cost.times(new_tiles.len() as u32),
+    ) {
+        return;
+    }
+
+    for coord in new_tiles {
+        let chunk_coord = helpers::geometry::global_coord_to_chunk_coord(&coord, &size);
+        let Some(chunk) = chunk_manager.get(&chunk_coord) else {
+            continue;
+        };
+        let tile_coord = helpers::geometry::global_coord_to_tile_coord(&coord, &size);
+        let tile_pos = helpers::geometry::tile_coord_to_world_off(&tile_coord, &size, &tile_size);
+
+        roads.insert(coord);
+        commands.entity(*chunk).with_children(|parent| {
+            parent.spawn((
+                Road,
+                TileCoord(tile_coord),
+                PbrBundle {
+                    mesh: road_assets.mesh.clone(),
+                    material: road_assets.material.clone(),
+                    transform: Transform::from_translation(tile_pos.extend(ROAD_HEIGHT).xzy()),
+                    ..default()
+                },
+            ));
+        });
+    }
+}
+
+/// Outline the tiles of the dragged road, or the tile under the cursor, in green when they can
+/// be built and in red when they can't.
+fn draw_road_tool(
+    mut gizmos: Gizmos,
+    cursor: GroundCursor,
+    drag: Res<RoadDrag>,
+    roads: Res<Roads>,
+    ground: BuildGround,
+    q_storages: Query<(&GlobalTransform, &Storage)>,
+    production: Res<ProductionSettings>,
+) {
+    let size = ground.chunk_manager.size();
+    let tile_size = ground.chunk_manager.tile_size();
+
+    let path = match drag.is_empty() {
+        true => {
+            let Some(point) = cursor.point() else {
+                return;
+            };
+            vec![helpers::geometry::world_pos_to_global_coord(
+                &point.xz(),
+                &size,
+                &tile_size,
+            )]
+        }
+        false => drag.to_vec(),
+    };
+
+    let start = helpers::geometry::global_coord_to_world_pos(&path[0], &size, &tile_size);
+    let reach = STORAGE_REACH as f32 * tile_size.x.max(tile_size.y);
+    let mut available = Goods::default();
+    for (transform, storage) in q_storages.iter() {
+        if transform.translation().xz().distance(start) <= reach {
+            available.add(&storage.goods);
+        }
+    }
+
+    let tiles = road_tiles(&path, &available, &production.road_cost, &roads, |coord| {
+        ground.is_free(coord)
+    });
+    for (coord, tile) in path.iter().zip(tiles) {
+        let position = helpers::geometry::global_coord_to_world_pos(coord, &size, &tile_size);
+        let color = match tile {
+            RoadTile::Blocked => ROAD_INVALID_COLOR,
+            _ => ROAD_VALID_COLOR,
+        };
+        gizmos.rect(
+            position.extend(ROAD_HEIGHT * 2.0).xzy(),
+            Quat::from_rotation_x(FRAC_PI_2),
+            tile_size * 0.9,
+            color,
+        );
+    }
+}
+
+/// What would happen to each tile of the path if the road was built, paying for the new tiles in
+/// order until the available goods run out.
+fn road_tiles(
+    path: &[IVec2],
+    available: &Goods,
+    road_cost: &Goods,
+    roads: &Roads,
+    is_free: impl Fn(&IVec2) -> bool,
+) -> Vec<RoadTile> {
+    let mut total = Goods::default();
+
+    path.iter()
+        .map(|coord| {
+            if roads.contains(coord) {
+                return RoadTile::Existing;
+            }
+
+            if !is_free(coord) {
+                return RoadTile::Blocked;
+            }
+
+            total.add(road_cost);
+            match available.contains(&total) {
+                true => RoadTile::New,
+                false => RoadTile::Blocked,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,181 @@
+use bevy::{input::common_conditions::input_just_pressed, prelude::*};
+
+use crate::{
+    camera::FocusCamera,
+    core::{CursorActive, GameStates, ToolMode},
+    helpers::camera::GroundCursor,
+    units::{Unit, UnitMoveOrder},
+};
+
+/// The longest time, in seconds, between two clicks on the same entity to count as a double-click.
+const DOUBLE_CLICK_TIME: f32 = 0.3;
+const SELECTION_COLOR: Color = Color::rgb(0.35, 0.75, 0.35);
+
+/// Entities that can be selected by clicking within the given radius around them.
+#[derive(Component, Deref)]
+pub struct Selectable(pub f32);
+
+#[derive(Component)]
+pub struct Selected;
+
+/// The entity that was clicked last and when, used to detect double-clicks.
+#[derive(Resource, Default)]
+struct LastClick {
+    entity: Option<Entity>,
+    time: f32,
+}
+
+pub struct SelectionPlugin;
+
+impl Plugin for SelectionPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<LastClick>().add_systems(
+            Update,
+            (
+                select_on_click.run_if(input_just_pressed(MouseButton::Left).and_then(
+                    |tool_mode: Res<ToolMode>, cursor_active: Res<CursorActive>| {
+                        matches!(*tool_mode, ToolMode::Select) && **cursor_active
+                    },
+                )),
+                order_selected_units.run_if(input_just_pressed(MouseButton::Right).and_then(
+                    |tool_mode: Res<ToolMode>, cursor_active: Res<CursorActive>| {
+                        matches!(*tool_mode, ToolMode::Select) && **cursor_active
+                    },
+                )),
+                draw_selection,
+            )
+                .chain()
+                .run_if(in_state(GameStates::Playing)),
+        );
+    }
+}
+
+/// Select the entity under the cursor instead of the selected ones, or add it to them or take it
+/// out of them while holding Shift.
+fn select_on_click(
+    mut commands: Commands,
+    cursor: GroundCursor,
+    q_selectable: Query<(Entity, &GlobalTransform, &Selectable, Has<Selected>)>,
+    mut last_click: ResMut<LastClick>,
+    mut focus_events: EventWriter<FocusCamera>,
+    input: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+) {
+    let Some(point) = cursor.point() else {
+        return;
+    };
+
+    let clicked = q_selectable
+        .iter()
+        .map(|(entity, transform, selectable, _)| {
+            let position = transform.translation().xz();
+            (
+                entity,
+                position,
+                position.distance(point.xz()),
+                **selectable,
+            )
+        })
+        .filter(|(_, _, distance, radius)| distance <= radius)
+        .min_by(|(_, _, a, _), (_, _, b, _)| a.total_cmp(b));
+
+    let now = time.elapsed_seconds();
+    let is_double_click = clicked.is_some_and(|(entity, ..)| {
+        last_click.entity == Some(entity) && now - last_click.time <= DOUBLE_CLICK_TIME
+    });
+
+    *last_click = LastClick {
+        entity: clicked.map(|(entity, ..)| entity),
+        time: now,
+    };
+
+    if input.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        if let Some((entity, ..)) = clicked {
+            match q_selectable
+                .get(entity)
+                .is_ok_and(|(.., is_selected)| is_selected)
+            {
+                true => commands.entity(entity).remove::<Selected>(),
+                false => commands.entity(entity).insert(Selected),
+            };
+        }
+        return;
+    }
+
+    for (entity, .., is_selected) in q_selectable.iter() {
+        if is_selected {
+            commands.entity(entity).remove::<Selected>();
+        }
+    }
+
+    if let Some((entity, position, ..)) = clicked {
+        commands.entity(entity).insert(Selected);
+
+        if is_double_click {
+            focus_events.send(FocusCamera(position));
+        }
+    }
+}
+
+/// The order sending the units to the point, an attack-move while holding Ctrl. No units, no order.
+pub fn move_order(
+    units: Vec<Entity>,
+    point: Vec2,
+    input: &ButtonInput<KeyCode>,
+) -> Option<UnitMoveOrder> {
+    if units.is_empty() {
+        return None;
+    }
+
+    Some(UnitMoveOrder {
+        units,
+        target: point,
+        attack: input.any_pressed([KeyCode::ControlLeft, KeyCode::ControlRight]),
+    })
+}
+
+/// Send the selected units to the point under the cursor, on an attack-move while holding Ctrl.
+fn order_selected_units(
+    cursor: GroundCursor,
+    q_selected_units: Query<Entity, (With<Unit>, With<Selected>)>,
+    input: Res<ButtonInput<KeyCode>>,
+    mut move_orders: EventWriter<UnitMoveOrder>,
+) {
+    let Some(point) = cursor.point() else {
+        return;
+    };
+
+    if let Some(order) = move_order(q_selected_units.iter().collect(), point.xz(), &input) {
+        move_orders.send(order);
+    }
+}
+
+fn draw_selection(
+    mut gizmos: Gizmos,
+    q_selected: Query<(&GlobalTransform, &Selectable), With<Selected>>,
+) {
+    for (transform, selectable) in q_selected.iter() {
+        let position = transform.translation().xz().extend(0.5).xzy();
+        gizmos.circle(position, Direction3d::Y, **selectable, SELECTION_COLOR);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move_order() {
+        let unit = Entity::from_raw(1);
+        let mut input = ButtonInput::<KeyCode>::default();
+        assert!(move_order(vec![], Vec2::ONE, &input).is_none());
+
+        let order = move_order(vec![unit], Vec2::ONE, &input).unwrap();
+        assert_eq!(order.units, vec![unit]);
+        assert_eq!(order.target, Vec2::ONE);
+        assert!(!order.attack);
+
+        input.press(KeyCode::ControlRight);
+        assert!(move_order(vec![unit], Vec2::ONE, &input).unwrap().attack);
+    }
+}
@@ -0,0 +1,295 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::{
+    core::{GameStates, Obstacle},
+    helpers,
+    terrain::{ChunkCoord, ResourceKind, TileCoord, CHUNK_SIZE},
+};
+
+/// How many tiles wide the buckets of the resource index are.
+const RESOURCE_BUCKET_SIZE: i32 = 8;
+
+/// The tiles taken by an obstacle changed, e.g. a building was placed or a tree cut.
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OccupancyChanged(pub IVec2);
+
+/// Where things are on the map, so questions about a tile or an area don't have to look at every
+/// child of the chunks around it: which tiles have an obstacle, and which resources are close to
+/// a tile. It is kept up to date as obstacles and resources are spawned and despawned.
+#[derive(Resource)]
+pub struct SpatialIndex {
+    size: UVec2,
+    /// How many obstacles are on each tile of a chunk, by tile index.
+    occupancy: HashMap<IVec2, Vec<u8>>,
+    /// The resources of each kind, bucketed by the square of tiles they are in.
+    resources: HashMap<ResourceKind, HashMap<IVec2, Vec<(Entity, IVec2)>>>,
+    /// The global tile of every indexed entity, and the kind of the resources.
+    entities: HashMap<Entity, (IVec2, Option<ResourceKind>, bool)>,
+}
+
+impl Default for SpatialIndex {
+    fn default() -> Self {
+        SpatialIndex::new(UVec2::splat(CHUNK_SIZE as u32))
+    }
+}
+
+impl SpatialIndex {
+    pub fn new(size: UVec2) -> Self {
+        SpatialIndex {
+            size,
+            occupancy: HashMap::default(),
+            resources: HashMap::default(),
+            entities: HashMap::default(),
+        }
+    }
+
+    fn bucket(global_coord: &IVec2) -> IVec2 {
+        global_coord.div_euclid(IVec2::splat(RESOURCE_BUCKET_SIZE))
+    }
+
+    fn occupancy_mut(&mut self, global_coord: &IVec2) -> &mut u8 {
+        let chunk_coord = helpers::geometry::global_coord_to_chunk_coord(global_coord, &self.size);
+        let tile_coord = helpers::geometry::global_coord_to_tile_coord(global_coord, &self.size);
+        let index = helpers::geometry::tile_coord_to_index(&tile_coord, &self.size);
+        let tiles = (self.size.x * self.size.y) as usize;
+
+        &mut self
+            .occupancy
+            .entry(chunk_coord)
+            .or_insert_with(|| vec![0; tiles])[index]
+    }
+
+    /// Add an entity on a tile, as an obstacle, a resource of a kind, or both. An entity that is
+    /// already in the index is moved.
+    pub fn insert(
+        &mut self,
+        entity: Entity,
+        global_coord: IVec2,
+        resource: Option<ResourceKind>,
+        is_obstacle: bool,
+    ) {
+        self.remove(entity);
+
+        if is_obstacle {
+            let count = self.occupancy_mut(&global_coord);
+            *count = count.saturating_add(1);
+        }
+        if let Some(kind) = resource {
+            self.resources
+                .entry(kind)
+                .or_default()
+                .entry(SpatialIndex::bucket(&global_coord))
+                .or_default()
+                .push((entity, global_coord));
+        }
+
+        self.entities
+            .insert(entity, (global_coord, resource, is_obstacle));
+    }
+
+    /// Take an entity out of the index, and return the tile it was on.
+    pub fn remove(&mut self, entity: Entity) -> Option<IVec2> {
+        let (global_coord, resource, is_obstacle) = self.entities.remove(&entity)?;
+
+        if is_obstacle {
+            let count = self.occupancy_mut(&global_coord);
+            *count = count.saturating_sub(1);
+        }
+        if let Some(bucket) = resource
+            .and_then(|kind| self.resources.get_mut(&kind))
+            .and_then(|buckets| buckets.get_mut(&SpatialIndex::bucket(&global_coord)))
+        {
+            bucket.retain(|(other, _)| *other != entity);
+        }
+
+        Some(global_coord)
+    }
+
+    /// Whether there is an obstacle on the tile.
+    pub fn is_blocked(&self, global_coord: &IVec2) -> bool {
+        let chunk_coord = helpers::geometry::global_coord_to_chunk_coord(global_coord, &self.size);
+        let tile_coord = helpers::geometry::global_coord_to_tile_coord(global_coord, &self.size);
+        let index = helpers::geometry::tile_coord_to_index(&tile_coord, &self.size);
+
+        self.occupancy
+            .get(&chunk_coord)
+            .is_some_and(|tiles| tiles[index] > 0)
+    }
+
+    /// The closest resource of a kind within `radius` tiles of a tile that `accept` agrees to,
+    /// with its tile. Only the buckets around the tile are looked at, nearest first, until no
+    /// bucket left can have anything closer.
+    pub fn nearest_resource(
+        &self,
+        kind: &ResourceKind,
+        global_coord: &IVec2,
+        radius: u32,
+        mut accept: impl FnMut(Entity) -> bool,
+    ) -> Option<(Entity, IVec2)> {
+        let buckets = self.resources.get(kind)?;
+        let center = SpatialIndex::bucket(global_coord);
+        let max_distance = (radius * radius) as i32;
+        let rings = radius as i32 / RESOURCE_BUCKET_SIZE + 1;
+        let mut closest: Option<(Entity, IVec2, i32)> = None;
+
+        for ring in 0..=rings {
+            // No tile of this ring is closer than the buckets in between.
+            let gap = (ring - 1).max(0) * RESOURCE_BUCKET_SIZE;
+            if closest.is_some_and(|(_, _, distance)| gap * gap > distance) {
+                break;
+            }
+
+            for bucket in ring_coords(&center, ring) {
+                for (entity, coord) in buckets.get(&bucket).into_iter().flatten() {
+                    let distance = (*coord - *global_coord).length_squared();
+                    if distance > max_distance
+                        || closest.is_some_and(|(_, _, closest)| closest <= distance)
+                    {
+                        continue;
+                    }
+                    if accept(*entity) {
+                        closest = Some((*entity, *coord, distance));
+                    }
+                }
+            }
+        }
+
+        closest.map(|(entity, coord, _)| (entity, coord))
+    }
+}
+
+/// The coordinates on the edge of the square `ring` steps around `center`.
+fn ring_coords(center: &IVec2, ring: i32) -> Vec<IVec2> {
+    if ring == 0 {
+        return vec![*center];
+    }
+
+    (-ring..=ring)
+        .flat_map(|y| (-ring..=ring).map(move |x| IVec2::new(x, y)))
+        .filter(|offset| offset.x.abs() == ring || offset.y.abs() == ring)
+        .map(|offset| *center + offset)
+        .collect()
+}
+
+pub struct SpatialPlugin;
+
+impl Plugin for SpatialPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<SpatialIndex>()
+            .add_event::<OccupancyChanged>()
+            .add_systems(
+                Update,
+                update_spatial_index.run_if(in_state(GameStates::Playing)),
+            );
+    }
+}
+
+/// The obstacles and resources spawned since the last frame.
+type SpawnedOccupants<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static TileCoord,
+        &'static Parent,
+        Option<&'static ResourceKind>,
+        Has<Obstacle>,
+    ),
+    Or<(Added<Obstacle>, Added<ResourceKind>)>,
+>;
+
+/// Index the obstacles and resources spawned since the last frame, and drop the despawned ones.
+pub(crate) fn update_spatial_index(
+    q_spawned: SpawnedOccupants,
+    q_chunks: Query<&ChunkCoord>,
+    mut removed_obstacles: RemovedComponents<Obstacle>,
+    mut removed_resources: RemovedComponents<ResourceKind>,
+    mut index: ResMut<SpatialIndex>,
+    mut changes: EventWriter<OccupancyChanged>,
+) {
+    for entity in removed_obstacles.read().chain(removed_resources.read()) {
+        if let Some(global_coord) = index.remove(entity) {
+            changes.send(OccupancyChanged(global_coord));
+        }
+    }
+
+    let size = index.size;
+    for (entity, tile_coord, parent, resource, is_obstacle) in q_spawned.iter() {
+        let Ok(chunk_coord) = q_chunks.get(parent.get()) else {
+            continue;
+        };
+        let global_coord =
+            helpers::geometry::tile_coord_to_global_coord(tile_coord, chunk_coord, &size);
+
+        index.insert(entity, global_coord, resource.copied(), is_obstacle);
+        if is_obstacle {
+            changes.send(OccupancyChanged(global_coord));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spatial_index_occupancy() {
+        let mut index = SpatialIndex::new(UVec2::splat(32));
+        let rock = Entity::from_raw(0);
+        let building = Entity::from_raw(1);
+        let coord = IVec2::new(-20, 40);
+
+        index.insert(rock, coord, Some(ResourceKind::Rock), true);
+        index.insert(building, coord, None, true);
+        assert!(index.is_blocked(&coord));
+        assert!(!index.is_blocked(&(coord + IVec2::X)));
+
+        assert_eq!(index.remove(rock), Some(coord));
+        assert!(index.is_blocked(&coord));
+        assert_eq!(index.remove(building), Some(coord));
+        assert!(!index.is_blocked(&coord));
+        assert_eq!(index.remove(building), None);
+    }
+
+    #[test]
+    fn test_spatial_index_nearest_resource() {
+        let mut index = SpatialIndex::new(UVec2::splat(32));
+        let trees = [
+            IVec2::new(3, 0),
+            IVec2::new(-9, 2),
+            IVec2::new(30, 30),
+            IVec2::new(1, 1),
+        ];
+        for (i, coord) in trees.iter().enumerate() {
+            index.insert(
+                Entity::from_raw(i as u32),
+                *coord,
+                Some(ResourceKind::Tree),
+                true,
+            );
+        }
+        index.insert(
+            Entity::from_raw(9),
+            IVec2::ZERO,
+            Some(ResourceKind::Rock),
+            true,
+        );
+
+        let nearest = |index: &SpatialIndex, radius, skip: Option<u32>| {
+            index
+                .nearest_resource(&ResourceKind::Tree, &IVec2::ZERO, radius, |entity| {
+                    Some(entity.index()) != skip
+                })
+                .map(|(entity, _)| entity.index())
+        };
+        assert_eq!(nearest(&index, 16, None), Some(3));
+        assert_eq!(nearest(&index, 16, Some(3)), Some(0));
+        assert_eq!(nearest(&index, 1, None), None);
+
+        index.remove(Entity::from_raw(3));
+        index.remove(Entity::from_raw(0));
+        assert_eq!(nearest(&index, 16, None), Some(1));
+        assert_eq!(nearest(&index, 8, None), None);
+        assert_eq!(nearest(&index, 64, Some(1)), Some(2));
+    }
+}
@@ -16,6 +16,15 @@ pub struct ResourceMapping(pub Vec<ResourceKind>);
 #[derive(Component)]
 pub(super) struct ChunkHandledTiles;
 
+/// The materials of the chunk were made while some of its neighbours weren't spawned yet, with the
+/// tiles of the chunk itself standing in for theirs. They are made again once the neighbours are
+/// generated.
+#[derive(Component)]
+pub(super) struct ChunkMissingNeighbours;
+
+#[derive(Component)]
+pub(super) struct WaterSurface;
+
 #[derive(Component)]
 pub(super) struct ChunkHandledResources;
 
@@ -0,0 +1,83 @@
+use bevy::prelude::*;
+use image::{Rgb, RgbImage};
+
+use crate::helpers;
+
+use super::{generators_from_seed, ResourceKind, TileKind, WorldGenSettings, CHUNK_SIZE};
+
+const WATER_COLOR: Rgb<u8> = Rgb([38, 92, 168]);
+const GRASS_COLOR: Rgb<u8> = Rgb([96, 160, 64]);
+const BARREN_COLOR: Rgb<u8> = Rgb([170, 150, 110]);
+const TREE_COLOR: Rgb<u8> = Rgb([24, 72, 24]);
+const TREE_DEAD_COLOR: Rgb<u8> = Rgb([230, 235, 240]);
+const ROCK_COLOR: Rgb<u8> = Rgb([110, 110, 110]);
+
+/// Generate the world for the chunks in `min..=max` and render it to an image.
+///
+/// Each tile is one pixel, coloured by its `TileKind` and overlaid with the resource that would
+/// be spawned on it. The image is built on the CPU only, so it can be used without a GPU.
+pub fn export_world_image(
+    seed: u64,
+    settings: &WorldGenSettings,
+    min: IVec2,
+    max: IVec2,
+) -> RgbImage {
+    let (terrain_generator, resource_generator) = generators_from_seed(seed, settings);
+
+    let size = UVec2::splat(CHUNK_SIZE as u32);
+    let chunks = (max - min + IVec2::ONE).max(IVec2::ZERO).as_uvec2();
+    let mut image = RgbImage::new(chunks.x * size.x, chunks.y * size.y);
+
+    for y in min.y..=max.y {
+        for x in min.x..=max.x {
+            let coord = IVec2::new(x, y);
+            let tiles = terrain_generator.generate(coord, size);
+            let resources = resource_generator.generate(coord, size);
+            let origin = (coord - min).as_uvec2() * size;
+
+            for (index, (tile, resource)) in tiles.iter().zip(resources.iter()).enumerate() {
+                let tile_coord = helpers::geometry::index_to_tile_coord(index, &size);
+                let pixel = origin + tile_coord;
+
+                image.put_pixel(pixel.x, pixel.y, tile_color(tile, resource));
+            }
+        }
+    }
+
+    image
+}
+
+/// The colour of a tile, matching the resources that `handle_chunks_resources` spawns on it.
+pub(crate) fn tile_color(tile: &TileKind, resource: &ResourceKind) -> Rgb<u8> {
+    match (resource, tile) {
+        (_, TileKind::Water) => WATER_COLOR,
+        (ResourceKind::Tree, TileKind::Grass) => TREE_COLOR,
+        (ResourceKind::Tree, TileKind::Barren) => TREE_DEAD_COLOR,
+        (ResourceKind::Rock, _) => ROCK_COLOR,
+        (ResourceKind::None, TileKind::Grass) => GRASS_COLOR,
+        (ResourceKind::None, TileKind::Barren) => BARREN_COLOR,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_world_image_size() {
+        let settings = WorldGenSettings::default();
+        let image = export_world_image(0, &settings, IVec2::new(-1, -1), IVec2::new(1, 0));
+
+        assert_eq!(image.width(), 3 * CHUNK_SIZE as u32);
+        assert_eq!(image.height(), 2 * CHUNK_SIZE as u32);
+    }
+
+    #[test]
+    fn test_export_world_image_deterministic() {
+        let settings = WorldGenSettings::default();
+        let first = export_world_image(42, &settings, IVec2::ZERO, IVec2::ONE);
+        let second = export_world_image(42, &settings, IVec2::ZERO, IVec2::ONE);
+
+        assert_eq!(first.as_raw(), second.as_raw());
+    }
+}
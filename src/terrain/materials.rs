@@ -16,22 +16,41 @@ use bevy::{
 };
 
 const MAX_TEXTURE_COUNT: usize = 4;
+/// How much of a tile, from its border towards its center, is blended with the neighbours.
+const TILE_BLEND: f32 = 0.25;
 
 #[derive(Asset, TypePath, Debug, Clone)]
 pub(super) struct TerrainMaterial {
     size: UVec2,
     textures: Vec<Handle<Image>>,
+    /// The tile mapping of the chunk with a one tile border taken from the neighbouring chunks,
+    /// so `(size.x + 2) * (size.y + 2)` entries.
     mapping: Vec<u32>,
+    /// The fog of war state of the tiles, padded in the same way as the mapping.
+    visibility: Vec<u32>,
 }
 
 impl TerrainMaterial {
-    pub fn new(size: UVec2, textures: Vec<Handle<Image>>, mapping: Vec<u32>) -> Self {
+    pub fn new(
+        size: UVec2,
+        textures: Vec<Handle<Image>>,
+        mapping: Vec<u32>,
+        visibility: Vec<u32>,
+    ) -> Self {
+        debug_assert_eq!(mapping.len(), ((size.x + 2) * (size.y + 2)) as usize);
+        debug_assert_eq!(visibility.len(), mapping.len());
+
         Self {
             size,
             textures,
             mapping,
+            visibility,
         }
     }
+
+    pub fn set_visibility(&mut self, visibility: Vec<u32>) {
+        self.visibility = visibility;
+    }
 }
 
 impl AsBindGroup for TerrainMaterial {
@@ -72,9 +91,17 @@ impl AsBindGroup for TerrainMaterial {
             usage: BufferUsages::STORAGE,
         });
 
-        let size = render_device.create_buffer_with_data(&BufferInitDescriptor {
-            label: Some("terrain_material_size"),
-            contents: &bytemuck::bytes_of(&self.size).to_vec(),
+        let visibility = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("terrain_material_visibility"),
+            contents: bytemuck::cast_slice(&self.visibility),
+            usage: BufferUsages::STORAGE,
+        });
+
+        // The `TerrainParams` of the shader: the size of the chunk, the blend and a padding.
+        let params = [self.size.x, self.size.y, TILE_BLEND.to_bits(), 0];
+        let params = render_device.create_buffer_with_data(&BufferInitDescriptor {
+            label: Some("terrain_material_params"),
+            contents: bytemuck::cast_slice(&params),
             usage: BufferUsages::UNIFORM,
         });
 
@@ -85,7 +112,8 @@ impl AsBindGroup for TerrainMaterial {
                 &textures[..],
                 &fallback_image.sampler,
                 mapping.as_entire_binding(),
-                size.as_entire_binding(),
+                params.as_entire_binding(),
+                visibility.as_entire_binding(),
             )),
         );
 
@@ -143,7 +171,7 @@ impl AsBindGroup for TerrainMaterial {
                 // count: NonZeroU32::new((TILEMAP_SIZE * TILEMAP_SIZE) as u32),
                 count: None,
             },
-            // @group(2) @binding(3) var<uniform> size: vec2<u32>;
+            // @group(2) @binding(3) var<uniform> params: TerrainParams;
             BindGroupLayoutEntry {
                 binding: 3,
                 visibility: ShaderStages::FRAGMENT,
@@ -154,6 +182,17 @@ impl AsBindGroup for TerrainMaterial {
                 },
                 count: None,
             },
+            // @group(2) @binding(4) var<storage, read> visibility: array<u32>;
+            BindGroupLayoutEntry {
+                binding: 4,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
         ]
     }
 }
@@ -163,3 +202,44 @@ impl Material for TerrainMaterial {
         "shaders/terrain.wgsl".into()
     }
 }
+
+/// The animated, transparent surface drawn on top of the water tiles of a chunk.
+#[derive(Asset, TypePath, AsBindGroup, Debug, Clone)]
+pub(super) struct WaterMaterial {
+    #[uniform(0)]
+    size: UVec2,
+    /// The distance to the closest land tile for every tile of the chunk, with a one tile border
+    /// from the neighbouring chunks, so `(size.x + 2) * (size.y + 2)` entries.
+    #[storage(1, read_only)]
+    shore: Vec<f32>,
+    /// The fog of war state of the tiles, padded in the same way as the shore distances.
+    #[storage(2, read_only)]
+    visibility: Vec<u32>,
+}
+
+impl WaterMaterial {
+    pub fn new(size: UVec2, shore: Vec<f32>, visibility: Vec<u32>) -> Self {
+        debug_assert_eq!(shore.len(), ((size.x + 2) * (size.y + 2)) as usize);
+        debug_assert_eq!(visibility.len(), shore.len());
+
+        Self {
+            size,
+            shore,
+            visibility,
+        }
+    }
+
+    pub fn set_visibility(&mut self, visibility: Vec<u32>) {
+        self.visibility = visibility;
+    }
+}
+
+impl Material for WaterMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/water.wgsl".into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Blend
+    }
+}
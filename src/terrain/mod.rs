@@ -1,18 +1,25 @@
 use bevy::prelude::*;
 pub use components::*;
+pub use export::*;
 use materials::*;
-use rand::{rngs::StdRng, RngCore, SeedableRng};
 pub use resources::*;
+use serde::{Deserialize, Serialize};
+pub use settings::*;
 use systems::*;
 
-use crate::core::GameStates;
+use crate::{
+    core::{setup_settings, update_settings, GameStates, RonAssetLoader},
+    fog::FogOfWarSet,
+};
 
 mod components;
+mod export;
 mod materials;
 mod resources;
+mod settings;
 mod systems;
 
-#[derive(Default, Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[derive(Default, Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
 #[repr(u32)]
 pub enum TileKind {
     #[default]
@@ -21,7 +28,7 @@ pub enum TileKind {
     Barren,
 }
 
-#[derive(Component, Default, Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[derive(Component, Default, Debug, Clone, Copy, Eq, PartialEq, Hash, Serialize, Deserialize)]
 #[repr(u32)]
 pub enum ResourceKind {
     #[default]
@@ -34,6 +41,9 @@ pub const CHUNK_SIZE: usize = 32;
 pub const CHUNK_TILE_SIZE: f32 = 16.0;
 const SPAWN_CHUNK_RADIUS: usize = 8;
 const LOAD_CHUNK_RADIUS: usize = 3;
+/// How far from the land, in tiles, the shallow water and the foam reach.
+const SHORE_DISTANCE: u32 = 3;
+const WATER_LEVEL: f32 = 0.5;
 
 pub struct TerrainPlugin {
     seed: u64,
@@ -47,25 +57,42 @@ impl TerrainPlugin {
 
 impl Plugin for TerrainPlugin {
     fn build(&self, app: &mut App) {
-        let mut seeder = StdRng::seed_from_u64(self.seed);
+        let settings = WorldGenSettings::default();
+        let (terrain_generator, resource_generator) = generators_from_seed(self.seed, &settings);
 
         app.add_plugins(MaterialPlugin::<TerrainMaterial>::default())
+            .add_plugins(MaterialPlugin::<WaterMaterial>::default())
+            .init_asset::<WorldGenSettings>()
+            .init_asset_loader::<RonAssetLoader<WorldGenSettings>>()
+            .init_resource::<WorldGenSettings>()
             .init_resource::<ChunkManager>()
-            .insert_resource(TerrainGenerator::new(seeder.next_u64()))
-            .insert_resource(ResourceGenerator::new(seeder.next_u64()))
+            .insert_resource(terrain_generator)
+            .insert_resource(resource_generator)
             .insert_resource(TerrainSeed(self.seed))
+            .insert_resource(WorldIdentity {
+                seed: self.seed,
+                settings,
+            })
+            .add_systems(
+                OnEnter(GameStates::Playing),
+                setup_settings::<WorldGenSettings>,
+            )
             .add_systems(
                 Update,
                 (
+                    update_settings::<WorldGenSettings>,
+                    regenerate_chunks_on_settings_change,
                     spawn_chunks_around_camera,
                     load_chunks_around_camera,
                     unload_chunks_outside_camera,
                     handle_chunks_tiles,
+                    reblend_chunks_tiles,
                     handle_chunks_resources,
                     generate_terrain_task,
                     handle_generate_terrain_task,
                     generate_resource_task,
                     handle_generate_resource_task,
+                    update_chunks_fog.after(FogOfWarSet),
                 )
                     .run_if(in_state(GameStates::Playing)),
             );
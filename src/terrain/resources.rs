@@ -7,20 +7,38 @@ use noise::{
     utils::{NoiseMapBuilder, PlaneMapBuilder},
     Fbm, MultiFractal, Perlin, Worley,
 };
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use serde::{Deserialize, Serialize};
 
-use super::{ResourceKind, TileKind, CHUNK_SIZE, CHUNK_TILE_SIZE};
+use crate::helpers;
+
+use super::{
+    ResourceKind, ResourceSettings, TerrainSettings, TileKind, TileMapping, WorldGenSettings,
+    CHUNK_SIZE, CHUNK_TILE_SIZE,
+};
 
 #[derive(Resource, Deref)]
-pub(super) struct TerrainSeed(pub u64);
+pub struct TerrainSeed(pub u64);
+
+/// The seed and generation settings that together identify a generated world.
+///
+/// Both decide what the map looks like, so the same seed with other settings is another world.
+#[derive(Resource, Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WorldIdentity {
+    pub seed: u64,
+    pub settings: WorldGenSettings,
+}
 
 #[derive(Resource, Clone)]
 pub(super) struct TerrainGenerator {
     seed: u64,
+    settings: TerrainSettings,
 }
 
 #[derive(Resource, Clone)]
 pub(super) struct ResourceGenerator {
     seed: u64,
+    settings: ResourceSettings,
 }
 
 #[derive(Debug, Resource)]
@@ -79,24 +97,56 @@ impl ChunkManager {
         self.chunks.contains_key(coord)
     }
 
+    /// The kind of the tile at a global coordinate, if its chunk was generated.
+    pub fn tile_kind(
+        &self,
+        global_coord: &IVec2,
+        q_mappings: &Query<&TileMapping>,
+    ) -> Option<TileKind> {
+        let chunk_coord = helpers::geometry::global_coord_to_chunk_coord(global_coord, &self.size);
+        let tile_coord = helpers::geometry::global_coord_to_tile_coord(global_coord, &self.size);
+        let mapping = q_mappings.get(*self.chunks.get(&chunk_coord)?).ok()?;
+
+        mapping
+            .get(helpers::geometry::tile_coord_to_index(
+                &tile_coord,
+                &self.size,
+            ))
+            .copied()
+    }
+
     pub fn loaded(&self, coord: &IVec2) -> bool {
         self.loaded.contains(coord)
     }
 }
 
+/// Derive the terrain and resource generators from the world seed.
+pub(super) fn generators_from_seed(
+    seed: u64,
+    settings: &WorldGenSettings,
+) -> (TerrainGenerator, ResourceGenerator) {
+    let mut seeder = StdRng::seed_from_u64(seed);
+
+    (
+        TerrainGenerator::new(seeder.next_u64(), settings.terrain.clone()),
+        ResourceGenerator::new(seeder.next_u64(), settings.resources.clone()),
+    )
+}
+
 impl TerrainGenerator {
-    pub fn new(seed: u64) -> Self {
-        TerrainGenerator { seed }
+    pub fn new(seed: u64, settings: TerrainSettings) -> Self {
+        TerrainGenerator { seed, settings }
     }
 }
 
 impl TerrainGenerator {
     pub fn generate(&self, coord: IVec2, size: UVec2) -> Vec<TileKind> {
+        let settings = &self.settings;
         let perlin = Fbm::<Perlin>::new(self.seed as u32)
-            .set_frequency(1.0)
-            .set_persistence(0.5)
-            .set_lacunarity(2.0)
-            .set_octaves(14);
+            .set_frequency(settings.noise.frequency)
+            .set_persistence(settings.noise.persistence)
+            .set_lacunarity(settings.noise.lacunarity)
+            .set_octaves(settings.noise.octaves);
 
         PlaneMapBuilder::new(perlin)
             .set_size(size.x as usize, size.y as usize)
@@ -105,8 +155,8 @@ impl TerrainGenerator {
             .build()
             .into_iter()
             .map(|noise| match noise {
-                n if n < 0.1 => TileKind::Barren,
-                n if n < 0.4 => TileKind::Grass,
+                n if n < settings.barren_threshold => TileKind::Barren,
+                n if n < settings.grass_threshold => TileKind::Grass,
                 _ => TileKind::Water,
             })
             .collect()
@@ -114,23 +164,24 @@ impl TerrainGenerator {
 }
 
 impl ResourceGenerator {
-    pub fn new(seed: u64) -> Self {
-        ResourceGenerator { seed }
+    pub fn new(seed: u64, settings: ResourceSettings) -> Self {
+        ResourceGenerator { seed, settings }
     }
 }
 
 impl ResourceGenerator {
     pub fn generate(&self, coord: IVec2, size: UVec2) -> Vec<ResourceKind> {
+        let settings = &self.settings;
         let perlin = Fbm::<Perlin>::new(self.seed as u32)
-            .set_frequency(2.0)
-            .set_persistence(0.5)
-            .set_lacunarity(2.0)
-            .set_octaves(14);
+            .set_frequency(settings.noise.frequency)
+            .set_persistence(settings.noise.persistence)
+            .set_lacunarity(settings.noise.lacunarity)
+            .set_octaves(settings.noise.octaves);
 
         let worley = Worley::new(self.seed as u32)
             .set_distance_function(distance_functions::euclidean)
             .set_return_type(ReturnType::Value)
-            .set_frequency(2.0);
+            .set_frequency(settings.worley_frequency);
 
         return PlaneMapBuilder::new(perlin)
             .set_size(size.x as usize, size.y as usize)
@@ -147,9 +198,9 @@ impl ResourceGenerator {
                     .into_iter(),
             )
             .map(|(noise, worley)| {
-                if worley < 0.0 || noise < 0.3 {
+                if worley < settings.worley_threshold || noise < settings.noise_threshold {
                     ResourceKind::None
-                } else if worley < 0.5 {
+                } else if worley < settings.rock_threshold {
                     ResourceKind::Rock
                 } else {
                     ResourceKind::Tree
@@ -167,14 +218,14 @@ mod tests {
 
     #[bench]
     fn bench_terrain_generator(b: &mut Bencher) {
-        let generator = TerrainGenerator::new(0);
+        let generator = TerrainGenerator::new(0, TerrainSettings::default());
 
         b.iter(|| generator.generate(IVec2::ZERO, UVec2::splat(128)));
     }
 
     #[bench]
     fn bench_resource_generator(b: &mut Bencher) {
-        let generator = ResourceGenerator::new(0);
+        let generator = ResourceGenerator::new(0, ResourceSettings::default());
 
         b.iter(|| generator.generate(IVec2::ZERO, UVec2::splat(128)));
     }
@@ -0,0 +1,95 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::core::{GameAssets, SettingsAsset};
+
+/// The parameters used to generate the world, loaded from a `.worldgen.ron` asset.
+///
+/// Together with the seed these decide what the map looks like, so changing them changes the
+/// world even if the seed stays the same.
+#[derive(Asset, Resource, TypePath, Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct WorldGenSettings {
+    pub terrain: TerrainSettings,
+    pub resources: ResourceSettings,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NoiseSettings {
+    pub frequency: f64,
+    pub persistence: f64,
+    pub lacunarity: f64,
+    pub octaves: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TerrainSettings {
+    pub noise: NoiseSettings,
+    /// Tiles with noise below this value are barren.
+    pub barren_threshold: f64,
+    /// Tiles with noise below this value (and above the barren one) are grass, the rest is water.
+    pub grass_threshold: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceSettings {
+    pub noise: NoiseSettings,
+    pub worley_frequency: f64,
+    /// Tiles with noise below this value have no resources.
+    pub noise_threshold: f64,
+    /// Tiles with worley below this value have no resources.
+    pub worley_threshold: f64,
+    /// Tiles with worley below this value have rocks, the rest have trees.
+    pub rock_threshold: f64,
+}
+
+impl Default for TerrainSettings {
+    fn default() -> Self {
+        Self {
+            noise: NoiseSettings {
+                frequency: 1.0,
+                persistence: 0.5,
+                lacunarity: 2.0,
+                octaves: 14,
+            },
+            barren_threshold: 0.1,
+            grass_threshold: 0.4,
+        }
+    }
+}
+
+impl Default for ResourceSettings {
+    fn default() -> Self {
+        Self {
+            noise: NoiseSettings {
+                frequency: 2.0,
+                persistence: 0.5,
+                lacunarity: 2.0,
+                octaves: 14,
+            },
+            worley_frequency: 2.0,
+            noise_threshold: 0.3,
+            worley_threshold: 0.0,
+            rock_threshold: 0.5,
+        }
+    }
+}
+
+impl SettingsAsset for WorldGenSettings {
+    const EXTENSIONS: &'static [&'static str] = &["worldgen.ron"];
+    const NAME: &'static str = "World generation";
+
+    fn handle(game_assets: &GameAssets) -> &Handle<Self> {
+        &game_assets.world_gen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::testing;
+
+    #[test]
+    fn test_world_gen_asset_matches_default() {
+        testing::assert_asset_matches_default::<WorldGenSettings>("settings/world.worldgen.ron");
+    }
+}
@@ -1,47 +1,289 @@
 use rand::{rngs::StdRng, SeedableRng};
 
-use crate::{core::{GameAssets, Obstacle}, helpers::{self, sampling::disc::PoissonDiscSampler}};
+use crate::{
+    core::{GameAssets, Obstacle},
+    fog::{FogOfWar, HiddenUntilExplored, TileVisibility},
+    helpers::{self, sampling::disc::PoissonDiscSampler},
+};
 
 use bevy::{
-    ecs::system::CommandQueue,
+    ecs::system::{CommandQueue, SystemParam},
     prelude::*,
     tasks::{block_on, futures_lite::future, AsyncComputeTaskPool},
+    utils::HashSet,
 };
 
 use super::{
-    ChunkCoord, ChunkHandledResources, ChunkHandledTiles, ChunkManager, ComputeResourceMapping,
-    ComputeTileMapping, ResourceGenerator, ResourceKind, ResourceMapping, TerrainGenerator,
-    TerrainMaterial, TerrainSeed, TileCoord, TileKind, TileMapping, LOAD_CHUNK_RADIUS,
-    SPAWN_CHUNK_RADIUS,
+    generators_from_seed, ChunkCoord, ChunkHandledResources, ChunkHandledTiles, ChunkManager,
+    ChunkMissingNeighbours, ComputeResourceMapping, ComputeTileMapping, ResourceGenerator,
+    ResourceKind, ResourceMapping, TerrainGenerator, TerrainMaterial, TerrainSeed, TileCoord,
+    TileKind, TileMapping, WaterMaterial, WaterSurface, WorldGenSettings, WorldIdentity,
+    LOAD_CHUNK_RADIUS, SHORE_DISTANCE, SPAWN_CHUNK_RADIUS, WATER_LEVEL,
 };
 
+/// The resources and the water surfaces generated on the chunks.
+type GeneratedEntities<'w, 's> =
+    Query<'w, 's, Entity, Or<(With<ResourceKind>, With<WaterSurface>)>>;
+
+/// Generate the world by the world generation settings, and generate the chunks again whenever
+/// the settings change.
+pub fn regenerate_chunks_on_settings_change(
+    mut commands: Commands,
+    settings: Res<WorldGenSettings>,
+    mut world_identity: ResMut<WorldIdentity>,
+    q_chunks: Query<(Entity, Option<&Children>), With<ChunkCoord>>,
+    q_resources: GeneratedEntities,
+) {
+    if !settings.is_changed() || world_identity.settings == *settings {
+        return;
+    }
+
+    info!("World generation settings changed, regenerating chunks");
+    apply_world_gen_settings(&mut commands, &mut world_identity, &settings);
+
+    for (chunk, children) in q_chunks.iter() {
+        for child in children.into_iter().flatten() {
+            if q_resources.contains(*child) {
+                commands.entity(*child).despawn_recursive();
+            }
+        }
+
+        commands.entity(chunk).remove::<(
+            TileMapping,
+            ResourceMapping,
+            ComputeTileMapping,
+            ComputeResourceMapping,
+            ChunkHandledTiles,
+            ChunkMissingNeighbours,
+            ChunkHandledResources,
+        )>();
+    }
+}
+
+fn apply_world_gen_settings(
+    commands: &mut Commands,
+    world_identity: &mut WorldIdentity,
+    settings: &WorldGenSettings,
+) {
+    let (terrain_generator, resource_generator) =
+        generators_from_seed(world_identity.seed, settings);
+
+    commands.insert_resource(terrain_generator);
+    commands.insert_resource(resource_generator);
+    world_identity.settings = settings.clone();
+}
+
+/// The assets the meshes and materials of the chunks are added to.
+#[derive(SystemParam)]
+pub struct ChunkAssets<'w> {
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<TerrainMaterial>>,
+    water_materials: ResMut<'w, Assets<WaterMaterial>>,
+}
+
 pub fn handle_chunks_tiles(
     mut commands: Commands,
     chunk_manager: Res<ChunkManager>,
-    q_chunks: Query<(Entity, &TileMapping), (With<ChunkCoord>, Without<ChunkHandledTiles>)>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<TerrainMaterial>>,
+    q_chunks: Query<(Entity, &ChunkCoord), (With<TileMapping>, Without<ChunkHandledTiles>)>,
+    q_mappings: Query<&TileMapping, With<ChunkCoord>>,
+    mut assets: ChunkAssets,
     game_assets: Res<GameAssets>,
+    fog: Res<FogOfWar>,
 ) {
     let chunk_size = chunk_manager.size();
     let tile_size = chunk_manager.tile_size();
 
-    for (entity, tile_mapping) in q_chunks.iter() {
-        let chunk_mesh = meshes.add(Plane3d::default().mesh().size(
+    for (entity, chunk_coord) in q_chunks.iter() {
+        // The materials need the tiles on the edges of the neighbouring chunks to blend across
+        // the seams, so wait until the spawned ones are generated. The chunk stands in for the
+        // ones past the edge of the spawned area until they are spawned too.
+        let neighbours = chunk_neighbours(chunk_coord)
+            .map(|coord| chunk_manager.get(&coord))
+            .collect::<Vec<_>>();
+        if neighbours
+            .iter()
+            .flatten()
+            .any(|chunk| !q_mappings.contains(**chunk))
+        {
+            continue;
+        }
+        let is_missing = neighbours.iter().any(Option::is_none);
+        let border = SHORE_DISTANCE + 1;
+        let Some(mapping) = padded_tile_mapping(chunk_coord, &chunk_size, border, |coord| {
+            chunk_manager
+                .get(coord)
+                .and_then(|chunk| q_mappings.get(*chunk).ok())
+                .map(|mapping| mapping.as_slice())
+        }) else {
+            continue;
+        };
+
+        let chunk_mesh = assets.meshes.add(Plane3d::default().mesh().size(
             tile_size.x * chunk_size.x as f32,
             tile_size.y * chunk_size.y as f32,
         ));
 
-        let chunk_material = materials.add(TerrainMaterial::new(
+        let chunk_material = assets.materials.add(TerrainMaterial::new(
             chunk_size,
             game_assets.tiles.clone(),
-            tile_mapping.iter().map(|kind| *kind as u32).collect(),
+            crop_tile_mapping(&mapping, &chunk_size, border, 1)
+                .iter()
+                .map(|kind| *kind as u32)
+                .collect(),
+            fog.padded_chunk(chunk_coord),
         ));
 
         commands
             .entity(entity)
-            .insert((chunk_mesh, chunk_material, ChunkHandledTiles));
+            .insert((chunk_mesh.clone(), chunk_material, ChunkHandledTiles));
+        if is_missing {
+            commands.entity(entity).insert(ChunkMissingNeighbours);
+        }
+
+        let shore = shore_distances(&mapping, &chunk_size, border);
+        if shore.iter().all(|distance| *distance == 0.0) {
+            continue;
+        }
+
+        commands.entity(entity).with_children(|parent| {
+            parent.spawn((
+                WaterSurface,
+                MaterialMeshBundle {
+                    mesh: chunk_mesh,
+                    material: assets.water_materials.add(WaterMaterial::new(
+                        chunk_size,
+                        shore,
+                        fog.padded_chunk(chunk_coord),
+                    )),
+                    transform: Transform::from_xyz(0.0, WATER_LEVEL, 0.0),
+                    ..default()
+                },
+            ));
+        });
+    }
+}
+
+/// Make the materials of the chunks that were missing neighbours again once every neighbour is
+/// generated, so they blend across those seams too.
+pub fn reblend_chunks_tiles(
+    mut commands: Commands,
+    chunk_manager: Res<ChunkManager>,
+    q_chunks: Query<(Entity, &ChunkCoord, Option<&Children>), With<ChunkMissingNeighbours>>,
+    q_mappings: Query<&TileMapping, With<ChunkCoord>>,
+    q_water: Query<(), With<WaterSurface>>,
+) {
+    for (entity, chunk_coord, children) in q_chunks.iter() {
+        let is_complete = chunk_neighbours(chunk_coord).all(|coord| {
+            chunk_manager
+                .get(&coord)
+                .is_some_and(|chunk| q_mappings.contains(*chunk))
+        });
+        if !is_complete {
+            continue;
+        }
+
+        for child in children.into_iter().flatten() {
+            if q_water.contains(*child) {
+                commands.entity(*child).despawn_recursive();
+            }
+        }
+        commands
+            .entity(entity)
+            .remove::<(ChunkHandledTiles, ChunkMissingNeighbours)>();
+    }
+}
+
+/// The coordinates of the chunk and of the 8 chunks around it.
+fn chunk_neighbours(chunk_coord: &IVec2) -> impl Iterator<Item = IVec2> + '_ {
+    (-1..=1).flat_map(move |y| (-1..=1).map(move |x| *chunk_coord + IVec2::new(x, y)))
+}
+
+/// Build the tile mapping of a chunk surrounded by a border of `border` tiles taken from the edges
+/// of the neighbouring chunks. The border next to a neighbour that is not generated repeats the
+/// closest tiles of the chunk itself. Returns `None` if the chunk is not generated.
+fn padded_tile_mapping<'a>(
+    chunk_coord: &IVec2,
+    size: &UVec2,
+    border: u32,
+    mapping: impl Fn(&IVec2) -> Option<&'a [TileKind]>,
+) -> Option<Vec<TileKind>> {
+    debug_assert!(border <= size.min_element());
+
+    let center = mapping(chunk_coord)?;
+    let neighbours = chunk_neighbours(chunk_coord)
+        .map(|coord| mapping(&coord))
+        .collect::<Vec<_>>();
+
+    let size = size.as_ivec2();
+    let border = border as i32;
+    let mut padded = Vec::with_capacity(((size.x + 2 * border) * (size.y + 2 * border)) as usize);
+    for y in -border..size.y + border {
+        for x in -border..size.x + border {
+            let coord = IVec2::new(x, y);
+            let offset = coord.div_euclid(size);
+            let (neighbour, tile_coord) =
+                match neighbours[((offset.y + 1) * 3 + offset.x + 1) as usize] {
+                    Some(neighbour) => (neighbour, coord.rem_euclid(size)),
+                    None => (center, coord.clamp(IVec2::ZERO, size - 1)),
+                };
+            let index =
+                helpers::geometry::tile_coord_to_index(&tile_coord.as_uvec2(), &size.as_uvec2());
+
+            padded.push(neighbour[index]);
+        }
+    }
+
+    Some(padded)
+}
+
+/// Shrink a padded tile mapping from a border of `from` tiles to a border of `to` tiles.
+fn crop_tile_mapping<T: Copy>(padded: &[T], size: &UVec2, from: u32, to: u32) -> Vec<T> {
+    let width = size.x + 2 * from;
+    let skip = from - to;
+
+    (skip..size.y + from + to)
+        .flat_map(|y| (skip..size.x + from + to).map(move |x| padded[(y * width + x) as usize]))
+        .collect()
+}
+
+/// The distance, in tiles, from every tile of a chunk (with a one tile border) to the closest
+/// land tile, capped at `SHORE_DISTANCE`. Land tiles have a distance of zero.
+///
+/// `padded` has to have a border of at least `SHORE_DISTANCE + 1` tiles so that the distances
+/// match on both sides of a chunk seam.
+fn shore_distances(padded: &[TileKind], size: &UVec2, border: u32) -> Vec<f32> {
+    let radius = SHORE_DISTANCE as i32;
+    let width = (size.x + 2 * border) as i32;
+    let is_land = |x: i32, y: i32| {
+        let x = x + border as i32;
+        let y = y + border as i32;
+        !matches!(padded[(y * width + x) as usize], TileKind::Water)
+    };
+
+    let size = size.as_ivec2();
+    let mut distances = Vec::with_capacity(((size.x + 2) * (size.y + 2)) as usize);
+    for y in -1..=size.y {
+        for x in -1..=size.x {
+            if is_land(x, y) {
+                distances.push(0.0);
+                continue;
+            }
+
+            let mut distance = SHORE_DISTANCE as f32;
+            for dy in -radius..=radius {
+                for dx in -radius..=radius {
+                    if is_land(x + dx, y + dy) {
+                        let to_edge = Vec2::new(dx as f32, dy as f32).length() - 0.5;
+                        distance = distance.min(to_edge);
+                    }
+                }
+            }
+
+            distances.push(distance);
+        }
     }
+
+    distances
 }
 
 pub fn handle_chunks_resources(
@@ -53,6 +295,7 @@ pub fn handle_chunks_resources(
     >,
     game_assets: Res<GameAssets>,
     terrain_seed: Res<TerrainSeed>,
+    fog: Res<FogOfWar>,
 ) {
     let chunk_size = chunk_manager.size();
     let tile_size = chunk_manager.tile_size();
@@ -77,6 +320,7 @@ pub fn handle_chunks_resources(
                         &tile_size,
                     );
                     let tile_seed = helpers::hash::seed_from_coord(**terrain_seed, &global_coord);
+                    let visibility = fog_visibility(fog.get(&global_coord));
 
                     match (resource, tile) {
                         (_, TileKind::Water) => (),
@@ -95,11 +339,13 @@ pub fn handle_chunks_resources(
                                 parent.spawn((
                                     TileCoord(tile_coord),
                                     Obstacle,
+                                    HiddenUntilExplored,
                                     ResourceKind::Tree,
                                     SceneBundle {
                                         scene: game_assets.tree.clone(),
                                         transform: Transform::from_translation(translation)
                                             .with_scale(Vec3::splat(4.0)),
+                                        visibility,
                                         ..default()
                                     },
                                 ));
@@ -119,11 +365,13 @@ pub fn handle_chunks_resources(
                                 parent.spawn((
                                     TileCoord(tile_coord),
                                     Obstacle,
+                                    HiddenUntilExplored,
                                     ResourceKind::Tree,
                                     SceneBundle {
                                         scene: game_assets.tree_dead.clone(),
                                         transform: Transform::from_translation(translation)
                                             .with_scale(Vec3::splat(4.0)),
+                                        visibility,
                                         ..default()
                                     },
                                 ));
@@ -145,12 +393,14 @@ pub fn handle_chunks_resources(
                                 parent.spawn((
                                     TileCoord(tile_coord),
                                     Obstacle,
+                                    HiddenUntilExplored,
                                     ResourceKind::Rock,
                                     SceneBundle {
                                         scene: game_assets.rock.clone(),
                                         transform: Transform::from_translation(translation)
                                             .with_scale(Vec3::splat(16.0))
                                             .with_rotation(Quat::from_rotation_y(rotation_y)),
+                                        visibility,
                                         ..default()
                                     },
                                 ));
@@ -162,6 +412,61 @@ pub fn handle_chunks_resources(
     }
 }
 
+pub fn update_chunks_fog(
+    fog: Res<FogOfWar>,
+    chunk_manager: Res<ChunkManager>,
+    q_chunks: Query<(&Handle<TerrainMaterial>, Option<&Children>), With<ChunkCoord>>,
+    q_water: Query<&Handle<WaterMaterial>, With<WaterSurface>>,
+    mut q_hidden: Query<(&TileCoord, &mut Visibility), With<HiddenUntilExplored>>,
+    mut materials: ResMut<Assets<TerrainMaterial>>,
+    mut water_materials: ResMut<Assets<WaterMaterial>>,
+) {
+    // The materials also show the border tiles of the neighbouring chunks.
+    let chunk_coords = fog
+        .changed()
+        .flat_map(|coord| {
+            (-1..=1).flat_map(move |y| (-1..=1).map(move |x| *coord + IVec2::new(x, y)))
+        })
+        .collect::<HashSet<_>>();
+
+    for chunk_coord in chunk_coords {
+        let Some(chunk) = chunk_manager.get(&chunk_coord) else {
+            continue;
+        };
+        let Ok((handle, children)) = q_chunks.get(*chunk) else {
+            continue;
+        };
+
+        let visibility = fog.padded_chunk(&chunk_coord);
+        if let Some(material) = materials.get_mut(handle) {
+            material.set_visibility(visibility.clone());
+        }
+
+        for child in children.into_iter().flatten() {
+            if let Ok(handle) = q_water.get(*child) {
+                if let Some(material) = water_materials.get_mut(handle) {
+                    material.set_visibility(visibility.clone());
+                }
+            }
+
+            if let Ok((tile_coord, mut visibility)) = q_hidden.get_mut(*child) {
+                let expected = fog_visibility(fog.get_tile(&chunk_coord, tile_coord));
+                if *visibility != expected {
+                    *visibility = expected;
+                }
+            }
+        }
+    }
+}
+
+/// How an entity hidden until its tile is explored is shown on a tile.
+fn fog_visibility(tile: TileVisibility) -> Visibility {
+    match tile {
+        TileVisibility::Unexplored => Visibility::Hidden,
+        _ => Visibility::Inherited,
+    }
+}
+
 pub fn spawn_chunks_around_camera(
     mut commands: Commands,
     q_camera: Query<&Transform, With<Camera>>,
@@ -362,3 +667,162 @@ pub fn handle_generate_resource_task(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::utils::HashMap;
+
+    use super::*;
+    use crate::helpers::testing::TestApp;
+
+    fn test_chunks() -> HashMap<IVec2, Vec<TileKind>> {
+        let mut chunks = HashMap::new();
+        for y in -1..=1 {
+            for x in -1..=1 {
+                chunks.insert(IVec2::new(x, y), vec![TileKind::Grass; 4]);
+            }
+        }
+        chunks.insert(IVec2::new(1, 0), vec![TileKind::Water; 4]);
+        chunks.insert(IVec2::new(0, -1), vec![TileKind::Barren; 4]);
+
+        chunks
+    }
+
+    #[test]
+    fn test_padded_tile_mapping() {
+        let size = UVec2::new(2, 2);
+        let chunks = test_chunks();
+
+        let padded = padded_tile_mapping(&IVec2::ZERO, &size, 1, |coord| {
+            chunks.get(coord).map(Vec::as_slice)
+        })
+        .unwrap();
+
+        let (grass, water, barren) = (TileKind::Grass, TileKind::Water, TileKind::Barren);
+        assert_eq!(
+            padded,
+            vec![
+                grass, barren, barren, grass, //
+                grass, grass, grass, water, //
+                grass, grass, grass, water, //
+                grass, grass, grass, grass, //
+            ]
+        );
+    }
+
+    #[test]
+    fn test_padded_tile_mapping_missing_neighbour() {
+        let size = UVec2::new(2, 2);
+        let (grass, water) = (TileKind::Grass, TileKind::Water);
+        let center = vec![grass, water, grass, grass];
+        let right = vec![water; 4];
+
+        // The chunk stands in for the neighbours that are missing.
+        let padded = padded_tile_mapping(&IVec2::ZERO, &size, 1, |coord| match *coord {
+            IVec2::ZERO => Some(center.as_slice()),
+            IVec2 { x: 1, y: 0 } => Some(right.as_slice()),
+            _ => None,
+        })
+        .unwrap();
+
+        assert_eq!(
+            padded,
+            vec![
+                grass, grass, water, water, //
+                grass, grass, water, water, //
+                grass, grass, grass, water, //
+                grass, grass, grass, grass, //
+            ]
+        );
+        assert!(padded_tile_mapping(&IVec2::ZERO, &size, 1, |_| None).is_none());
+    }
+
+    #[test]
+    fn test_crop_tile_mapping() {
+        let size = UVec2::new(2, 2);
+        let chunks = test_chunks();
+        let lookup = |coord: &IVec2| chunks.get(coord).map(Vec::as_slice);
+
+        let wide = padded_tile_mapping(&IVec2::ZERO, &size, 2, lookup).unwrap();
+        let narrow = padded_tile_mapping(&IVec2::ZERO, &size, 1, lookup).unwrap();
+
+        assert_eq!(crop_tile_mapping(&wide, &size, 2, 1), narrow);
+    }
+
+    #[test]
+    fn test_shore_distances() {
+        let size = UVec2::new(8, 1);
+        let border = SHORE_DISTANCE + 1;
+        let width = size.x + 2 * border;
+        let height = size.y + 2 * border;
+        // A single column of land on the left of an otherwise water only map.
+        let padded = (0..width * height)
+            .map(|index| match index % width {
+                x if x == border => TileKind::Grass,
+                _ => TileKind::Water,
+            })
+            .collect::<Vec<_>>();
+
+        let distances = shore_distances(&padded, &size, border);
+        let row = &distances[(size.x + 2) as usize..2 * (size.x + 2) as usize];
+
+        assert_eq!(row[1], 0.0);
+        assert_eq!(row[0], 0.5);
+        assert_eq!(row[2], 0.5);
+        assert_eq!(row[3], 1.5);
+        assert_eq!(row[9], SHORE_DISTANCE as f32);
+    }
+
+    #[test]
+    fn test_resources_hidden_until_explored() {
+        let size = ChunkManager::default().size();
+        let mut app = TestApp::default()
+            .explored(
+                helpers::geometry::tile_coord_to_global_coord(&UVec2::ZERO, &IVec2::ZERO, &size),
+                0,
+            )
+            .init_resource::<Assets<TerrainMaterial>>()
+            .init_resource::<Assets<WaterMaterial>>()
+            .systems(update_chunks_fog)
+            .build();
+        let chunk = app
+            .world
+            .spawn((
+                ChunkCoord(IVec2::ZERO),
+                Handle::<TerrainMaterial>::default(),
+            ))
+            .id();
+        app.world
+            .resource_mut::<ChunkManager>()
+            .insert(IVec2::ZERO, chunk);
+        let explored = app
+            .world
+            .spawn((
+                TileCoord(UVec2::ZERO),
+                HiddenUntilExplored,
+                Visibility::Hidden,
+            ))
+            .set_parent(chunk)
+            .id();
+        let unexplored = app
+            .world
+            .spawn((
+                TileCoord(UVec2::new(4, 4)),
+                HiddenUntilExplored,
+                Visibility::Hidden,
+            ))
+            .set_parent(chunk)
+            .id();
+
+        app.update();
+
+        assert_eq!(
+            app.world.get::<Visibility>(explored),
+            Some(&Visibility::Inherited)
+        );
+        assert_eq!(
+            app.world.get::<Visibility>(unexplored),
+            Some(&Visibility::Hidden)
+        );
+    }
+}
@@ -1,9 +1,20 @@
 use bevy::prelude::*;
 
 use crate::{
-    building::{BuildingKind, BuildingTool},
+    building::{
+        Building, BuildingBuilder, BuildingKind, BuildingLevel, BuildingPriority, BuildingStaff,
+        BuildingThroughput, BuildingTool, BuildingUnderstaffed, BuildingUpgrading, BuildingWorkers,
+        ChangeBuildingPriority, CropStage, FarmCrop, ProductionSettings, UpgradeBuilding,
+    },
+    combat::Health,
     core::{CursorActive, GameAssets, GameStates, ToolMode},
-    quota::{Quota, QuotaSuccess, QuotaTimer, ResourceCount},
+    goods::{Stockpile, Storage},
+    military::{SoldierKind, SoldierStats, TrainSoldier, TrainingQueue},
+    population::Population,
+    quota::{Quota, QuotaSuccess, QuotaTimer},
+    raids::{RaidSchedule, RaidSettings},
+    selection::Selected,
+    units::{Unit, UnitCargo, UnitState},
 };
 
 const NORMAL_BUTTON: Color = Color::rgb(0.15, 0.15, 0.15);
@@ -19,6 +30,9 @@ struct BuildingButton;
 #[derive(Component)]
 struct QuotaInformation;
 
+#[derive(Component)]
+struct PopulationInformation;
+
 #[derive(Component)]
 struct QuotaSuccessDisplay;
 
@@ -28,6 +42,28 @@ struct QuotaSuccessDisplayRoot;
 #[derive(Component)]
 struct HideMeIn(Timer);
 
+#[derive(Component)]
+struct BuildingInfoPanel;
+
+#[derive(Component)]
+struct BuildingInfoText;
+
+#[derive(Component)]
+struct UpgradeButton;
+
+#[derive(Component)]
+struct UnitInfoPanel;
+
+#[derive(Component)]
+struct UnitInfoText;
+
+#[derive(Component)]
+struct PriorityButton;
+
+/// Trains a soldier of its kind at the selected building.
+#[derive(Component, Deref)]
+struct TrainButton(SoldierKind);
+
 pub struct UIPlugin;
 
 impl Plugin for UIPlugin {
@@ -40,8 +76,14 @@ impl Plugin for UIPlugin {
                     building_button_interaction,
                     update_cursor_on_interraction,
                     update_quota_information,
+                    update_population_information,
                     update_quota_success_display,
                     update_hide_me_in,
+                    update_building_info_panel,
+                    update_unit_info_panel,
+                    upgrade_button_interaction,
+                    priority_button_interaction,
+                    train_button_interaction,
                 )
                     .run_if(in_state(GameStates::Playing)),
             );
@@ -101,6 +143,181 @@ fn setup_ui(mut commands: Commands, game_assets: Res<GameAssets>) {
                                 ),
                             ));
                         });
+
+                    parent
+                        .spawn(NodeBundle {
+                            style: Style {
+                                width: Val::Percent(20.0),
+                                height: Val::Percent(100.0),
+                                margin: UiRect::left(Val::Px(10.0)),
+                                flex_direction: FlexDirection::Row,
+                                justify_content: JustifyContent::Center,
+                                align_items: AlignItems::Center,
+                                ..default()
+                            },
+                            background_color: NORMAL_BUTTON.into(),
+                            ..default()
+                        })
+                        .with_children(|parent| {
+                            parent.spawn((
+                                PopulationInformation,
+                                TextBundle::from_section(
+                                    "POPULATION: 0/0",
+                                    TextStyle {
+                                        font_size: 30.0,
+                                        color: Color::rgb(0.9, 0.9, 0.9),
+                                        ..default()
+                                    },
+                                ),
+                            ));
+                        });
+                });
+
+            parent
+                .spawn((
+                    UnitInfoPanel,
+                    NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            right: Val::Px(10.0),
+                            top: Val::Percent(12.0),
+                            padding: UiRect::all(Val::Px(10.0)),
+                            ..default()
+                        },
+                        background_color: NORMAL_BUTTON.into(),
+                        visibility: Visibility::Hidden,
+                        ..default()
+                    },
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        UnitInfoText,
+                        TextBundle::from_section(
+                            "WORKER",
+                            TextStyle {
+                                font_size: 24.0,
+                                color: Color::rgb(0.9, 0.9, 0.9),
+                                ..default()
+                            },
+                        ),
+                    ));
+                });
+
+            parent
+                .spawn((
+                    BuildingInfoPanel,
+                    NodeBundle {
+                        style: Style {
+                            position_type: PositionType::Absolute,
+                            left: Val::Px(10.0),
+                            top: Val::Percent(12.0),
+                            padding: UiRect::all(Val::Px(10.0)),
+                            flex_direction: FlexDirection::Column,
+                            row_gap: Val::Px(10.0),
+                            ..default()
+                        },
+                        background_color: NORMAL_BUTTON.into(),
+                        visibility: Visibility::Hidden,
+                        ..default()
+                    },
+                ))
+                .with_children(|parent| {
+                    parent.spawn((
+                        BuildingInfoText,
+                        TextBundle::from_section(
+                            "BUILDING",
+                            TextStyle {
+                                font_size: 24.0,
+                                color: Color::rgb(0.9, 0.9, 0.9),
+                                ..default()
+                            },
+                        ),
+                    ));
+
+                    parent
+                        .spawn((
+                            UpgradeButton,
+                            ButtonBundle {
+                                style: Style {
+                                    padding: UiRect::all(Val::Px(5.0)),
+                                    border: UiRect::all(Val::Px(2.0)),
+                                    justify_content: JustifyContent::Center,
+                                    ..default()
+                                },
+                                border_color: BorderColor(Color::BLACK),
+                                background_color: NORMAL_BUTTON.into(),
+                                ..default()
+                            },
+                        ))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(
+                                "UPGRADE (U)",
+                                TextStyle {
+                                    font_size: 20.0,
+                                    color: Color::rgb(0.9, 0.9, 0.9),
+                                    ..default()
+                                },
+                            ));
+                        });
+
+                    parent
+                        .spawn((
+                            PriorityButton,
+                            ButtonBundle {
+                                style: Style {
+                                    padding: UiRect::all(Val::Px(5.0)),
+                                    border: UiRect::all(Val::Px(2.0)),
+                                    justify_content: JustifyContent::Center,
+                                    ..default()
+                                },
+                                border_color: BorderColor(Color::BLACK),
+                                background_color: NORMAL_BUTTON.into(),
+                                ..default()
+                            },
+                        ))
+                        .with_children(|parent| {
+                            parent.spawn(TextBundle::from_section(
+                                "PRIORITY (P)",
+                                TextStyle {
+                                    font_size: 20.0,
+                                    color: Color::rgb(0.9, 0.9, 0.9),
+                                    ..default()
+                                },
+                            ));
+                        });
+
+                    for kind in SoldierKind::ALL {
+                        parent
+                            .spawn((
+                                TrainButton(kind),
+                                ButtonBundle {
+                                    style: Style {
+                                        padding: UiRect::all(Val::Px(5.0)),
+                                        border: UiRect::all(Val::Px(2.0)),
+                                        justify_content: JustifyContent::Center,
+                                        ..default()
+                                    },
+                                    border_color: BorderColor(Color::BLACK),
+                                    background_color: NORMAL_BUTTON.into(),
+                                    visibility: Visibility::Hidden,
+                                    ..default()
+                                },
+                            ))
+                            .with_children(|parent| {
+                                parent.spawn(TextBundle::from_section(
+                                    format!(
+                                        "TRAIN {} ({})",
+                                        kind.name().to_uppercase(),
+                                        format!("{:?}", kind.key()).trim_start_matches("Key")
+                                    ),
+                                    TextStyle {
+                                        font_size: 20.0,
+                                        color: Color::rgb(0.9, 0.9, 0.9),
+                                        ..default()
+                                    },
+                                ));
+                            });
+                    }
                 });
 
             parent
@@ -261,17 +478,71 @@ fn building_button_interaction(
 fn update_quota_information(
     mut q_quota: Query<&mut Text, With<QuotaInformation>>,
     quota_timer: Res<QuotaTimer>,
-    resource_count: Res<ResourceCount>,
+    stockpile: Res<Stockpile>,
     quota: Res<Quota>,
+    raids: Res<RaidSchedule>,
+    raid_settings: Res<RaidSettings>,
 ) {
+    // The raid the player was warned about, with how long until it comes.
+    let raid = raids
+        .next
+        .zip(raid_settings.rules())
+        .map(|((side, _), rules)| (side, raids.remaining(rules) as u64));
+
     for mut text in q_quota.iter_mut() {
         let seconds = quota_timer.remaining().as_secs();
         let minutes = seconds / 60;
         let seconds = seconds % 60;
 
+        let progress = quota
+            .iter()
+            .map(|(good, amount)| format!("{} {}/{}", good, stockpile.get(good), amount))
+            .collect::<Vec<_>>();
+
         text.sections[0].value = format!(
-            "TIME LEFT: {:02}:{:02} QUOTA: {}/{}",
-            minutes, seconds, **resource_count, **quota
+            "TIME LEFT: {:02}:{:02} QUOTA: {}\nSTOCK: {}",
+            minutes,
+            seconds,
+            progress.join(", "),
+            **stockpile
+        );
+        if let Some((side, seconds)) = raid {
+            text.sections[0].value += &format!(
+                "\nRAID IN: {:02}:{:02} FROM THE {}",
+                seconds / 60,
+                seconds % 60,
+                side.name()
+            );
+        }
+    }
+}
+
+fn update_population_information(
+    mut q_population: Query<&mut Text, With<PopulationInformation>>,
+    population: Res<Population>,
+    stockpile: Res<Stockpile>,
+    production: Res<ProductionSettings>,
+) {
+    let food = production
+        .food
+        .iter()
+        .map(|good| stockpile.get(good))
+        .sum::<u32>();
+    let hunger = match (population.short_meals, population.is_starving()) {
+        (0, _) => "",
+        (_, false) => " HUNGRY",
+        (_, true) => " STARVING",
+    };
+
+    for mut text in q_population.iter_mut() {
+        text.sections[0].value = format!(
+            "POPULATION: {}/{}\nFREE WORKERS: {}\nSOLDIERS: {}\nFOOD: {}{}",
+            population.citizens,
+            population.capacity,
+            population.free(),
+            population.soldiers,
+            food,
+            hunger
         );
     }
 }
@@ -288,7 +559,9 @@ fn update_quota_success_display(
 
     for (display, mut visibility) in q_display_root.iter_mut() {
         *visibility = Visibility::Visible;
-        commands.entity(display).insert(HideMeIn(Timer::from_seconds(5.0, TimerMode::Once)));
+        commands
+            .entity(display)
+            .insert(HideMeIn(Timer::from_seconds(5.0, TimerMode::Once)));
     }
 
     for mut text in q_display.iter_mut() {
@@ -318,3 +591,291 @@ fn update_hide_me_in(
         }
     }
 }
+
+/// The selected buildings, with everything their info panel shows.
+type SelectedBuildings<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static BuildingKind,
+        &'static BuildingLevel,
+        &'static BuildingWorkers,
+        &'static BuildingPriority,
+        &'static BuildingStaff,
+        Has<BuildingUnderstaffed>,
+        Option<&'static FarmCrop>,
+        Option<&'static BuildingUpgrading>,
+        Has<BuildingBuilder>,
+        Option<&'static BuildingThroughput>,
+        Option<&'static Storage>,
+        Option<&'static TrainingQueue>,
+        Option<&'static Health>,
+    ),
+    (With<Building>, With<Selected>),
+>;
+
+/// The buttons training soldiers, with the kind they train.
+type TrainButtons<'w, 's> = Query<
+    'w,
+    's,
+    (&'static mut Visibility, &'static TrainButton),
+    (Without<UpgradeButton>, Without<BuildingInfoPanel>),
+>;
+
+fn update_building_info_panel(
+    q_selected: SelectedBuildings,
+    mut q_panel: Query<&mut Visibility, With<BuildingInfoPanel>>,
+    mut q_button: Query<&mut Visibility, (With<UpgradeButton>, Without<BuildingInfoPanel>)>,
+    mut q_train_buttons: TrainButtons,
+    mut q_text: Query<&mut Text, With<BuildingInfoText>>,
+    production: Res<ProductionSettings>,
+) {
+    let selected = q_selected.get_single().ok();
+
+    for mut visibility in q_panel.iter_mut() {
+        let expected = match selected {
+            Some(_) => Visibility::Inherited,
+            None => Visibility::Hidden,
+        };
+        if *visibility != expected {
+            *visibility = expected;
+        }
+    }
+
+    let trains = selected.map_or(&[][..], |(kind, ..)| production.trains(kind));
+    for (mut visibility, button) in q_train_buttons.iter_mut() {
+        let expected = match trains.contains(button) {
+            true => Visibility::Inherited,
+            false => Visibility::Hidden,
+        };
+        if *visibility != expected {
+            *visibility = expected;
+        }
+    }
+
+    let Some((
+        kind,
+        level,
+        workers,
+        priority,
+        staff,
+        is_understaffed,
+        crop,
+        upgrading,
+        has_builder,
+        throughput,
+        storage,
+        training,
+        health,
+    )) = selected
+    else {
+        return;
+    };
+
+    let max_level = production.max_level(kind);
+    let next = production.level(kind, **level + 1);
+    for mut visibility in q_button.iter_mut() {
+        let expected = match (&next, upgrading) {
+            (Some(_), None) => Visibility::Inherited,
+            _ => Visibility::Hidden,
+        };
+        if *visibility != expected {
+            *visibility = expected;
+        }
+    }
+
+    let mut info = format!(
+        "{} {}/{}\nPRIORITY: {}",
+        kind.name(),
+        **level,
+        max_level,
+        priority.name()
+    );
+    if let Some(health) = health {
+        info += &format!("\nHEALTH: {}/{}", health.current, health.max);
+    }
+    let needed = production.staff(kind, **level);
+    if needed > 0 {
+        info += &format!("\nSTAFF: {}/{}", **staff, needed);
+        if is_understaffed {
+            info += " UNDERSTAFFED";
+        }
+    }
+    let housing = production.housing(kind);
+    if housing > 0 {
+        info += &format!("\nHOUSING: {}", housing);
+    }
+    if production.get(kind).is_some() {
+        let capacity = production.level(kind, **level).unwrap_or_default().workers;
+        info += &format!("\nWORKERS: {}/{}", **workers, capacity);
+    }
+    match production.recipe(kind) {
+        Some(recipe) if recipe.inputs.is_empty() => info += &format!("\nMAKES: {}", recipe.outputs),
+        Some(recipe) => info += &format!("\n{} -> {}", recipe.inputs, recipe.outputs),
+        None => {}
+    }
+    if let Some(crop) = crop {
+        let stage = match crop.stage {
+            CropStage::Sowing => "SOWING",
+            CropStage::Growing => "GROWING",
+            CropStage::Harvesting => "HARVESTING",
+        };
+        info += &format!("\nCROP: {} {:.0}%", stage, crop.timer.fraction() * 100.0);
+    }
+    for soldier in trains {
+        if let Some(rules) = production.soldier(soldier) {
+            info += &format!("\n{}: {}", soldier.name().to_uppercase(), rules.cost);
+        }
+    }
+    if let Some(training) = training {
+        if let Some(soldier) = training.soldiers.front() {
+            let time = production.soldier(soldier).map_or(1.0, |rules| rules.time);
+            info += &format!(
+                "\nTRAINING: {} {:.0}% ({} QUEUED)",
+                soldier.name().to_uppercase(),
+                (training.elapsed / time).min(1.0) * 100.0,
+                training.soldiers.len() - 1
+            );
+            if training.waiting {
+                info += " WAITING FOR A CITIZEN";
+            }
+        }
+    }
+    match (throughput, storage) {
+        (_, Some(storage)) => {
+            info += &format!(
+                "\nSTORED: {}/{}\n{}",
+                storage.goods.total(),
+                storage.capacity,
+                storage.goods
+            );
+        }
+        (Some(throughput), None) => info += &format!("\nTHROUGHPUT: {:.1}/min", **throughput),
+        (None, None) => info += "\nTHROUGHPUT: -",
+    }
+    match (upgrading, next) {
+        (Some(upgrading), _) => {
+            info += &format!("\nUPGRADING: {:.0}%", upgrading.fraction() * 100.0);
+            if !has_builder {
+                info += " WAITING FOR A WORKER";
+            }
+        }
+        (None, Some(next)) => info += &format!("\nUPGRADE: {}", next.cost),
+        (None, None) => {}
+    }
+
+    for mut text in q_text.iter_mut() {
+        text.sections[0].value = info.clone();
+    }
+}
+
+/// The selected units, with what soldiers they are.
+type SelectedUnits<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static UnitState,
+        &'static UnitCargo,
+        Option<&'static Health>,
+        Option<&'static SoldierKind>,
+        Option<&'static SoldierStats>,
+    ),
+    (With<Unit>, With<Selected>),
+>;
+
+fn update_unit_info_panel(
+    q_selected: SelectedUnits,
+    mut q_panel: Query<&mut Visibility, With<UnitInfoPanel>>,
+    mut q_text: Query<&mut Text, With<UnitInfoText>>,
+) {
+    let count = q_selected.iter().count();
+
+    for mut visibility in q_panel.iter_mut() {
+        let expected = match count {
+            0 => Visibility::Hidden,
+            _ => Visibility::Inherited,
+        };
+        if *visibility != expected {
+            *visibility = expected;
+        }
+    }
+
+    let info = match q_selected.get_single() {
+        Ok((state, cargo, health, soldier, stats)) => {
+            let name = soldier.map_or("WORKER".to_string(), |soldier| {
+                soldier.name().to_uppercase()
+            });
+            let mut info = format!("{}\nSTATE: {}", name, state.name());
+            if let Some(health) = health {
+                info += &format!("\nHEALTH: {}/{}", health.current, health.max);
+            }
+            if !cargo.is_empty() {
+                info += &format!("\nCARRYING: {}", **cargo);
+            }
+            if let Some(stats) = stats {
+                info += &format!(
+                    "\nDAMAGE: {} {:?} ({:.1}/s)\nRANGE: {}\nARMOR: {} {:?}",
+                    stats.damage,
+                    stats.attack,
+                    stats.attack_speed,
+                    stats.range,
+                    stats.armor,
+                    stats.armor_kind
+                )
+                .to_uppercase();
+            }
+            info
+        }
+        Err(_) if count > 0 => format!("{} UNITS", count),
+        Err(_) => return,
+    };
+
+    for mut text in q_text.iter_mut() {
+        text.sections[0].value = info.clone();
+    }
+}
+
+fn upgrade_button_interaction(
+    q_interaction: Query<&Interaction, (Changed<Interaction>, With<UpgradeButton>)>,
+    q_selected: Query<Entity, (With<Building>, With<Selected>)>,
+    mut upgrades: EventWriter<UpgradeBuilding>,
+) {
+    for interaction in q_interaction.iter() {
+        if matches!(interaction, Interaction::Pressed) {
+            for building in q_selected.iter() {
+                upgrades.send(UpgradeBuilding(building));
+            }
+        }
+    }
+}
+
+fn train_button_interaction(
+    q_interaction: Query<(&Interaction, &TrainButton), Changed<Interaction>>,
+    q_selected: Query<Entity, (With<Building>, With<Selected>)>,
+    mut orders: EventWriter<TrainSoldier>,
+) {
+    for (interaction, button) in q_interaction.iter() {
+        if matches!(interaction, Interaction::Pressed) {
+            for building in q_selected.iter() {
+                orders.send(TrainSoldier {
+                    building,
+                    kind: **button,
+                });
+            }
+        }
+    }
+}
+
+fn priority_button_interaction(
+    q_interaction: Query<&Interaction, (Changed<Interaction>, With<PriorityButton>)>,
+    q_selected: Query<Entity, (With<Building>, With<Selected>)>,
+    mut priorities: EventWriter<ChangeBuildingPriority>,
+) {
+    for interaction in q_interaction.iter() {
+        if matches!(interaction, Interaction::Pressed) {
+            for building in q_selected.iter() {
+                priorities.send(ChangeBuildingPriority(building));
+            }
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use bevy::{prelude::*, utils::HashMap};
+
+/// How close, in world units, units get before they push each other away.
+pub const SEPARATION_RADIUS: f32 = 4.0;
+/// How close, in world units, a unit waits behind another one going to the same spot.
+pub const QUEUE_SPACING: f32 = 5.0;
+
+/// The units bucketed by the cell of a grid they are in, so the ones close to a point are found
+/// without looking at all of them. It is rebuilt every frame from the positions of the units.
+#[derive(Resource)]
+pub struct UnitSpatialHash {
+    cell_size: f32,
+    cells: HashMap<IVec2, Vec<(Entity, Vec2)>>,
+}
+
+impl Default for UnitSpatialHash {
+    fn default() -> Self {
+        UnitSpatialHash::new(SEPARATION_RADIUS * 2.0)
+    }
+}
+
+impl UnitSpatialHash {
+    pub fn new(cell_size: f32) -> Self {
+        UnitSpatialHash {
+            cell_size,
+            cells: HashMap::default(),
+        }
+    }
+
+    fn cell(&self, point: &Vec2) -> IVec2 {
+        (*point / self.cell_size).floor().as_ivec2()
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+    }
+
+    pub fn insert(&mut self, entity: Entity, point: Vec2) {
+        let cell = self.cell(&point);
+        self.cells.entry(cell).or_default().push((entity, point));
+    }
+
+    /// The units within `radius` world units of a point.
+    pub fn neighbours(
+        &self,
+        point: Vec2,
+        radius: f32,
+    ) -> impl Iterator<Item = (Entity, Vec2)> + '_ {
+        let min = self.cell(&(point - radius));
+        let max = self.cell(&(point + radius));
+
+        (min.y..=max.y)
+            .flat_map(move |y| (min.x..=max.x).map(move |x| IVec2::new(x, y)))
+            .filter_map(|cell| self.cells.get(&cell))
+            .flatten()
+            .copied()
+            .filter(move |(_, other)| other.distance(point) <= radius)
+    }
+}
+
+/// Push away from the points closer than `radius`, the harder the closer they are. Points right
+/// on top of `point` don't push, since there is no telling which way.
+pub fn separation(point: Vec2, others: impl Iterator<Item = Vec2>, radius: f32) -> Vec2 {
+    others
+        .map(|other| {
+            let away = point - other;
+            let distance = away.length();
+            match distance > 0.0 && distance < radius {
+                true => away / distance * (1.0 - distance / radius),
+                false => Vec2::ZERO,
+            }
+        })
+        .sum()
+}
+
+/// The direction to move in, at most of length 1, to head in the `desired` direction while
+/// being pushed. A unit pushed straight back steps aside to its right instead, so two units
+/// meeting head-on pass each other rather than stopping.
+pub fn steer(desired: Vec2, push: Vec2) -> Vec2 {
+    let steering = desired + push;
+    if desired != Vec2::ZERO && steering.dot(desired) <= 0.0 {
+        return -desired.perp();
+    }
+
+    steering.clamp_length_max(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spatial_hash_neighbours() {
+        let mut hash = UnitSpatialHash::new(8.0);
+        hash.insert(Entity::from_raw(0), Vec2::new(1.0, 1.0));
+        hash.insert(Entity::from_raw(1), Vec2::new(-3.0, 1.0));
+        hash.insert(Entity::from_raw(2), Vec2::new(30.0, 30.0));
+
+        let mut neighbours = hash
+            .neighbours(Vec2::ZERO, 4.0)
+            .map(|(entity, _)| entity.index())
+            .collect::<Vec<_>>();
+        neighbours.sort();
+        assert_eq!(neighbours, vec![0, 1]);
+
+        hash.clear();
+        assert_eq!(hash.neighbours(Vec2::ZERO, 4.0).count(), 0);
+    }
+
+    #[test]
+    fn test_separation() {
+        let push = separation(
+            Vec2::ZERO,
+            [Vec2::new(2.0, 0.0), Vec2::new(10.0, 0.0)].into_iter(),
+            4.0,
+        );
+        assert_eq!(push, Vec2::new(-0.5, 0.0));
+
+        assert_eq!(
+            separation(Vec2::ZERO, [Vec2::ZERO].into_iter(), 4.0),
+            Vec2::ZERO
+        );
+    }
+
+    #[test]
+    fn test_steer() {
+        assert_eq!(steer(Vec2::X, Vec2::ZERO), Vec2::X);
+        assert_eq!(steer(Vec2::X, Vec2::Y), Vec2::new(1.0, 1.0).normalize());
+        // Pushed straight back, the unit steps aside instead.
+        assert_eq!(steer(Vec2::X, Vec2::NEG_X * 2.0), Vec2::NEG_Y);
+        assert_eq!(steer(Vec2::NEG_X, Vec2::X * 2.0), Vec2::Y);
+    }
+}
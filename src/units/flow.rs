@@ -0,0 +1,158 @@
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashMap};
+
+use crate::{
+    helpers::{self, pathfinding::FlowField},
+    roads::{Roads, ROAD_PATH_COST},
+    spatial::{OccupancyChanged, SpatialIndex},
+    terrain::{ChunkManager, TileKind, TileMapping},
+};
+
+/// How many chunks around the chunk of its goal a flow field covers, at first.
+const FLOW_FIELD_CHUNKS: i32 = 1;
+/// The most flow fields kept at once. The one built the longest ago goes first.
+const FLOW_FIELD_LIMIT: usize = 16;
+/// The most flow fields built in one frame. Units that need another one wait for the next frame.
+const FLOW_FIELD_BUILDS: u32 = 2;
+/// How many tiles wide the squares are that goals are grouped by. Units going anywhere in a
+/// square share one flow field, and walk straight to their goal once they are in the square.
+pub const FLOW_GOAL_SIZE: i32 = 8;
+
+/// The square of tiles a goal is grouped in.
+pub fn goal_square(global_coord: &IVec2) -> IVec2 {
+    global_coord.div_euclid(IVec2::splat(FLOW_GOAL_SIZE))
+}
+
+/// The flow fields of the squares units are heading to, built when a unit first needs one and
+/// dropped when an obstacle appears in their area or the roads change.
+#[derive(Resource, Default)]
+pub struct FlowFields {
+    fields: HashMap<IVec2, (FlowField, u32)>,
+    built: u32,
+    /// How many flow fields were built during this frame.
+    built_this_frame: u32,
+}
+
+impl FlowFields {
+    /// The flow field to the square of the goal, covering the tile `from`. It is built over the
+    /// chunks around both if there is none yet, or grown to them if `from` is outside of it. There
+    /// is none while no more fields can be built this frame.
+    pub fn get_or_build(
+        &mut self,
+        goal: &IVec2,
+        from: &IVec2,
+        chunk_manager: &ChunkManager,
+        q_mappings: &Query<&TileMapping>,
+        roads: &Roads,
+        index: &SpatialIndex,
+    ) -> Option<&FlowField> {
+        let square = goal_square(goal);
+        let existing = self.fields.get(&square).map(|(field, _)| field.area());
+        if !self
+            .fields
+            .get(&square)
+            .is_some_and(|(field, _)| field.contains(from))
+        {
+            if self.built_this_frame >= FLOW_FIELD_BUILDS {
+                return None;
+            }
+            if existing.is_none() && self.fields.len() >= FLOW_FIELD_LIMIT {
+                let oldest = self
+                    .fields
+                    .iter()
+                    .min_by_key(|(_, (_, built))| *built)
+                    .map(|(square, _)| *square);
+                if let Some(oldest) = oldest {
+                    self.fields.remove(&oldest);
+                }
+            }
+
+            let size = chunk_manager.size();
+            let chunk_area = |global_coord: &IVec2| {
+                let chunk_coord =
+                    helpers::geometry::global_coord_to_chunk_coord(global_coord, &size);
+                let min = helpers::geometry::tile_coord_to_global_coord(
+                    &UVec2::ZERO,
+                    &(chunk_coord - FLOW_FIELD_CHUNKS),
+                    &size,
+                );
+                let max = helpers::geometry::tile_coord_to_global_coord(
+                    &(size - 1),
+                    &(chunk_coord + FLOW_FIELD_CHUNKS),
+                    &size,
+                );
+                (min, max)
+            };
+            let (goal_min, goal_max) = existing.unwrap_or_else(|| chunk_area(goal));
+            let (from_min, from_max) = chunk_area(from);
+            let (min, max) = (goal_min.min(from_min), goal_max.max(from_max));
+            let cost = |coord: &IVec2| match chunk_manager.tile_kind(coord, q_mappings) {
+                None | Some(TileKind::Water) => None,
+                _ if index.is_blocked(coord) => None,
+                _ if roads.contains(coord) => Some(ROAD_PATH_COST),
+                _ => Some(1.0),
+            };
+
+            let square_min = square * FLOW_GOAL_SIZE;
+            let goals = (0..FLOW_GOAL_SIZE)
+                .flat_map(|y| (0..FLOW_GOAL_SIZE).map(move |x| square_min + IVec2::new(x, y)))
+                .filter(|coord| cost(coord).is_some())
+                .collect::<Vec<_>>();
+
+            let field = FlowField::new(&goals, &min, &max, cost);
+            self.built += 1;
+            self.built_this_frame += 1;
+            self.fields.insert(square, (field, self.built));
+        }
+
+        self.fields.get(&square).map(|(field, _)| field)
+    }
+
+    fn invalidate(&mut self, coord: &IVec2) {
+        self.fields.retain(|_, (field, _)| !field.contains(coord));
+    }
+}
+
+/// The flow fields, and the map they are built over.
+#[derive(SystemParam)]
+pub struct FlowPaths<'w, 's> {
+    flow_fields: ResMut<'w, FlowFields>,
+    pub chunk_manager: Res<'w, ChunkManager>,
+    q_mappings: Query<'w, 's, &'static TileMapping>,
+    pub roads: Res<'w, Roads>,
+    index: Res<'w, SpatialIndex>,
+}
+
+impl FlowPaths<'_, '_> {
+    /// The flow field to the square of the goal from the tile, built if there is none yet and
+    /// there is time for it this frame.
+    pub fn field(&mut self, goal: &IVec2, from: &IVec2) -> Option<&FlowField> {
+        self.flow_fields.get_or_build(
+            goal,
+            from,
+            &self.chunk_manager,
+            &self.q_mappings,
+            &self.roads,
+            &self.index,
+        )
+    }
+}
+
+/// Drop the flow fields whose area had an obstacle appear. An obstacle that is gone, like a cut
+/// tree, only leaves the fields a little longer than they need to be, so they are kept.
+pub(super) fn update_flow_fields(
+    mut changes: EventReader<OccupancyChanged>,
+    mut flow_fields: ResMut<FlowFields>,
+    roads: Res<Roads>,
+    index: Res<SpatialIndex>,
+) {
+    flow_fields.built_this_frame = 0;
+    if roads.is_changed() {
+        flow_fields.fields.clear();
+    }
+
+    for OccupancyChanged(global_coord) in changes.read() {
+        if index.is_blocked(global_coord) {
+            flow_fields.invalidate(global_coord);
+        }
+    }
+}
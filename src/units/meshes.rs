@@ -0,0 +1,41 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+/// The capsule the units are drawn with, and a material for every colour they come in. A system
+/// spawning units makes them for its first units and gives the same ones to all of the others.
+#[derive(SystemParam)]
+pub struct UnitMeshes<'w, 's> {
+    meshes: ResMut<'w, Assets<Mesh>>,
+    materials: ResMut<'w, Assets<StandardMaterial>>,
+    mesh: Local<'s, Option<Handle<Mesh>>>,
+    colors: Local<'s, Vec<(Color, Handle<StandardMaterial>)>>,
+}
+
+impl UnitMeshes<'_, '_> {
+    /// How a unit of the colour standing at the position is drawn.
+    pub fn bundle(&mut self, color: Color, position: Vec2) -> MaterialMeshBundle<StandardMaterial> {
+        let mesh = self
+            .mesh
+            .get_or_insert_with(|| self.meshes.add(Capsule3d::new(0.5, 1.0)))
+            .clone();
+        let material = match self.colors.iter().find(|(other, _)| *other == color) {
+            Some((_, material)) => material.clone(),
+            None => {
+                let material = self.materials.add(StandardMaterial {
+                    base_color: color,
+                    unlit: true,
+                    ..default()
+                });
+                self.colors.push((color, material.clone()));
+                material
+            }
+        };
+
+        MaterialMeshBundle {
+            mesh,
+            material,
+            transform: Transform::from_translation(position.extend(2.0).xzy())
+                .with_scale(Vec3::splat(2.0)),
+            ..default()
+        }
+    }
+}
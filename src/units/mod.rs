@@ -1,10 +1,41 @@
 use std::{collections::VecDeque, f32::EPSILON};
 
-use bevy::prelude::*;
+use avoidance::*;
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+pub use flow::*;
+pub use meshes::*;
 
-use crate::{building::BuildingHasWorker, core::GameStates, quota::ResourceCount};
+use crate::{
+    building::{Building, ResourceClaimed},
+    core::GameStates,
+    goods::{Goods, Storage},
+    helpers,
+    jobs::{Job, JobKind, JobState, WorkerJob},
+    roads::ROAD_SPEED_BONUS,
+    terrain::ResourceKind,
+};
 
 const CLOSE_ENOUGH: f32 = EPSILON;
+pub const UNIT_VISION: u32 = 6;
+pub const UNIT_SELECT_RADIUS: f32 = 4.0;
+/// How fast workers walk, in world units per second.
+pub const UNIT_SPEED: f32 = 16.0;
+/// How many seconds a unit can go without getting closer to its next waypoint before it is stuck.
+const STUCK_TIME: f32 = 3.0;
+/// How many seconds a unit can queue behind others before it is stuck.
+const QUEUE_WAIT_TIME: f32 = 15.0;
+/// How hard units are pushed away from each other, compared to how hard they head to their
+/// waypoint.
+const SEPARATION_WEIGHT: f32 = 1.5;
+/// How far, in tiles, from the center of a building units are pushed away from it.
+const BUILDING_AVOID_RADIUS: f32 = 0.75;
+
+mod avoidance;
+mod flow;
+mod meshes;
 
 #[derive(Component, Deref, DerefMut)]
 pub struct UnitWaypoints(pub VecDeque<(Vec2, Vec<UnitWaypointAction>)>);
@@ -15,57 +46,377 @@ pub struct UnitVelocity(pub f32);
 #[derive(Component)]
 pub struct Unit;
 
+/// The goods the unit carries around.
+#[derive(Component, Debug, Default, Deref, DerefMut)]
+pub struct UnitCargo(pub Goods);
+
+/// What the unit is doing. It follows from the waypoints, the cargo and the work of the unit and
+/// is updated every frame.
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UnitState {
+    #[default]
+    Idle,
+    Moving,
+    /// Working at a resource.
+    Gathering,
+    /// Bringing goods to a storage for its job.
+    Carrying,
+    /// Bringing back the goods of a job that failed.
+    Returning,
+    /// Going after an enemy, or attacking it.
+    Fighting,
+    /// The unit can't get any closer to where it is going, or has goods and nowhere to put them.
+    Stuck,
+}
+
+impl UnitState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            UnitState::Idle => "IDLE",
+            UnitState::Moving => "MOVING",
+            UnitState::Gathering => "GATHERING",
+            UnitState::Carrying => "CARRYING",
+            UnitState::Returning => "RETURNING",
+            UnitState::Fighting => "FIGHTING",
+            UnitState::Stuck => "STUCK",
+        }
+    }
+}
+
+/// How close the unit got to its next waypoint, and for how long it hasn't got any closer.
+#[derive(Component, Default)]
+pub struct UnitProgress {
+    distance: f32,
+    stalled: f32,
+    /// The unit is queuing behind another one going to the same spot, which counts as being
+    /// stalled only slowly.
+    waiting: bool,
+    /// How far the unit still has to go along the flow field it follows. Going around an obstacle
+    /// can take it further from its waypoint, so this is what counts as getting closer instead.
+    flow_distance: Option<f32>,
+}
+
+/// The unit is fighting: it heads to the point, e.g. where its enemy is, or stands its ground
+/// without one. Its waypoints are kept for when the fight is over.
+#[derive(Component, Debug, Clone, Copy, PartialEq)]
+pub struct UnitEngagement(pub Option<Vec2>);
+
+/// The unit is bringing the goods of a failed job to the closest storage with space.
+#[derive(Component)]
+struct UnitReturning;
+
 #[derive(Component, Deref)]
 struct UnitWaypointActions(pub Vec<UnitWaypointAction>);
 
+/// The unit stays where it is until the timer finishes.
+#[derive(Component, Deref, DerefMut)]
+struct UnitWorking(Timer);
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum UnitWaypointAction {
-    Gather(Entity),
-    Deposit,
-    Release(Entity),
+    /// Gather the resource, which becomes the goods the unit carries.
+    Gather(Entity, Goods),
+    /// Work for the given number of seconds before moving to the next waypoint.
+    Work(f32),
+    /// Store the goods the unit carries in a storage building.
+    Deposit(Entity),
+    /// Take the goods from a storage building.
+    Take(Entity, Goods),
+    /// The unit got to its job and starts doing it.
+    StartJob(Entity),
+    CompleteJob(Entity),
+}
+
+/// Why an action of a unit, or the unit itself, failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnitFailure {
+    /// The entity the action was for is gone, e.g. a demolished storage.
+    TargetGone(Entity),
+    /// The resource isn't the one claimed by the job of the unit, e.g. an entity that took the
+    /// place of a resource cut down by another unit.
+    NotClaimed(Entity),
+    /// Not everything the unit carries fit in the storage.
+    StorageFull(Entity),
+    /// The storage doesn't have the goods the unit came for.
+    MissingGoods(Entity),
+    /// The unit didn't get any closer to its next waypoint for a while.
+    NoProgress,
+}
+
+/// A unit couldn't do what it was told. Its job fails and its waypoints are dropped; a unit that
+/// still carries goods brings them back to a storage, any other unit goes idle.
+#[derive(Event, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnitFailed {
+    pub unit: Entity,
+    pub failure: UnitFailure,
+}
+
+/// Send units to a point on the map. Workers go there first and then carry on with their job.
+#[derive(Event, Debug, Clone)]
+pub struct UnitMoveOrder {
+    pub units: Vec<Entity>,
+    pub target: Vec2,
+    /// Soldiers fight the enemies they meet on the way.
+    pub attack: bool,
 }
 
 pub struct UnitsPlugin;
 
 impl Plugin for UnitsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            Update,
-            (update_unit_position, update_unit_waypoints, manage_unit_actions).run_if(in_state(GameStates::Playing)),
-        );
+        app.add_event::<UnitMoveOrder>()
+            .add_event::<UnitFailed>()
+            .init_resource::<UnitSpatialHash>()
+            .init_resource::<FlowFields>()
+            .add_systems(
+                Update,
+                (
+                    handle_unit_move_orders,
+                    update_flow_fields,
+                    update_unit_working,
+                    update_unit_position,
+                    update_unit_waypoints,
+                    manage_unit_actions,
+                    update_unit_states,
+                    recover_failed_units,
+                    return_unit_cargo,
+                )
+                    .chain()
+                    .run_if(in_state(GameStates::Playing)),
+            );
     }
 }
 
-fn update_unit_position(
-    mut q_units: Query<(&mut Transform, &UnitVelocity, &UnitWaypoints), With<Unit>>, time: Res<Time>,
-) {
-    for (mut transform, velocity, waypoints) in q_units.iter_mut() {
-        if let Some((next_waypoint, _)) = waypoints.front() {
-            let direction = *next_waypoint - transform.translation.xz();
-            let distance = direction.length();
-            let direction = direction.normalize();
-            let delta = velocity.0 * time.delta_seconds();
+/// The state of a unit from what it is doing. A unit that is working or following waypoints
+/// without being stalled is busy; a unit with nothing to do is idle unless it carries goods.
+pub fn unit_state(
+    is_working: bool,
+    has_waypoints: bool,
+    has_cargo: bool,
+    is_returning: bool,
+    is_stalled: bool,
+) -> UnitState {
+    match (is_working, has_waypoints) {
+        (true, _) => UnitState::Gathering,
+        (false, true) if is_stalled => UnitState::Stuck,
+        (false, true) if is_returning => UnitState::Returning,
+        (false, true) if has_cargo => UnitState::Carrying,
+        (false, true) => UnitState::Moving,
+        (false, false) if has_cargo => UnitState::Stuck,
+        (false, false) => UnitState::Idle,
+    }
+}
 
-            if distance < delta {
-                transform.translation = next_waypoint.extend(transform.translation.y).xzy();
-            } else {
-                transform.translation += direction.extend(0.0).xzy() * delta;
+fn handle_unit_move_orders(
+    mut q_units: Query<&mut UnitWaypoints, With<Unit>>,
+    mut orders: EventReader<UnitMoveOrder>,
+) {
+    for order in orders.read() {
+        for unit in order.units.iter() {
+            if let Ok(mut waypoints) = q_units.get_mut(*unit) {
+                // A new order replaces the previous one instead of adding another detour.
+                if waypoints
+                    .front()
+                    .is_some_and(|(_, actions)| actions.is_empty())
+                {
+                    waypoints.pop_front();
+                }
+                waypoints.push_front((order.target, vec![]));
             }
         }
     }
 }
 
-fn update_unit_waypoints(
+fn update_unit_working(
     mut commands: Commands,
-    mut q_units: Query<(Entity, &mut UnitWaypoints, &Transform), With<Unit>>
+    mut q_units: Query<(Entity, &mut UnitWorking), With<Unit>>,
+    time: Res<Time>,
 ) {
+    for (unit, mut working) in q_units.iter_mut() {
+        if working.tick(time.delta()).finished() {
+            commands.entity(unit).remove::<UnitWorking>();
+        }
+    }
+}
+
+/// The units, with where they are going and whether they are fighting or working.
+type MovingUnits<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static mut Transform,
+        &'static UnitVelocity,
+        &'static UnitWaypoints,
+        &'static mut UnitProgress,
+        Option<&'static UnitEngagement>,
+        Has<UnitWorking>,
+    ),
+    With<Unit>,
+>;
+
+/// Move the units towards their next waypoint while keeping them apart. Units push each other and
+/// the units standing around away, and are pushed away from buildings other than the one they
+/// are going to. A unit going to the same spot as a unit just ahead of it waits behind it, so
+/// crowds at a busy storage queue up instead of stacking on one spot.
+///
+/// Units heading to a waypoint outside the square of tiles they are in follow the flow field to
+/// that square, which takes them around water and obstacles however many units are going there,
+/// and walk straight to the waypoint from there. Waypoints are where something is done, like a
+/// job or a storage, so the way between them is left to the flow fields. Fighting units head
+/// straight for their enemy instead.
+fn update_unit_position(
+    mut q_units: MovingUnits,
+    q_buildings: Query<&GlobalTransform, With<Building>>,
+    mut spatial_hash: ResMut<UnitSpatialHash>,
+    mut paths: FlowPaths,
+    time: Res<Time>,
+) {
+    let size = paths.chunk_manager.size();
+    let tile_size = paths.chunk_manager.tile_size();
+
+    spatial_hash.clear();
+    let mut targets = HashMap::new();
+    for (entity, transform, _, waypoints, ..) in q_units.iter() {
+        spatial_hash.insert(entity, transform.translation.xz());
+        if let Some((target, actions)) = waypoints.front() {
+            if !actions.is_empty() {
+                targets.insert(entity, *target);
+            }
+        }
+    }
+
+    let buildings = q_buildings
+        .iter()
+        .map(|transform| {
+            helpers::geometry::world_pos_to_global_coord(
+                &transform.translation().xz(),
+                &size,
+                &tile_size,
+            )
+        })
+        .collect::<HashSet<_>>();
+
+    for (entity, mut transform, velocity, waypoints, mut progress, engagement, is_working) in
+        q_units.iter_mut()
+    {
+        progress.waiting = false;
+        progress.flow_distance = None;
+        if is_working {
+            continue;
+        }
+
+        let position = transform.translation.xz();
+        let global_coord =
+            helpers::geometry::world_pos_to_global_coord(&position, &size, &tile_size);
+        let speed = match paths.roads.contains(&global_coord) {
+            true => velocity.0 * ROAD_SPEED_BONUS,
+            false => velocity.0,
+        };
+        let delta = speed * time.delta_seconds();
+        let neighbours = || {
+            spatial_hash
+                .neighbours(position, SEPARATION_RADIUS.max(QUEUE_SPACING))
+                .filter(|(other, _)| *other != entity)
+        };
+        let push = separation(
+            position,
+            neighbours().map(|(_, point)| point),
+            SEPARATION_RADIUS,
+        ) * SEPARATION_WEIGHT;
+
+        if let Some(UnitEngagement(point)) = engagement {
+            if let Some(point) = point.filter(|point| point.distance(position) >= delta) {
+                let steering = steer((point - position).normalize_or_zero(), push);
+                transform.translation += steering.extend(0.0).xzy() * delta;
+            }
+            continue;
+        }
+
+        let Some((next_waypoint, _)) = waypoints.front() else {
+            // Units standing around make way for the ones passing by.
+            transform.translation += push.clamp_length_max(1.0).extend(0.0).xzy() * delta;
+            continue;
+        };
+
+        if let Some(target) = targets.get(&entity) {
+            let distance = target.distance(position);
+            let is_queued = neighbours().any(|(other, point)| {
+                point.distance(position) < QUEUE_SPACING
+                    && point.distance(*target) < distance
+                    && targets
+                        .get(&other)
+                        .is_some_and(|other_target| other_target.distance(*target) < CLOSE_ENOUGH)
+            });
+            if is_queued {
+                progress.waiting = true;
+                continue;
+            }
+        }
+
+        let target_coord =
+            helpers::geometry::world_pos_to_global_coord(next_waypoint, &size, &tile_size);
+        let avoidance = (-1..=1)
+            .flat_map(|y| (-1..=1).map(move |x| global_coord + IVec2::new(x, y)))
+            .filter(|tile| *tile != target_coord && buildings.contains(tile))
+            .map(|tile| {
+                let center = helpers::geometry::global_coord_to_world_pos(&tile, &size, &tile_size);
+                separation(
+                    position,
+                    std::iter::once(center),
+                    tile_size.x * BUILDING_AVOID_RADIUS,
+                )
+            })
+            .sum::<Vec2>();
+
+        let mut direction = *next_waypoint - position;
+        if goal_square(&target_coord) != goal_square(&global_coord) {
+            // Without a flow field yet the unit waits for one, rather than walk into the water.
+            let Some(field) = paths.field(&target_coord, &global_coord) else {
+                progress.waiting = true;
+                continue;
+            };
+            if let Some(next) = field.next(&global_coord) {
+                let next_position =
+                    helpers::geometry::global_coord_to_world_pos(&next, &size, &tile_size);
+                direction = next_position - position;
+                progress.flow_distance = field
+                    .cost(&next)
+                    .map(|cost| cost * tile_size.x + direction.length());
+            }
+        }
+
+        let distance = next_waypoint.distance(position);
+        if distance < delta {
+            transform.translation = next_waypoint.extend(transform.translation.y).xzy();
+        } else {
+            let steering = steer(
+                direction.normalize_or_zero(),
+                push + avoidance * SEPARATION_WEIGHT,
+            );
+            transform.translation += steering.extend(0.0).xzy() * delta;
+        }
+    }
+}
+
+/// The units free to walk on to their waypoints.
+type WalkingUnits<'w, 's> = Query<
+    'w,
+    's,
+    (Entity, &'static mut UnitWaypoints, &'static Transform),
+    (With<Unit>, Without<UnitWorking>, Without<UnitEngagement>),
+>;
+
+fn update_unit_waypoints(mut commands: Commands, mut q_units: WalkingUnits) {
     for (entity, mut waypoints, transform) in q_units.iter_mut() {
         if let Some((next_waypoint, actions)) = waypoints.front() {
             let direction = *next_waypoint - transform.translation.xz();
             let distance = direction.length();
 
             if distance < CLOSE_ENOUGH {
-                commands.entity(entity).insert(UnitWaypointActions(actions.clone()));
+                commands
+                    .entity(entity)
+                    .insert(UnitWaypointActions(actions.clone()));
                 waypoints.pop_front();
             }
         }
@@ -74,25 +425,383 @@ fn update_unit_waypoints(
 
 fn manage_unit_actions(
     mut commands: Commands,
-    q_units: Query<(Entity, &UnitWaypointActions), With<Unit>>,
-    mut resource_count: ResMut<ResourceCount>,
+    mut q_units: Query<
+        (
+            Entity,
+            &UnitWaypointActions,
+            &mut UnitCargo,
+            Option<&WorkerJob>,
+        ),
+        With<Unit>,
+    >,
+    q_resources: Query<(), (With<ResourceKind>, With<ResourceClaimed>)>,
+    mut q_storages: Query<&mut Storage>,
+    mut q_jobs: Query<(&Job, &mut JobState)>,
+    mut failures: EventWriter<UnitFailed>,
 ) {
-    for (unit, actions) in q_units.iter() {
+    for (unit, actions, mut cargo, worker_job) in q_units.iter_mut() {
         commands.entity(unit).remove::<UnitWaypointActions>();
 
         for action in actions.iter() {
-            match action {
-                UnitWaypointAction::Gather(entity) => {
-                    commands.entity(*entity).despawn_recursive();
-                }
-                UnitWaypointAction::Deposit => {
-                    **resource_count += 1;
+            let result = match action {
+                UnitWaypointAction::Gather(entity, goods) => {
+                    let job = worker_job.and_then(|job| q_jobs.get(**job).ok());
+                    let is_claimed = job.is_some_and(|(job, state)| {
+                        *state == JobState::InProgress(unit)
+                            && matches!(job.kind, JobKind::Gather { resource, .. } if resource == *entity)
+                    });
+                    match (q_resources.contains(*entity), is_claimed) {
+                        (true, true) => {
+                            commands.entity(*entity).despawn_recursive();
+                            cargo.add(goods);
+                            Ok(())
+                        }
+                        (true, false) => Err(UnitFailure::NotClaimed(*entity)),
+                        (false, _) => Err(UnitFailure::TargetGone(*entity)),
+                    }
                 }
-                UnitWaypointAction::Release(entity) => {
-                    commands.entity(*entity).remove::<BuildingHasWorker>();
-                    commands.entity(unit).despawn_recursive();
+                UnitWaypointAction::Work(duration) => {
+                    commands
+                        .entity(unit)
+                        .insert(UnitWorking(Timer::from_seconds(*duration, TimerMode::Once)));
+                    Ok(())
                 }
+                UnitWaypointAction::Deposit(entity) => match q_storages.get_mut(*entity) {
+                    Ok(mut storage) => {
+                        **cargo = storage.store(&cargo);
+                        match cargo.is_empty() {
+                            true => Ok(()),
+                            false => Err(UnitFailure::StorageFull(*entity)),
+                        }
+                    }
+                    Err(_) => Err(UnitFailure::TargetGone(*entity)),
+                },
+                UnitWaypointAction::Take(entity, goods) => match q_storages.get_mut(*entity) {
+                    Ok(mut storage) => match storage.goods.take(goods) {
+                        true => {
+                            cargo.add(goods);
+                            Ok(())
+                        }
+                        false => Err(UnitFailure::MissingGoods(*entity)),
+                    },
+                    Err(_) => Err(UnitFailure::TargetGone(*entity)),
+                },
+                UnitWaypointAction::StartJob(job) => match q_jobs.get_mut(*job) {
+                    Ok((_, mut state)) => {
+                        *state = JobState::InProgress(unit);
+                        Ok(())
+                    }
+                    Err(_) => Err(UnitFailure::TargetGone(*job)),
+                },
+                UnitWaypointAction::CompleteJob(job) => match q_jobs.get_mut(*job) {
+                    Ok((_, mut state)) => {
+                        *state = JobState::Completed;
+                        Ok(())
+                    }
+                    Err(_) => Err(UnitFailure::TargetGone(*job)),
+                },
+            };
+
+            // The rest of the actions depend on this one, so they are dropped with it.
+            if let Err(failure) = result {
+                warn!("Unit {:?} failed {:?}: {:?}", unit, action, failure);
+                failures.send(UnitFailed { unit, failure });
+                break;
+            }
+        }
+    }
+}
+
+/// How long a unit has been stalled after another `delta` seconds. Getting closer to its waypoint
+/// starts over; queuing counts too, only slower, so that a unit isn't stuck behind a queue that
+/// doesn't move forever.
+fn stall_time(stalled: f32, is_closer: bool, is_waiting: bool, delta: f32) -> f32 {
+    match (is_closer, is_waiting) {
+        (true, _) => 0.0,
+        (false, true) => stalled + delta * STUCK_TIME / QUEUE_WAIT_TIME,
+        (false, false) => stalled + delta,
+    }
+}
+
+/// The units, with everything their state follows from.
+type UnitStates<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Transform,
+        &'static UnitWaypoints,
+        &'static UnitCargo,
+        &'static mut UnitState,
+        &'static mut UnitProgress,
+        Has<UnitWorking>,
+        Has<UnitReturning>,
+        Has<UnitEngagement>,
+    ),
+    With<Unit>,
+>;
+
+/// Update the state of every unit, and report the units that are stuck on their way.
+fn update_unit_states(
+    mut q_units: UnitStates,
+    mut failures: EventWriter<UnitFailed>,
+    time: Res<Time>,
+) {
+    for (
+        unit,
+        transform,
+        waypoints,
+        cargo,
+        mut state,
+        mut progress,
+        is_working,
+        is_returning,
+        is_fighting,
+    ) in q_units.iter_mut()
+    {
+        match waypoints.front() {
+            Some((next_waypoint, _)) if !is_working && !is_fighting => {
+                let distance = progress
+                    .flow_distance
+                    .unwrap_or_else(|| next_waypoint.distance(transform.translation.xz()));
+                let is_closer = distance < progress.distance - CLOSE_ENOUGH;
+                progress.stalled = stall_time(
+                    progress.stalled,
+                    is_closer,
+                    progress.waiting,
+                    time.delta_seconds(),
+                );
+                progress.distance = distance;
+            }
+            _ => {
+                progress.distance = f32::INFINITY;
+                progress.stalled = 0.0;
+            }
+        }
+
+        let is_stalled = progress.stalled >= STUCK_TIME;
+        let next = match is_fighting {
+            true => UnitState::Fighting,
+            false => unit_state(
+                is_working,
+                !waypoints.is_empty(),
+                !cargo.is_empty(),
+                is_returning,
+                is_stalled,
+            ),
+        };
+        if next == UnitState::Stuck && *state != UnitState::Stuck && is_stalled {
+            failures.send(UnitFailed {
+                unit,
+                failure: UnitFailure::NoProgress,
+            });
+        }
+        if *state != next {
+            *state = next;
+        }
+    }
+}
+
+/// Units that failed drop their waypoints and fail their job. Bringing back the goods they carry
+/// is left to `return_unit_cargo`.
+fn recover_failed_units(
+    mut commands: Commands,
+    mut failures: EventReader<UnitFailed>,
+    mut q_units: Query<(&mut UnitWaypoints, &mut UnitProgress, Option<&WorkerJob>), With<Unit>>,
+    mut q_jobs: Query<&mut JobState>,
+) {
+    for UnitFailed { unit, .. } in failures.read() {
+        let Ok((mut waypoints, mut progress, job)) = q_units.get_mut(*unit) else {
+            continue;
+        };
+
+        waypoints.clear();
+        *progress = UnitProgress::default();
+        commands
+            .entity(*unit)
+            .remove::<(UnitWorking, UnitReturning)>();
+        if let Some(mut state) = job.and_then(|job| q_jobs.get_mut(**job).ok()) {
+            *state = JobState::Failed;
+        }
+    }
+}
+
+/// The units free to bring back what they carry.
+type CarryingUnits<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Transform,
+        &'static UnitCargo,
+        &'static mut UnitWaypoints,
+        Has<UnitReturning>,
+    ),
+    (With<Unit>, Without<WorkerJob>, Without<UnitWorking>),
+>;
+
+/// Units without a job that still carry goods bring them to the closest storage with space.
+/// Without one they stay stuck with their goods until there is.
+fn return_unit_cargo(
+    mut commands: Commands,
+    mut q_units: CarryingUnits,
+    q_storages: Query<(Entity, &GlobalTransform, &Storage)>,
+) {
+    for (unit, transform, cargo, mut waypoints, is_returning) in q_units.iter_mut() {
+        if cargo.is_empty() {
+            if is_returning {
+                commands.entity(unit).remove::<UnitReturning>();
             }
+            continue;
         }
+        if !waypoints.is_empty() {
+            continue;
+        }
+
+        let point = transform.translation.xz();
+        let storage = q_storages
+            .iter()
+            .filter(|(_, _, storage)| storage.space() > 0)
+            .map(|(entity, transform, _)| (entity, transform.translation().xz()))
+            .min_by(|(_, a), (_, b)| a.distance(point).total_cmp(&b.distance(point)));
+        let Some((storage, position)) = storage else {
+            continue;
+        };
+
+        **waypoints = VecDeque::from([(position, vec![UnitWaypointAction::Deposit(storage)])]);
+        commands.entity(unit).insert(UnitReturning);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::testing::{spawn_storage, TestApp};
+
+    #[test]
+    fn test_unit_state() {
+        assert_eq!(
+            unit_state(false, false, false, false, false),
+            UnitState::Idle
+        );
+        assert_eq!(
+            unit_state(true, true, false, false, false),
+            UnitState::Gathering
+        );
+        assert_eq!(
+            unit_state(false, true, false, false, false),
+            UnitState::Moving
+        );
+        assert_eq!(
+            unit_state(false, true, true, false, false),
+            UnitState::Carrying
+        );
+        assert_eq!(
+            unit_state(false, true, true, true, false),
+            UnitState::Returning
+        );
+        assert_eq!(unit_state(false, true, true, false, true), UnitState::Stuck);
+        // Goods and nowhere to bring them.
+        assert_eq!(
+            unit_state(false, false, true, false, false),
+            UnitState::Stuck
+        );
+    }
+
+    #[test]
+    fn test_stall_time() {
+        assert_eq!(stall_time(2.0, true, false, 1.0), 0.0);
+        assert_eq!(stall_time(2.0, true, true, 1.0), 0.0);
+        assert_eq!(stall_time(2.0, false, false, 1.0), 3.0);
+
+        // A unit waiting in a queue that never moves gets stuck too, just later.
+        let waited = (0..15).fold(0.0, |stalled, _| stall_time(stalled, false, true, 1.0));
+        assert!(stall_time(0.0, false, true, 1.0) < 1.0);
+        assert!(waited >= STUCK_TIME - EPSILON);
+    }
+
+    /// A headless app running the actions of the units, and what the units do when one fails.
+    fn actions_app() -> App {
+        TestApp::default()
+            .event::<UnitFailed>()
+            .systems((manage_unit_actions, recover_failed_units, return_unit_cargo).chain())
+            .build()
+    }
+
+    fn spawn_unit(app: &mut App, cargo: Goods, actions: Vec<UnitWaypointAction>) -> Entity {
+        app.world
+            .spawn((
+                Unit,
+                Transform::default(),
+                UnitCargo(cargo),
+                UnitWaypoints(VecDeque::from([(Vec2::new(32.0, 0.0), vec![])])),
+                UnitProgress::default(),
+                UnitWaypointActions(actions),
+            ))
+            .id()
+    }
+
+    #[test]
+    fn test_unit_returns_cargo() {
+        let mut app = actions_app();
+        let storage = spawn_storage(&mut app, Vec2::new(64.0, 0.0), 10, &Goods::default());
+        let gone = app.world.spawn(Storage::new(10)).id();
+        app.world.despawn(gone);
+        let job = app
+            .world
+            .spawn(JobState::InProgress(Entity::PLACEHOLDER))
+            .id();
+        let unit = spawn_unit(
+            &mut app,
+            Goods::from_iter([("logs", 2)]),
+            vec![
+                UnitWaypointAction::Deposit(gone),
+                UnitWaypointAction::CompleteJob(job),
+            ],
+        );
+        app.world.entity_mut(unit).insert(WorkerJob(job));
+
+        app.update();
+
+        // The storage was demolished on the way, so the job fails and the unit stops.
+        assert_eq!(app.world.get::<JobState>(job), Some(&JobState::Failed));
+        assert!(app.world.get::<UnitWaypoints>(unit).unwrap().is_empty());
+
+        // Once released from its job, it brings the goods to the storage that is left.
+        app.world.entity_mut(unit).remove::<WorkerJob>();
+        app.update();
+
+        let waypoints = app.world.get::<UnitWaypoints>(unit).unwrap();
+        assert_eq!(
+            **waypoints,
+            VecDeque::from([(
+                Vec2::new(64.0, 0.0),
+                vec![UnitWaypointAction::Deposit(storage)]
+            )])
+        );
+        assert!(app.world.get::<UnitReturning>(unit).is_some());
+    }
+
+    #[test]
+    fn test_unit_goes_idle() {
+        let mut app = actions_app();
+        spawn_storage(&mut app, Vec2::ZERO, 10, &Goods::default());
+        // A tree that isn't claimed by the job of the unit is left standing.
+        let tree = app.world.spawn((ResourceKind::Tree, ResourceClaimed)).id();
+        let unit = spawn_unit(
+            &mut app,
+            Goods::default(),
+            vec![UnitWaypointAction::Gather(
+                tree,
+                Goods::from_iter([("logs", 2)]),
+            )],
+        );
+
+        app.update();
+
+        assert!(app.world.get_entity(tree).is_some());
+        assert!(app.world.get::<UnitWaypoints>(unit).unwrap().is_empty());
+        assert!(app.world.get::<UnitCargo>(unit).unwrap().is_empty());
+        assert!(app.world.get::<UnitReturning>(unit).is_none());
     }
 }